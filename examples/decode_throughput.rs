@@ -0,0 +1,61 @@
+//! Compare the throughput of decoding frames on the thread updating the tracker with decoding
+//! them on a `DecodePool`. Run with `cargo run --release --example decode_throughput [FRAMES]`.
+
+use chrono::Utc;
+use flight_tracker::{DecodePool, RawFrame, Tracker};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+const FRAMES: [&str; 6] = [
+    "*8D4840D6202CC371C32CE0576098;",
+    "*8D40621D58C382D690C8AC2863A7;",
+    "*8D40621D58C386435CC412692AD6;",
+    "*8D485020994409940838175B284F;",
+    "*8DA05F219B06B6AF189400CBC33F;",
+    "*5D4840D6A0B2C3;",
+];
+
+fn main() {
+    let count: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1_000_000);
+    let frames: Vec<String> = FRAMES
+        .iter()
+        .cycle()
+        .take(count)
+        .map(|f| f.to_string())
+        .collect();
+    let time = Utc::now();
+
+    let tracker = Arc::new(Mutex::new(Tracker::new()));
+    let start = Instant::now();
+    for frame in &frames {
+        let mut tracker = tracker.lock().unwrap();
+        let _ = tracker.update_with_avr(frame, time);
+    }
+    report("serial", count, start);
+
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    for threads in (0..).map(|i| 1 << i).take_while(|&t| t <= cores.max(2)) {
+        let tracker = Arc::new(Mutex::new(Tracker::new()));
+        let mut pool = DecodePool::new(tracker, threads);
+        let start = Instant::now();
+        for frame in &frames {
+            pool.push(RawFrame::Avr(frame.clone()), time);
+        }
+        pool.finish();
+        report(&format!("{} decode threads", threads), count, start);
+    }
+}
+
+fn report(name: &str, count: usize, start: Instant) {
+    let secs = start.elapsed().as_secs_f64();
+    println!(
+        "{:>18}: {:>8.0} frames/s ({:.2} s)",
+        name,
+        count as f64 / secs,
+        secs
+    );
+}
@@ -0,0 +1,177 @@
+/// Byte which starts each frame in the Beast binary format, and which is doubled when it occurs
+/// within a frame
+const ESCAPE: u8 = 0x1a;
+/// Length of the MLAT timestamp at the start of each frame (bytes)
+const TIMESTAMP_LEN: usize = 6;
+
+/// Kind of message carried by a Beast frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BeastFrameKind {
+    /// Mode A/C reply (type `1`)
+    ModeAc,
+    /// Short Mode S frame (type `2`)
+    ModeSShort,
+    /// Long Mode S frame (type `3`)
+    ModeSLong,
+}
+
+impl BeastFrameKind {
+    fn from_type(frame_type: u8) -> Option<Self> {
+        match frame_type {
+            0x31 => Some(BeastFrameKind::ModeAc),
+            0x32 => Some(BeastFrameKind::ModeSShort),
+            0x33 => Some(BeastFrameKind::ModeSLong),
+            _ => None,
+        }
+    }
+
+    /// Length of the message (bytes)
+    fn message_len(self) -> usize {
+        match self {
+            BeastFrameKind::ModeAc => 2,
+            BeastFrameKind::ModeSShort => 7,
+            BeastFrameKind::ModeSLong => 14,
+        }
+    }
+}
+
+/// A frame received in the Beast binary format
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeastFrame {
+    pub kind: BeastFrameKind,
+    /// MLAT timestamp, counting at 12 MHz
+    pub timestamp: u64,
+    /// Signal level (0-255)
+    pub signal: u8,
+    /// Message as received, escaping removed
+    pub message: Vec<u8>,
+}
+
+/// Splits a stream in the Beast binary format, as sent by dump1090 and readsb on port 30005,
+/// into frames. Each frame is the escape byte `0x1a`, a type byte, a 6-byte timestamp, a signal
+/// level byte and the message, with any `0x1a` after the type byte doubled. Bytes can be pushed
+/// in chunks of any size, such as they arrive from a socket, and a frame split across chunks is
+/// completed by the next chunk. Frames of other types (such as status frames) and garbage are
+/// skipped until the next frame starts.
+#[derive(Debug, Clone, Default)]
+pub struct BeastDecoder {
+    buffer: Vec<u8>,
+}
+
+/// Outcome of trying to read a frame at the start of the buffered bytes
+enum Parse {
+    Frame(BeastFrame, usize),
+    /// The frame isn't complete yet
+    Incomplete,
+    /// Not the start of a frame that can be read, skip this many bytes
+    Skip(usize),
+}
+
+impl BeastDecoder {
+    pub fn new() -> Self {
+        BeastDecoder::default()
+    }
+
+    /// Add received bytes and return the frames completed by them
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<BeastFrame> {
+        self.buffer.extend_from_slice(bytes);
+        let mut frames = Vec::new();
+        let mut start = 0;
+        while start < self.buffer.len() {
+            match parse_frame(&self.buffer[start..]) {
+                Parse::Frame(frame, len) => {
+                    frames.push(frame);
+                    start += len;
+                }
+                Parse::Skip(len) => start += len,
+                Parse::Incomplete => break,
+            }
+        }
+        self.buffer.drain(..start);
+        frames
+    }
+}
+
+fn parse_frame(bytes: &[u8]) -> Parse {
+    if bytes[0] != ESCAPE {
+        let garbage = bytes.iter().take_while(|&&b| b != ESCAPE).count();
+        return Parse::Skip(garbage);
+    }
+    let frame_type = match bytes.get(1) {
+        Some(&frame_type) => frame_type,
+        None => return Parse::Incomplete,
+    };
+    let kind = match BeastFrameKind::from_type(frame_type) {
+        Some(kind) => kind,
+        // A doubled escape byte is part of some other frame
+        None if frame_type == ESCAPE => return Parse::Skip(2),
+        None => return Parse::Skip(1),
+    };
+    let len = TIMESTAMP_LEN + 1 + kind.message_len();
+    let mut data = Vec::with_capacity(len);
+    let mut i = 2;
+    while data.len() < len {
+        match (bytes.get(i), bytes.get(i + 1)) {
+            (None, _) | (Some(&ESCAPE), None) => return Parse::Incomplete,
+            (Some(&ESCAPE), Some(&ESCAPE)) => {
+                data.push(ESCAPE);
+                i += 2;
+            }
+            // An undoubled escape byte starts the next frame, so this one was cut short
+            (Some(&ESCAPE), Some(_)) => return Parse::Skip(i),
+            (Some(&byte), _) => {
+                data.push(byte);
+                i += 1;
+            }
+        }
+    }
+    let timestamp = data[..TIMESTAMP_LEN]
+        .iter()
+        .fold(0, |timestamp, &byte| timestamp << 8 | u64::from(byte));
+    let frame = BeastFrame {
+        kind,
+        timestamp,
+        signal: data[TIMESTAMP_LEN],
+        message: data[TIMESTAMP_LEN + 1..].to_vec(),
+    };
+    Parse::Frame(frame, i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Long frame with a timestamp containing an escaped 0x1a, then a short frame
+    const STREAM: [u8; 42] = [
+        0x1a, 0x33, 0x00, 0x00, 0x1a, 0x1a, 0x00, 0x00, 0x01, 0x80, 0x8D, 0x48, 0x40, 0xD6, 0x20,
+        0x2C, 0xC3, 0x71, 0xC3, 0x2C, 0xE0, 0x57, 0x60, 0x98, 0x1a, 0x32, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x02, 0x40, 0x5D, 0x48, 0x40, 0xD6, 0xA0, 0xB2, 0xC3, 0x1a, 0x34,
+    ];
+
+    #[test]
+    fn frames_split_across_chunks() {
+        let mut decoder = BeastDecoder::new();
+        let mut frames = Vec::new();
+        for chunk in STREAM.chunks(5) {
+            frames.extend(decoder.push(chunk));
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].kind, BeastFrameKind::ModeSLong);
+        assert_eq!(frames[0].timestamp, 0x1a00_0001);
+        assert_eq!(frames[0].signal, 0x80);
+        assert_eq!(frames[0].message[..4], [0x8D, 0x48, 0x40, 0xD6]);
+        assert_eq!(frames[1].kind, BeastFrameKind::ModeSShort);
+        assert_eq!(frames[1].timestamp, 2);
+        assert_eq!(frames[1].message.len(), 7);
+    }
+
+    #[test]
+    fn garbage_and_truncated_frames_skipped() {
+        let mut decoder = BeastDecoder::new();
+        let mut stream = vec![0x00, 0x42, 0x1a, 0x1a, 0x1a, 0x33, 0x00, 0x01];
+        stream.extend_from_slice(&STREAM[24..]);
+        let frames = decoder.push(&stream);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].kind, BeastFrameKind::ModeSShort);
+    }
+}
@@ -1,19 +1,81 @@
-use anyhow::Result;
+use adsb::{ICAOAddress, Position, VerticalRateSource};
+use anyhow::{bail, Context, Result};
 use chrono::{Utc, Duration};
-use flight_tracker::Tracker;
-use postgres::{Client, NoTls};
+#[cfg(feature = "metrics")]
+use flight_tracker::to_prometheus_metrics;
+#[cfg(feature = "sqlite")]
+use flight_tracker::FrameDatabase;
+use flight_tracker::{
+    flight_summary_json, icao_from_u32, icao_to_u32, offset_to_end_at, parse_timestamped_line,
+    to_aircraft_json, tracker_event_json, websocket_accept_key, websocket_key,
+    websocket_text_frame, Acquisition, Aircraft, Airport, BeastDecoder, BeastFrameKind,
+    CsvRegistry, DecodePool, HeadingReference, Labels, Movement, PositionQuality, RawFrame,
+    SquawkRegion, TimeFormat, Tracker, METERS_PER_FOOT, POSITION_CSV_HEADER,
+};
+use postgres::types::Type;
+use postgres::{Client, NoTls, Statement};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::fmt;
+use std::fs;
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::net::TcpStream;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::mpsc::{self, SyncSender};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::Instant;
 use structopt::StructOpt;
 use itertools::Itertools;
 
 const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+const PLAIN_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
 const NA: &str = "";
+// ANSI foreground colors readable on a dark background. Red is left out since it is reserved
+// for emergencies, as are dark blue and black which are hard to see.
+const AIRCRAFT_COLORS: [u8; 9] = [32, 33, 35, 36, 92, 93, 94, 95, 96];
+// ANSI background colors used to highlight rows in altitude bands, one per band in the order
+// given on the command line.
+const HIGHLIGHT_COLORS: [u8; 5] = [44, 42, 45, 46, 43];
+const PROGRESS_BAR_WIDTH: usize = 40;
+// With `--output csv`, positions of an aircraft received within this many seconds of the last one
+// printed are skipped unless `--csv-interval` says otherwise, and a progress line is logged to
+// stderr after each this many messages
+const CSV_OUTPUT_INTERVAL_SECS: i64 = 10;
+const CSV_PROGRESS_MESSAGES: u64 = 10_000;
+// Most new aircraft notified about in a minute, so startup or a busy period doesn't flood the log
+const MAX_NOTIFICATIONS_PER_MINUTE: usize = 10;
+// Number of pings read from Postgres per query, and how long to wait for new pings once all
+// have been read.
+const POSTGRES_BATCH_SIZE: i64 = 10000;
+const POSTGRES_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// Query replaying pings in the order they were recorded with `--record-sqlite`
+#[cfg(feature = "sqlite")]
+const SQLITE_DEFAULT_QUERY: &str = "SELECT timestamp, data FROM pings ORDER BY timestamp, rowid";
+// Database connected to when no connection string is given
+const DEFAULT_POSTGRES_DSN: &str = "host=storage.local port=54322 user=orbital password=orbital";
+// Wait before reconnecting to a TCP server after the first failure, doubled with each further
+// failure up to the maximum
+const RECONNECT_MIN_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+// Most events waiting to be sent to a WebSocket client before it's dropped for not keeping up
+const WEBSOCKET_QUEUE_SIZE: usize = 1000;
+// How long to wait for an HTTP server to accept a connection and to respond
+const HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+// Conversion for showing speeds in metric units
+const KMH_PER_KNOT: f64 = 1.852;
+// How often `--json` output is written, matching dump1090's aircraft.json
+const JSON_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// How often the tracker's state is saved to `--state-file`, besides on shutdown (including by a
+// signal), so that little is lost if the program is killed outright
+const STATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
 #[derive(StructOpt)]
 #[structopt(about = "Track aircraft via ADSB")]
@@ -28,146 +90,2270 @@ struct Cli {
         long = "expire"
     )]
     expire: i64,
+    #[structopt(
+        name = "table",
+        help = "Show the aircraft table instead of message statistics",
+        short = "t",
+        long = "table"
+    )]
+    table: bool,
+    #[structopt(
+        name = "plain",
+        help = "Print plain text without colors or clearing the screen, e.g. for logging to a file",
+        short = "p",
+        long = "plain"
+    )]
+    plain: bool,
+    #[structopt(
+        name = "output",
+        help = "What to print: display (statistics, table or details) or csv (each decoded position, with progress on stderr, for piping)",
+        default_value = "display",
+        long = "output"
+    )]
+    output: OutputMode,
+    #[structopt(
+        name = "history-dir",
+        help = "Directory in which to write tar1090-style history_N.json snapshots",
+        long = "history-dir",
+        parse(from_os_str)
+    )]
+    history_dir: Option<PathBuf>,
+    #[structopt(
+        name = "history-interval",
+        help = "Number of seconds between history snapshots",
+        default_value = "30",
+        long = "history-interval",
+        parse(try_from_str = parse_history_interval)
+    )]
+    history_interval: u64,
+    #[structopt(
+        name = "history-count",
+        help = "Number of history snapshots to keep before reusing the oldest",
+        default_value = "120",
+        long = "history-count"
+    )]
+    history_count: usize,
+    #[structopt(
+        name = "highlight-alt",
+        help = "Highlight aircraft with an altitude in the band MIN:MAX (feet), may be repeated",
+        long = "highlight-alt",
+        number_of_values = 1
+    )]
+    highlight_alt: Vec<AltitudeBand>,
+    #[structopt(
+        name = "proximity",
+        help = "Warn about aircraft within NM:FT of each other (nautical miles horizontally, feet vertically)",
+        long = "proximity"
+    )]
+    proximity: Option<Separation>,
+    #[structopt(
+        name = "track-age",
+        help = "Number of minutes of positions to keep in each aircraft's track",
+        long = "track-age"
+    )]
+    track_age: Option<i64>,
+    #[structopt(
+        name = "track-min-distance",
+        help = "Distance (nautical miles) within which consecutive track positions are merged, keeping the first and latest",
+        long = "track-min-distance"
+    )]
+    track_min_distance: Option<f64>,
+    #[structopt(
+        name = "dedup-window",
+        help = "Drop frames identical to one received within this many milliseconds, as when combining the feeds of nearby receivers",
+        long = "dedup-window"
+    )]
+    dedup_window: Option<i64>,
+    #[structopt(
+        name = "squawk-region",
+        help = "Region whose VFR squawk is used to count VFR and IFR traffic: us (1200) or eu (7000)",
+        default_value = "us",
+        long = "squawk-region"
+    )]
+    squawk_region: SquawkRegion,
+    #[structopt(
+        name = "labels",
+        help = "CSV file of icao,label lines giving labels to show for aircraft of interest",
+        long = "labels",
+        parse(from_os_str)
+    )]
+    labels: Option<PathBuf>,
+    #[structopt(
+        name = "registry",
+        help = "BaseStation-style aircraft.csv to look up the registration and type of aircraft in",
+        long = "registry",
+        parse(from_os_str)
+    )]
+    registry: Option<PathBuf>,
+    #[structopt(
+        name = "decode-threads",
+        help = "Number of threads decoding frames, so the tracker is locked only to apply them (0 decodes on the reader thread). Needs spare cores to pay off",
+        default_value = "0",
+        long = "decode-threads"
+    )]
+    decode_threads: usize,
+    #[structopt(
+        name = "mach-above",
+        help = "Show speed as a Mach number for aircraft above this flight level (~ when derived from ground speed)",
+        long = "mach-above",
+        parse(try_from_str = parse_flight_level)
+    )]
+    mach_above: Option<u32>,
+    #[structopt(
+        name = "notify-new",
+        help = "Log newly acquired aircraft to stderr",
+        long = "notify-new"
+    )]
+    notify_new: bool,
+    #[structopt(
+        name = "beep",
+        help = "Ring the terminal bell for newly acquired aircraft",
+        long = "beep"
+    )]
+    beep: bool,
+    #[structopt(
+        name = "reacquire-after",
+        help = "Also notify about aircraft heard again after this many seconds of silence",
+        long = "reacquire-after"
+    )]
+    reacquire_after: Option<i64>,
+    #[structopt(
+        name = "home-airport",
+        help = "Flag likely arrivals (A) and departures (D) at the airport at LAT,LON[,ELEVATION_FT]",
+        long = "home-airport"
+    )]
+    home_airport: Option<Airport>,
+    #[structopt(
+        name = "flight-summaries",
+        help = "File to append a JSON summary of each expired aircraft to, one per line (- for stdout)",
+        long = "flight-summaries",
+        parse(from_os_str)
+    )]
+    flight_summaries: Option<PathBuf>,
+    #[structopt(
+        name = "csv-out",
+        help = "File to write each decoded position to as a time,icao,lat,lon,alt CSV line (- for stdout)",
+        long = "csv-out",
+        parse(from_os_str)
+    )]
+    csv_out: Option<PathBuf>,
+    #[structopt(
+        name = "csv-interval",
+        help = "Write at most one position per aircraft in this many seconds to the --csv-out file or --output csv (default 10 for the latter)",
+        long = "csv-interval"
+    )]
+    csv_interval: Option<i64>,
+    #[cfg(feature = "sqlite")]
+    #[structopt(
+        name = "record-sqlite",
+        help = "SQLite database to record every frame received to, in a pings table as replayed by the sqlite command",
+        long = "record-sqlite",
+        parse(from_os_str)
+    )]
+    record_sqlite: Option<PathBuf>,
+    #[structopt(
+        name = "receiver",
+        help = "Location of the receiver as LAT,LON, to show the distance and bearing of aircraft",
+        long = "receiver"
+    )]
+    receiver: Option<ReceiverPosition>,
+    #[structopt(
+        name = "max-range",
+        help = "Discard positions farther than this from the receiver (nautical miles, default 400). At 180 or less, lone position messages are decoded against the receiver",
+        long = "max-range",
+        requires = "receiver"
+    )]
+    max_range: Option<f64>,
+    #[structopt(
+        name = "json",
+        help = "File to keep updated with the current aircraft in dump1090's aircraft.json format (- for a line per update on stdout)",
+        long = "json",
+        parse(from_os_str)
+    )]
+    json: Option<PathBuf>,
+    #[structopt(
+        name = "state-file",
+        help = "File from which to restore the tracked aircraft on startup, saved every minute and on shutdown, including on Ctrl-C or SIGTERM",
+        long = "state-file",
+        parse(from_os_str)
+    )]
+    state_file: Option<PathBuf>,
+    #[cfg(feature = "metrics")]
+    #[structopt(
+        name = "metrics-port",
+        help = "Serve metrics in the Prometheus text format over HTTP on this port",
+        long = "metrics-port"
+    )]
+    metrics_port: Option<u16>,
+    #[structopt(
+        name = "websocket-port",
+        help = "Push the current aircraft, then each new aircraft, position, callsign and expiry as JSON to WebSocket clients on this port",
+        long = "websocket-port"
+    )]
+    websocket_port: Option<u16>,
+    #[structopt(
+        name = "sort",
+        help = "Column to sort the aircraft table by: icao, callsign, altitude, distance or last-seen",
+        default_value = "icao",
+        long = "sort"
+    )]
+    sort: SortKey,
+    #[structopt(
+        name = "units",
+        help = "Units to show altitudes and speeds in: imperial (feet and knots) or metric (meters and km/h)",
+        default_value = "imperial",
+        long = "units"
+    )]
+    units: Units,
+    #[structopt(
+        name = "reverse",
+        help = "Sort the aircraft table in descending order",
+        long = "reverse"
+    )]
+    reverse: bool,
+    #[structopt(
+        name = "detail",
+        help = "Show everything known about the aircraft with this ICAO address instead of the table or statistics",
+        long = "detail",
+        parse(try_from_str = parse_icao_address)
+    )]
+    detail: Option<ICAOAddress>,
+}
+
+/// How the aircraft table and statistics are shown
+struct DisplayOptions {
+    /// Show the aircraft table rather than message statistics
+    table: bool,
+    /// Print plain text, without escape codes
+    plain: bool,
+    /// Altitude bands to highlight in the aircraft table
+    highlight: Vec<AltitudeBand>,
+    /// Region whose VFR squawk convention is used in the statistics
+    squawk_region: SquawkRegion,
+    /// Labels shown in the aircraft table
+    labels: Labels,
+    /// Altitude above which speed is shown as a Mach number (feet)
+    mach_above: Option<u32>,
+    /// Airport whose arrivals and departures are flagged
+    home_airport: Option<Airport>,
+    /// Column the aircraft table is sorted by
+    sort: SortKey,
+    /// Units altitudes and speeds are shown in
+    units: Units,
+    /// Sort the aircraft table in descending order
+    reverse: bool,
+    /// Aircraft to show the details of instead of the table or statistics
+    detail: Option<ICAOAddress>,
+    /// Separation below which pairs of aircraft are warned about
+    proximity: Option<Separation>,
+}
+
+/// Range of altitudes (feet) in which aircraft are highlighted
+#[derive(Debug, Clone, Copy)]
+struct AltitudeBand {
+    min: i32,
+    max: i32,
+}
+
+impl AltitudeBand {
+    fn contains(&self, altitude: i32) -> bool {
+        self.min <= altitude && altitude <= self.max
+    }
+}
+
+impl FromStr for AltitudeBand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid altitude band '{}' (expected MIN:MAX)", s);
+        let (min, max) = s.split_once(':').ok_or_else(error)?;
+        let min = min.trim().parse().map_err(|_| error())?;
+        let max = max.trim().parse().map_err(|_| error())?;
+        if min > max {
+            return Err(error());
+        }
+        Ok(AltitudeBand { min, max })
+    }
+}
+
+/// Minimum separation between aircraft, below which they're warned about
+#[derive(Debug, Clone, Copy)]
+struct Separation {
+    /// Horizontal distance (nautical miles)
+    horizontal: f64,
+    /// Altitude difference (feet)
+    vertical: i32,
+}
+
+impl FromStr for Separation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid separation '{}' (expected NM:FT)", s);
+        let (horizontal, vertical) = s.split_once(':').ok_or_else(error)?;
+        let horizontal: f64 = horizontal.trim().parse().map_err(|_| error())?;
+        let vertical: i32 = vertical.trim().parse().map_err(|_| error())?;
+        if !(horizontal >= 0.0 && horizontal.is_finite()) || vertical < 0 {
+            return Err(error());
+        }
+        Ok(Separation {
+            horizontal,
+            vertical,
+        })
+    }
+}
+
+/// Location of the receiver (degrees)
+#[derive(Debug, Clone, Copy)]
+struct ReceiverPosition {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl FromStr for ReceiverPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid receiver position '{}' (expected LAT,LON)", s);
+        let (latitude, longitude) = s.split_once(',').ok_or_else(error)?;
+        let latitude: f64 = latitude.trim().parse().map_err(|_| error())?;
+        let longitude: f64 = longitude.trim().parse().map_err(|_| error())?;
+        if latitude.abs() > 90.0 || longitude.abs() > 180.0 {
+            return Err(error());
+        }
+        Ok(ReceiverPosition {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+/// Parse an ICAO address given as six hex digits
+fn parse_icao_address(s: &str) -> Result<ICAOAddress, String> {
+    let error = || format!("invalid ICAO address '{}' (expected six hex digits)", s);
+    if s.len() != 6 {
+        return Err(error());
+    }
+    let address = u32::from_str_radix(s, 16).map_err(|_| error())?;
+    icao_from_u32(address).ok_or_else(error)
+}
+
+/// Parse a flight level, which has three digits at most
+fn parse_flight_level(s: &str) -> Result<u32, String> {
+    s.parse()
+        .ok()
+        .filter(|&level| level <= 999)
+        .ok_or_else(|| format!("invalid flight level '{}' (expected 0 to 999)", s))
+}
+
+/// Parse the number of seconds between history snapshots, which mustn't be zero
+fn parse_history_interval(s: &str) -> Result<u64, String> {
+    s.parse()
+        .ok()
+        .filter(|&seconds| seconds > 0)
+        .ok_or_else(|| format!("invalid interval '{}' (expected at least 1 second)", s))
+}
+
+/// Column the aircraft table is sorted by. Aircraft missing the value are always listed last.
+#[derive(Debug, Clone, Copy)]
+enum SortKey {
+    Icao,
+    Callsign,
+    Altitude,
+    /// Distance from the receiver, when its location is known
+    Distance,
+    /// Most recently seen first
+    LastSeen,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "icao" => Ok(SortKey::Icao),
+            "callsign" => Ok(SortKey::Callsign),
+            "altitude" => Ok(SortKey::Altitude),
+            "distance" => Ok(SortKey::Distance),
+            "last-seen" => Ok(SortKey::LastSeen),
+            _ => Err(format!(
+                "unknown sort column '{}' (expected icao, callsign, altitude, distance or last-seen)",
+                s
+            )),
+        }
+    }
+}
+
+/// Units altitudes and speeds are shown in. They're always kept in feet and knots, and only
+/// converted for display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Units {
+    /// Feet and knots
+    Imperial,
+    /// Meters and kilometers per hour
+    Metric,
+}
+
+impl Units {
+    /// Convert an altitude in feet
+    fn altitude<T: Into<f64>>(self, feet: Option<T>) -> Option<f64> {
+        let feet = feet?.into();
+        Some(match self {
+            Units::Imperial => feet,
+            Units::Metric => feet * METERS_PER_FOOT,
+        })
+    }
+
+    /// Convert a speed in knots
+    fn speed(self, knots: Option<f64>) -> Option<f64> {
+        let knots = knots?;
+        Some(match self {
+            Units::Imperial => knots,
+            Units::Metric => knots * KMH_PER_KNOT,
+        })
+    }
+
+    fn altitude_unit(self) -> &'static str {
+        match self {
+            Units::Imperial => "ft",
+            Units::Metric => "m",
+        }
+    }
+
+    fn speed_unit(self) -> &'static str {
+        match self {
+            Units::Imperial => "kt",
+            Units::Metric => "km/h",
+        }
+    }
+}
+
+/// What the program prints to stdout
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputMode {
+    /// Statistics, the aircraft table or an aircraft's details, refreshed periodically
+    Display,
+    /// Decoded positions as CSV, as they're received
+    Csv,
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "display" => Ok(OutputMode::Display),
+            "csv" => Ok(OutputMode::Csv),
+            _ => Err(format!("unknown output '{}' (expected display or csv)", s)),
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "imperial" => Ok(Units::Imperial),
+            "metric" => Ok(Units::Metric),
+            _ => Err(format!(
+                "unknown units '{}' (expected imperial or metric)",
+                s
+            )),
+        }
+    }
+}
+
+/// Offset added to the timestamps of replayed messages, so that old recordings look live
+#[derive(Debug, Clone, Copy)]
+enum TimeShift {
+    Fixed(Duration),
+    /// Shift the latest message to the time the replay starts, so the recording ends now
+    Auto,
+}
+
+impl FromStr for TimeShift {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(TimeShift::Auto);
+        }
+        match s.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() => Ok(TimeShift::Fixed(Duration::milliseconds(
+                (seconds * 1000.0).round() as i64,
+            ))),
+            _ => Err(format!(
+                "invalid time shift '{}' (expected seconds or auto)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(about = "Read messages from stdin")]
+    Stdin {
+        #[structopt(
+            help = "Lines are prefixed with a timestamp, e.g. `1604340192.345,*8D...;`",
+            long = "timestamped"
+        )]
+        timestamped: bool,
+        #[structopt(
+            help = "Timestamp format: auto, epoch-s, epoch-ms or rfc3339",
+            default_value = "auto",
+            long = "time-format"
+        )]
+        time_format: TimeFormat,
+        #[structopt(
+            help = "Shift timestamps by this many seconds, or by `auto` to replay as if the last message was received now (reading all input first)",
+            long = "time-shift",
+            requires = "timestamped",
+            allow_hyphen_values = true
+        )]
+        time_shift: Option<TimeShift>,
+    },
+    #[structopt(about = "Replay a file of timestamped messages at the pace they were received")]
+    Replay {
+        #[structopt(
+            help = "File of lines prefixed with a timestamp, e.g. `1604340192.345,*8D...;`",
+            parse(from_os_str)
+        )]
+        path: PathBuf,
+        #[structopt(
+            help = "Playback speed, e.g. 2 to replay twice as fast",
+            default_value = "1",
+            long = "speed"
+        )]
+        speed: f64,
+        #[structopt(
+            help = "Timestamp format: auto, epoch-s, epoch-ms or rfc3339",
+            default_value = "auto",
+            long = "time-format"
+        )]
+        time_format: TimeFormat,
+    },
+    #[structopt(about = "Process a file of messages as fast as possible, then print a summary")]
+    File {
+        #[structopt(
+            help = "File of messages, each line optionally prefixed with a timestamp, e.g. `2020-11-02T18:03:12.345Z,*8D...;`",
+            parse(from_os_str)
+        )]
+        path: PathBuf,
+        #[structopt(
+            help = "Timestamp format: auto, epoch-s, epoch-ms or rfc3339",
+            default_value = "auto",
+            long = "time-format"
+        )]
+        time_format: TimeFormat,
+    },
+    #[structopt(about = "Read messages from a TCP server")]
+    Tcp {
+        #[structopt(help = "host")]
+        host: String,
+        #[structopt(help = "port", default_value = "30002")]
+        port: u16,
+    },
+    #[structopt(about = "Read messages in the Beast binary format from a TCP server")]
+    Beast {
+        #[structopt(help = "host")]
+        host: String,
+        #[structopt(help = "port", default_value = "30005")]
+        port: u16,
+    },
+    #[structopt(about = "Read SBS BaseStation messages from a TCP server")]
+    Sbs {
+        #[structopt(help = "host")]
+        host: String,
+        #[structopt(help = "port", default_value = "30003")]
+        port: u16,
+    },
+    #[structopt(about = "Poll dump1090's aircraft.json over HTTP for already decoded aircraft")]
+    Json1090 {
+        #[structopt(help = "URL, e.g. `http://localhost:8080/data/aircraft.json`")]
+        url: String,
+        #[structopt(help = "Seconds between polls", default_value = "1", long = "interval")]
+        interval: f64,
+    },
+    #[structopt(about = "Replay pings stored in Postgres")]
+    Postgres {
+        #[structopt(
+            help = "Connection string, e.g. `host=localhost user=adsb password=secret`",
+            default_value = DEFAULT_POSTGRES_DSN,
+            long = "dsn",
+            env = "DATABASE_URL"
+        )]
+        dsn: String,
+        #[structopt(
+            help = "Column of the pings table holding the time each frame was received",
+            default_value = "timestamp",
+            long = "timestamp-column"
+        )]
+        timestamp_column: String,
+        #[structopt(
+            help = "Column of the pings table holding the binary frames",
+            default_value = "data",
+            long = "data-column"
+        )]
+        data_column: String,
+    },
+    #[cfg(feature = "sqlite")]
+    #[structopt(about = "Replay pings recorded to SQLite with --record-sqlite")]
+    Sqlite {
+        #[structopt(help = "Database file", parse(from_os_str))]
+        path: PathBuf,
+        #[structopt(
+            help = "Query returning the time each frame was received and the binary frame, in the order to replay them",
+            default_value = SQLITE_DEFAULT_QUERY,
+            long = "query"
+        )]
+        query: String,
+    },
+}
+
+/// Progress through a recorded dataset, shared between the reader and the output thread
+struct ReplayProgress {
+    /// Number of messages processed so far
+    processed: u64,
+    /// Total number of messages, if known up front
+    total: Option<u64>,
+    /// Timestamp of the last message processed
+    message_time: Option<chrono::DateTime<Utc>>,
+    started: chrono::DateTime<Utc>,
+}
+
+impl ReplayProgress {
+    fn new(total: Option<u64>) -> Self {
+        ReplayProgress {
+            processed: 0,
+            total,
+            message_time: None,
+            started: Utc::now(),
+        }
+    }
+
+    fn update(&mut self, message_time: chrono::DateTime<Utc>) {
+        self.processed += 1;
+        self.message_time = Some(message_time);
+    }
+}
+
+/// Logs and/or beeps for newly acquired aircraft, up to `MAX_NOTIFICATIONS_PER_MINUTE`. Any
+/// beyond that are counted and reported once notifications resume.
+struct Notifier {
+    log: bool,
+    beep: bool,
+    recent: VecDeque<chrono::DateTime<Utc>>,
+    suppressed: u64,
+}
+
+impl Notifier {
+    fn new(log: bool, beep: bool) -> Self {
+        Notifier {
+            log,
+            beep,
+            recent: VecDeque::new(),
+            suppressed: 0,
+        }
+    }
+
+    fn notify(&mut self, tracker: &Tracker, acquisitions: Vec<Acquisition>) {
+        let now = Utc::now();
+        while let Some(time) = self.recent.front() {
+            if now.signed_duration_since(*time) < Duration::minutes(1) {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        for acquisition in acquisitions {
+            if self.recent.len() >= MAX_NOTIFICATIONS_PER_MINUTE {
+                self.suppressed += 1;
+                continue;
+            }
+            self.recent.push_back(now);
+            if self.log {
+                if self.suppressed > 0 {
+                    eprintln!("{} more aircraft acquired", self.suppressed);
+                }
+                let callsign = tracker
+                    .get_aircraft(&acquisition.icao_address)
+                    .and_then(|a| a.callsign.clone())
+                    .unwrap_or_else(|| NA.to_string());
+                let line = format!(
+                    "{} {} {} {}",
+                    acquisition.time.format("%Y-%m-%d %H:%M:%S UTC"),
+                    if acquisition.reacquired {
+                        "reacquired"
+                    } else {
+                        "acquired"
+                    },
+                    acquisition.icao_address,
+                    callsign
+                );
+                eprintln!("{}", line.trim_end());
+            }
+            self.suppressed = 0;
+            if self.beep {
+                eprint!("\x07");
+            }
+        }
+    }
+}
+
+/// Replay progress, left as `None` for live feeds
+type Progress = Arc<Mutex<Option<ReplayProgress>>>;
+
+/// Flag raised when the program is ending. Readers check it between messages and stop, and the
+/// threads writing files make a last update before returning.
+#[derive(Clone, Default)]
+struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    fn request(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+
+    fn is_requested(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
+    }
+}
+
+/// Raised by the handler for SIGINT and SIGTERM, which can only safely set a flag. The main thread
+/// watches it to request a shutdown.
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    SIGNALLED.store(true, atomic::Ordering::Relaxed);
+    // A second signal kills the program, in case a reader is blocked waiting for input
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+/// Shut down gracefully on Ctrl-C or a request to terminate, rather than being killed before
+/// the files being written are brought up to date
+fn handle_signals() {
+    let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            libc::signal(signal, handler);
+        }
+    }
+}
+
+/// Where readers send the frames they receive: either decoded on the reader thread while holding
+/// the tracker lock, or handed to a pool of decoding threads
+enum FrameSink {
+    Inline(Arc<Mutex<Tracker>>),
+    Pool(DecodePool),
+}
+
+impl FrameSink {
+    fn new(tracker: Arc<Mutex<Tracker>>, decode_threads: usize) -> Self {
+        match decode_threads {
+            0 => FrameSink::Inline(tracker),
+            threads => FrameSink::Pool(DecodePool::new(tracker, threads)),
+        }
+    }
+
+    fn push(&mut self, frame: RawFrame, time: chrono::DateTime<Utc>) {
+        match self {
+            FrameSink::Inline(tracker) => {
+                let mut tracker = tracker.lock().unwrap();
+                let _ = match frame {
+                    RawFrame::Avr(frame) => tracker.update_with_avr(&frame, time),
+                    RawFrame::Binary(frame) => tracker.update_with_binary(&frame, time),
+                };
+            }
+            FrameSink::Pool(pool) => pool.push(frame, time),
+        }
+    }
+
+    /// Called by readers when they have no more input at hand, so that frames aren't held back
+    /// in a partly filled batch while waiting for more
+    fn flush(&mut self) {
+        if let FrameSink::Pool(pool) = self {
+            pool.flush();
+        }
+    }
+
+    /// Called by readers at the end of their input, to apply all frames before returning
+    fn finish(self) {
+        if let FrameSink::Pool(pool) = self {
+            pool.finish();
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Cli::from_args();
+    let mut tracker = match &args.state_file {
+        Some(path) if path.exists() => Tracker::load(path)?,
+        _ => Tracker::new(),
+    };
+    tracker.set_max_track_age(args.track_age.map(Duration::minutes));
+    tracker.set_track_min_distance(args.track_min_distance);
+    tracker.set_dedup_window(args.dedup_window.map(Duration::milliseconds));
+    if let Some(receiver) = args.receiver {
+        tracker.set_receiver_position(receiver.latitude, receiver.longitude);
+    }
+    if let Some(max_range) = args.max_range {
+        tracker.set_max_range(max_range);
+    }
+    if let Some(path) = &args.registry {
+        tracker.set_registry(Box::new(CsvRegistry::load(path)?));
+    }
+    let notifier = (args.notify_new || args.beep).then(|| {
+        tracker.enable_acquisitions(args.reacquire_after.map(Duration::seconds));
+        Notifier::new(args.notify_new, args.beep)
+    });
+    if args.flight_summaries.is_some() {
+        tracker.enable_flight_summaries();
+    }
+    let csv_output = args.output == OutputMode::Csv;
+    if csv_output && args.csv_out.is_some() {
+        bail!("--output csv prints positions to stdout, so it can't be combined with --csv-out");
+    }
+    if args.csv_out.is_some() {
+        tracker.enable_position_log(args.csv_interval.map(Duration::seconds));
+    } else if csv_output {
+        let interval = args.csv_interval.unwrap_or(CSV_OUTPUT_INTERVAL_SECS);
+        tracker.enable_position_log(Some(Duration::seconds(interval)));
+    }
+    #[cfg(feature = "sqlite")]
+    if args.record_sqlite.is_some() {
+        tracker.enable_frame_log();
+    }
+    let tracker = Arc::new(Mutex::new(tracker));
+    let progress = Arc::new(Mutex::new(None));
+    let expire = Duration::seconds(args.expire);
+    let plain = args.plain || !io::stdout().is_terminal();
+    if plain && !args.plain && !csv_output {
+        eprintln!("stdout is not a terminal, printing plain text (use --plain to hide this note)");
+    }
+    let labels = match &args.labels {
+        Some(path) => Labels::load(path)?,
+        None => Labels::default(),
+    };
+    let options = DisplayOptions {
+        table: args.table,
+        plain,
+        highlight: args.highlight_alt,
+        squawk_region: args.squawk_region,
+        labels,
+        mach_above: args.mach_above.map(|flight_level| flight_level * 100),
+        home_airport: args.home_airport,
+        sort: args.sort,
+        units: args.units,
+        reverse: args.reverse,
+        detail: args.detail,
+        proximity: args.proximity,
+    };
+    let shutdown = Shutdown::default();
+    handle_signals();
+    // The display and metrics threads run until the program exits, the file writers are joined,
+    // as is the CSV output so that the last positions are printed
+    let mut writers = Vec::new();
+    if csv_output {
+        let (tracker, shutdown) = (tracker.clone(), shutdown.clone());
+        writers.push(print_positions_csv(tracker, expire, notifier, shutdown));
+    } else {
+        write_output(tracker.clone(), progress.clone(), expire, options, notifier);
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(port) = args.metrics_port {
+        serve_metrics(tracker.clone(), expire, listen(port)?);
+    }
+    if let Some(port) = args.websocket_port {
+        serve_websocket(tracker.clone(), expire, listen(port)?);
+    }
+    if let Some(dir) = args.history_dir {
+        let interval = std::time::Duration::from_secs(args.history_interval);
+        writers.push(write_history(
+            tracker.clone(),
+            expire,
+            dir,
+            interval,
+            args.history_count,
+            shutdown.clone(),
+        ));
+    }
+    if let Some(path) = args.json {
+        let shutdown = shutdown.clone();
+        writers.push(write_aircraft_json(tracker.clone(), expire, path, shutdown));
+    }
+    if let Some(path) = args.flight_summaries {
+        let shutdown = shutdown.clone();
+        writers.push(write_flight_summaries(tracker.clone(), path, shutdown));
+    }
+    if let Some(path) = args.csv_out {
+        writers.push(write_positions_csv(tracker.clone(), path, shutdown.clone()));
+    }
+    if let Some(path) = args.state_file {
+        writers.push(save_state(tracker.clone(), path, shutdown.clone()));
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = args.record_sqlite {
+        writers.push(record_to_sqlite(tracker.clone(), path, shutdown.clone()));
+    }
+    let decode_threads = args.decode_threads;
+    let sink = || FrameSink::new(tracker.clone(), decode_threads);
+    let reader = match args.cmd {
+        Command::Stdin {
+            timestamped,
+            time_format,
+            time_shift,
+        } => read_from_stdin(
+            sink(),
+            progress,
+            timestamped.then_some(time_format),
+            time_shift,
+            shutdown.clone(),
+        ),
+        Command::Replay {
+            path,
+            speed,
+            time_format,
+        } => {
+            if !(speed > 0.0 && speed.is_finite()) {
+                bail!("invalid speed {} (expected a positive number)", speed);
+            }
+            replay_file(sink(), progress, path, speed, time_format, shutdown.clone())
+        }
+        Command::File { path, time_format } => {
+            read_from_file(sink(), tracker.clone(), path, time_format, shutdown.clone())
+        }
+        Command::Tcp { host, port } => read_from_network(host, port, sink(), shutdown.clone()),
+        Command::Beast { host, port } => {
+            read_beast_from_network(host, port, sink(), shutdown.clone())
+        }
+        Command::Sbs { host, port } => {
+            read_sbs_from_network(host, port, tracker.clone(), shutdown.clone())
+        }
+        Command::Json1090 { url, interval } => {
+            if !(interval > 0.0 && interval.is_finite()) {
+                bail!("invalid interval {} (expected a positive number)", interval);
+            }
+            let interval = std::time::Duration::from_secs_f64(interval);
+            poll_aircraft_json(url, interval, tracker.clone(), shutdown.clone())
+        }
+        Command::Postgres {
+            dsn,
+            timestamp_column,
+            data_column,
+        } => {
+            let columns = (timestamp_column, data_column);
+            read_from_postgres(sink(), progress, dsn, columns, shutdown.clone())
+        }
+        #[cfg(feature = "sqlite")]
+        Command::Sqlite { path, query } => {
+            read_from_sqlite(sink(), progress, path, query, shutdown.clone())
+        }
+    };
+
+    // Readers which run out of input, such as a replay, end the program, as does SIGINT or
+    // SIGTERM. A file writer only returns early if it fails, which stops the reader too. Readers
+    // notice between messages, so one blocked waiting for input (such as on stdin or a quiet TCP
+    // feed) only stops once more arrives, or on a second signal.
+    while !reader.is_finished()
+        && !writers.iter().any(JoinHandle::is_finished)
+        && !SIGNALLED.load(atomic::Ordering::Relaxed)
+    {
+        thread::sleep(REFRESH_INTERVAL);
+    }
+    shutdown.request();
+    // Every writer gets to finish before the first error is reported
+    let mut result = reader.join().unwrap();
+    for writer in writers {
+        result = result.and(writer.join().unwrap());
+    }
+    result
+}
+
+/// Read AVR frames from stdin until it ends. If a time format is given each line is expected to
+/// be prefixed with the time the frame was received, otherwise the current time is used.
+/// Timestamped input is treated as a replay of unknown length, with its timestamps shifted by
+/// `time_shift`. To shift the input so it ends now, all of it is read before any is applied. The
+/// replay progress shows the original timestamps.
+fn read_from_stdin(
+    mut sink: FrameSink,
+    progress: Progress,
+    time_format: Option<TimeFormat>,
+    time_shift: Option<TimeShift>,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        if time_format.is_some() {
+            *progress.lock().unwrap() = Some(ReplayProgress::new(None));
+        }
+        let mut reader = BufReader::new(io::stdin());
+        let mut buffered = VecDeque::new();
+        // The same offset is applied to every message to keep the intervals between them
+        let offset = match (time_shift, time_format) {
+            (Some(TimeShift::Fixed(offset)), _) => offset,
+            (Some(TimeShift::Auto), Some(format)) => {
+                buffered = (&mut reader).lines().collect::<io::Result<_>>()?;
+                let lines = buffered.iter().map(String::as_str);
+                offset_to_end_at(lines, format, Utc::now()).unwrap_or_else(Duration::zero)
+            }
+            _ => Duration::zero(),
+        };
+        let mut input = String::new();
+        while !shutdown.is_requested() {
+            match buffered.pop_front() {
+                Some(line) => input = line,
+                None => {
+                    if reader.read_line(&mut input)? == 0 {
+                        break;
+                    }
+                }
+            }
+            match time_format {
+                Some(format) => {
+                    if let Some((time, frame)) = parse_timestamped_line(&input, format) {
+                        sink.push(RawFrame::Avr(frame.to_string()), time + offset);
+                        if let Some(progress) = progress.lock().unwrap().as_mut() {
+                            progress.update(time);
+                        }
+                    }
+                }
+                None => sink.push(RawFrame::Avr(input.clone()), Utc::now()),
+            }
+            if buffered.is_empty() && reader.buffer().is_empty() {
+                sink.flush();
+            }
+            input.clear();
+        }
+        sink.finish();
+        Ok(())
+    })
+}
+
+struct Ping {
+    timestamp: chrono::DateTime<Utc>,
+    data: Vec<u8>,
+}
+
+/// Read pings in batches, each in its own short-lived transaction so that a long replay doesn't
+/// hold one open. Pings are ordered by data as well as by timestamp, and each batch resumes from
+/// the last ping read, skipping as many identical pings as have already been read, so that pings
+/// sharing a timestamp, or even their data, are neither skipped nor repeated. Once caught up, new
+/// pings are polled for.
+fn read_from_postgres(
+    mut sink: FrameSink,
+    progress: Progress,
+    dsn: String,
+    (timestamp_column, data_column): (String, String),
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let mut client = Client::connect(&dsn, NoTls)?;
+        let total: i64 = client.query_one("SELECT count(*) FROM pings", &[])?.get(0);
+        *progress.lock().unwrap() = Some(ReplayProgress::new(Some(total as u64)));
+        let (timestamp, data) = (
+            quote_identifier(&timestamp_column),
+            quote_identifier(&data_column),
+        );
+        let first = client
+            .prepare(&format!(
+                "SELECT {0}, {1} FROM pings ORDER BY {0}, {1} LIMIT $1",
+                timestamp, data
+            ))
+            .with_context(|| {
+                format!(
+                    "can't read columns {} and {} of pings",
+                    timestamp_column, data_column
+                )
+            })?;
+        check_column_types(
+            &first,
+            &[
+                (&timestamp_column, Type::TIMESTAMPTZ),
+                (&data_column, Type::BYTEA),
+            ],
+        )?;
+        let next = client.prepare(&format!(
+            "SELECT {0}, {1} FROM pings WHERE ({0}, {1}) >= ($1, $2) ORDER BY {0}, {1} \
+             OFFSET $3 LIMIT $4",
+            timestamp, data
+        ))?;
+        let mut last: Option<Ping> = None;
+        // How many pings identical to the last one have been read
+        let mut repeats: i64 = 0;
+        while !shutdown.is_requested() {
+            let rows = match &last {
+                None => client.query(&first, &[&POSTGRES_BATCH_SIZE])?,
+                Some(ping) => client.query(
+                    &next,
+                    &[&ping.timestamp, &ping.data, &repeats, &POSTGRES_BATCH_SIZE],
+                )?,
+            };
+            if rows.is_empty() {
+                thread::sleep(POSTGRES_POLL_INTERVAL);
+                continue;
+            }
+            for row in rows {
+                let ping = Ping {
+                    timestamp: row.get(0),
+                    data: row.get(1),
+                };
+                sink.push(RawFrame::Binary(ping.data.clone()), Utc::now());
+                if let Some(progress) = progress.lock().unwrap().as_mut() {
+                    progress.update(ping.timestamp);
+                }
+                let repeated = last.as_ref().is_some_and(|last| {
+                    (last.timestamp, &last.data) == (ping.timestamp, &ping.data)
+                });
+                repeats = if repeated { repeats + 1 } else { 1 };
+                last = Some(ping);
+            }
+            sink.flush();
+        }
+        sink.finish();
+        Ok(())
+    })
+}
+
+/// Replay the pings returned by a query of an SQLite database, from start to end, as described
+/// for `FrameDatabase::replay`
+#[cfg(feature = "sqlite")]
+fn read_from_sqlite(
+    mut sink: FrameSink,
+    progress: Progress,
+    path: PathBuf,
+    query: String,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let database = FrameDatabase::open_read_only(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        *progress.lock().unwrap() = Some(ReplayProgress::new(database.count(&query)?));
+        database
+            .replay(&query, |frame| {
+                sink.push(RawFrame::Binary(frame.data), Utc::now());
+                if let Some(progress) = progress.lock().unwrap().as_mut() {
+                    progress.update(frame.time);
+                }
+                !shutdown.is_requested()
+            })
+            .with_context(|| format!("can't run query '{}'", query))?;
+        sink.finish();
+        Ok(())
+    })
+}
+
+/// Quote a Postgres identifier, such as a column name, for use in a query
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Check that the columns returned by a statement have the types they will be read as, so that
+/// a mismatch fails with an error naming the column rather than when reading the first row
+fn check_column_types(statement: &Statement, expected: &[(&str, Type)]) -> Result<()> {
+    for (column, (name, expected)) in statement.columns().iter().zip(expected) {
+        if column.type_() != expected {
+            bail!(
+                "column {} of pings has type {}, expected {}",
+                name,
+                column.type_(),
+                expected
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Replay a file of timestamped AVR frames, waiting between frames for the time that passed
+/// between them when they were received, divided by `speed`. Frames are applied with their
+/// recorded timestamps. Lines without a valid timestamp are skipped.
+fn replay_file(
+    mut sink: FrameSink,
+    progress: Progress,
+    path: PathBuf,
+    speed: f64,
+    time_format: TimeFormat,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let open = || fs::File::open(&path).with_context(|| format!("opening {}", path.display()));
+        let total = BufReader::new(open()?).lines().count();
+        *progress.lock().unwrap() = Some(ReplayProgress::new(Some(total as u64)));
+        let started = Instant::now();
+        let mut first_time = None;
+        for line in BufReader::new(open()?).lines() {
+            if shutdown.is_requested() {
+                break;
+            }
+            let line = line?;
+            let (time, frame) = match parse_timestamped_line(&line, time_format) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            // Wait relative to the start of the replay, so delays don't accumulate
+            let first_time = *first_time.get_or_insert(time);
+            let elapsed = time.signed_duration_since(first_time).num_milliseconds();
+            let due = std::time::Duration::from_millis(elapsed.max(0) as u64).div_f64(speed);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                sink.flush();
+                thread::sleep(wait);
+            }
+            sink.push(RawFrame::Avr(frame.to_string()), time);
+            if let Some(progress) = progress.lock().unwrap().as_mut() {
+                progress.update(time);
+            }
+        }
+        sink.finish();
+        Ok(())
+    })
 }
 
-#[derive(StructOpt)]
-enum Command {
-    #[structopt(about = "Read messages from stdin")]
-    Stdin,
-    #[structopt(about = "Read messages from a TCP server")]
-    Tcp {
-        #[structopt(help = "host")]
-        host: String,
-        #[structopt(help = "port", default_value = "30002")]
-        port: u16,
-    },
-    Postgres,
+/// Process every line of a file without pacing. Lines with a timestamp are given that time, others
+/// the current time. At the end of the file a summary is printed.
+fn read_from_file(
+    mut sink: FrameSink,
+    tracker: Arc<Mutex<Tracker>>,
+    path: PathBuf,
+    time_format: TimeFormat,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let file = fs::File::open(&path).with_context(|| format!("opening {}", path.display()))?;
+        for line in BufReader::new(file).lines() {
+            if shutdown.is_requested() {
+                break;
+            }
+            let line = line?;
+            match parse_timestamped_line(&line, time_format) {
+                Some((time, frame)) => sink.push(RawFrame::Avr(frame.to_string()), time),
+                None => sink.push(RawFrame::Avr(line), Utc::now()),
+            }
+        }
+        sink.finish();
+        let tracker = tracker.lock().unwrap();
+        println!(
+            "Processed {} messages from {} aircraft ({} unknown messages, {} positions)",
+            tracker.get_num_messages(),
+            tracker.get_num_aircraft_seen(),
+            tracker.get_num_unknown_messages(),
+            tracker.get_num_positions()
+        );
+        Ok(())
+    })
 }
 
-fn main() -> Result<()> {
-    let args = Cli::from_args();
-    let tracker = Arc::new(Mutex::new(Tracker::new()));
-    let expire = Duration::seconds(args.expire);
-    let writer = write_output(tracker.clone(), expire);
-    let reader = match args.cmd {
-        Command::Stdin => read_from_stdin(tracker),
-        Command::Tcp { host, port } => read_from_network(host, port, tracker),
-        Command::Postgres => read_from_postgres(tracker),
-    };
+/// Connect to a TCP server and pass the connection to `read`, reconnecting whenever the
+/// connection can't be made, fails or is closed by the server, until shutdown is requested. The
+/// wait before reconnecting doubles with each failed attempt, up to `RECONNECT_MAX_DELAY`, and
+/// starts over once a connection is made.
+fn read_reconnecting(
+    host: &str,
+    port: u16,
+    shutdown: &Shutdown,
+    mut read: impl FnMut(TcpStream) -> Result<()>,
+) -> Result<()> {
+    let mut delay = RECONNECT_MIN_DELAY;
+    while !shutdown.is_requested() {
+        let outcome = match TcpStream::connect((host, port)) {
+            Ok(stream) => {
+                delay = RECONNECT_MIN_DELAY;
+                read(stream).map(|_| "connection closed".to_string())
+            }
+            Err(e) => Err(e.into()),
+        };
+        if shutdown.is_requested() {
+            break;
+        }
+        let reason = outcome.unwrap_or_else(|e| format!("{:#}", e));
+        eprintln!(
+            "{}:{}: {}, reconnecting in {}s",
+            host,
+            port,
+            reason,
+            delay.as_secs()
+        );
+        thread::sleep(delay);
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+    Ok(())
+}
 
-    reader.join().unwrap()?;
-    writer.join().unwrap()?;
+fn read_from_network(
+    host: String,
+    port: u16,
+    mut sink: FrameSink,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        read_reconnecting(&host, port, &shutdown, |stream| {
+            let mut reader = BufReader::new(stream);
+            let mut input = String::new();
+            while !shutdown.is_requested() {
+                if reader.read_line(&mut input)? == 0 {
+                    break;
+                }
+                sink.push(RawFrame::Avr(input.clone()), Utc::now());
+                if reader.buffer().is_empty() {
+                    sink.flush();
+                }
+                input.clear();
+            }
+            sink.flush();
+            Ok(())
+        })?;
+        sink.finish();
+        Ok(())
+    })
+}
 
-    Ok(())
+fn read_beast_from_network(
+    host: String,
+    port: u16,
+    mut sink: FrameSink,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        read_reconnecting(&host, port, &shutdown, |mut stream| {
+            // A frame cut short by the old connection isn't completed by the new one
+            let mut decoder = BeastDecoder::new();
+            let mut buffer = [0; 4096];
+            while !shutdown.is_requested() {
+                let len = stream.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                let time = Utc::now();
+                for frame in decoder.push(&buffer[..len]) {
+                    if frame.kind != BeastFrameKind::ModeAc {
+                        sink.push(RawFrame::Binary(frame.message), time);
+                    }
+                }
+                sink.flush();
+            }
+            Ok(())
+        })?;
+        sink.finish();
+        Ok(())
+    })
 }
 
-fn read_from_stdin(tracker: Arc<Mutex<Tracker>>) -> JoinHandle<Result<()>> {
+/// Read SBS BaseStation messages, which are already decoded so they're applied to the tracker
+/// directly rather than through a decode pool
+fn read_sbs_from_network(
+    host: String,
+    port: u16,
+    tracker: Arc<Mutex<Tracker>>,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
     thread::spawn(move || {
-        let mut input = String::new();
-        loop {
-            let _ = io::stdin().read_line(&mut input)?;
+        read_reconnecting(&host, port, &shutdown, |stream| {
+            for line in BufReader::new(stream).lines() {
+                if shutdown.is_requested() {
+                    break;
+                }
+                tracker.lock().unwrap().update_with_sbs(&line?, Utc::now());
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Fetch dump1090's `aircraft.json` from `url` every `interval` and update the tracker with it,
+/// until shutdown is requested. Failed polls are reported and retried at the next interval.
+fn poll_aircraft_json(
+    url: String,
+    interval: std::time::Duration,
+    tracker: Arc<Mutex<Tracker>>,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        while !shutdown.is_requested() {
+            let next_poll = Instant::now() + interval;
+            let updated = http_get(&url).and_then(|document| {
+                let mut tracker = tracker.lock().unwrap();
+                match tracker.update_with_aircraft_json(&document, Utc::now()) {
+                    Some(_) => Ok(()),
+                    None => bail!("not an aircraft.json document"),
+                }
+            });
+            if let Err(e) = updated {
+                eprintln!("{}: {:#}", url, e);
+            }
+            while Instant::now() < next_poll && !shutdown.is_requested() {
+                thread::sleep(REFRESH_INTERVAL.min(next_poll - Instant::now()));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Fetch a document with a plain HTTP/1.0 GET request, so that the response is neither chunked
+/// nor kept alive. HTTPS isn't supported.
+fn http_get(url: &str) -> Result<String> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => bail!("unsupported URL (expected http://host[:port]/path)"),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().context("invalid port")?),
+        None => (authority, 80),
+    };
+    let address = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .with_context(|| format!("no address found for {}", host))?;
+    let mut stream = TcpStream::connect_timeout(&address, HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+        path, authority
+    )?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .context("malformed HTTP response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        bail!("{}", status);
+    }
+    Ok(body.to_string())
+}
+
+fn write_output(
+    tracker: Arc<Mutex<Tracker>>,
+    progress: Progress,
+    expire: Duration,
+    options: DisplayOptions,
+    mut notifier: Option<Notifier>,
+) -> JoinHandle<Result<()>> {
+    let interval = if options.plain {
+        PLAIN_REFRESH_INTERVAL
+    } else {
+        REFRESH_INTERVAL
+    };
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if options.plain {
+            println!("--- {}", Utc::now().format("%Y-%m-%d %H:%M:%S UTC"));
+        } else {
+            // Clear screen
+            print!("\x1B[2J\x1B[H");
+        }
+        {
             let mut tracker = tracker.lock().unwrap();
-            let _ = tracker.update_with_avr(&input, Utc::now());
-            input.clear();
+            // Replayed messages are judged by their own timestamps rather than the wall clock
+            let now = tracker.get_current_time();
+            tracker.remove_stale(expire, now);
+            if let Some(notifier) = notifier.as_mut() {
+                let acquisitions = tracker.take_acquisitions();
+                notifier.notify(&tracker, acquisitions);
+            }
+            if let Some(icao_address) = &options.detail {
+                print_aircraft_detail(&tracker, icao_address, now, &options);
+            } else if options.table {
+                print_ascii_table(&tracker, &expire, now, &options);
+            } else {
+                print_message_stats(&tracker, &expire, now, &options);
+            }
+            print_callsign_conflicts(&tracker, &expire, now);
+            if let Some(separation) = options.proximity {
+                print_proximity_warnings(&tracker, &expire, now, separation);
+            }
+        }
+        if let Some(progress) = progress.lock().unwrap().as_ref() {
+            print_replay_progress(progress);
         }
     })
 }
 
-struct Ping {
-    timestamp: chrono::DateTime<Utc>,
-    data: Vec<u8>,
+/// Periodically write snapshots to `history_0.json` .. `history_{count - 1}.json`, overwriting
+/// the oldest once all are used, and keep `receiver.json` updated with the number of valid
+/// snapshots so tar1090 can load them for its playback slider.
+fn write_history(
+    tracker: Arc<Mutex<Tracker>>,
+    expire: Duration,
+    dir: PathBuf,
+    interval: std::time::Duration,
+    count: usize,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        fs::create_dir_all(&dir)?;
+        let count = count.max(1);
+        let mut written = 0;
+        loop {
+            let last = shutdown.is_requested();
+            let json = {
+                let tracker = tracker.lock().unwrap();
+                to_aircraft_json(&tracker, &expire, tracker.get_current_time())
+            };
+            let path = dir.join(format!("history_{}.json", written % count));
+            write_atomically(&path, &json)?;
+            written += 1;
+            let receiver = format!(
+                "{{\"version\":\"flight-tracker {}\",\"refresh\":{},\"history\":{}}}",
+                env!("CARGO_PKG_VERSION"),
+                interval.as_millis(),
+                written.min(count)
+            );
+            write_atomically(&dir.join("receiver.json"), &receiver)?;
+            if last {
+                return Ok(());
+            }
+            thread::sleep(interval);
+        }
+    })
+}
+
+/// Periodically write the current aircraft in dump1090's `aircraft.json` format to the file at
+/// `path`, replacing its contents, or as a line to stdout if it's `-`. Times are relative to the
+/// most recent message, so replayed messages look as they did when they were received.
+fn write_aircraft_json(
+    tracker: Arc<Mutex<Tracker>>,
+    expire: Duration,
+    path: PathBuf,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || loop {
+        let last = shutdown.is_requested();
+        let json = {
+            let tracker = tracker.lock().unwrap();
+            let now = tracker.get_current_time();
+            to_aircraft_json(&tracker, &expire, now)
+        };
+        if path == Path::new("-") {
+            println!("{}", json);
+        } else {
+            write_atomically(&path, &json)?;
+        }
+        if last {
+            return Ok(());
+        }
+        thread::sleep(JSON_INTERVAL);
+    })
 }
 
-fn read_from_postgres(tracker: Arc<Mutex<Tracker>>) -> JoinHandle<Result<()>> {
+/// Record the frames received by the tracker to an SQLite database, creating its pings table if
+/// needed, one transaction per batch taken from the tracker
+#[cfg(feature = "sqlite")]
+fn record_to_sqlite(
+    tracker: Arc<Mutex<Tracker>>,
+    path: PathBuf,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
     thread::spawn(move || {
-        let mut client = Client::connect(
-            "host=storage.local port=54322 user=orbital password=orbital",
-            NoTls,
-        )?;
-        let mut trans = client.transaction().unwrap();
-        let portal = trans.bind(
-            "SELECT timestamp, data FROM pings order by timestamp asc",
-            &[],
-        )?;
+        let database =
+            FrameDatabase::create(&path).with_context(|| format!("opening {}", path.display()))?;
         loop {
-            let result = trans.query_portal(&portal, 10000)?;
-            for row in result {
-                let ping = Ping {
-                    timestamp: row.get(0),
-                    data: row.get(1),
-                };
-                let mut tracker = tracker.lock().unwrap();
-                let _ = tracker.update_with_binary(&ping.data, Utc::now());
+            let last = shutdown.is_requested();
+            let frames = tracker.lock().unwrap().take_frames();
+            if !frames.is_empty() {
+                database.record(&frames)?;
+            }
+            if last {
+                return Ok(());
             }
+            thread::sleep(REFRESH_INTERVAL);
         }
     })
 }
 
-fn read_from_network(
-    host: String,
-    port: u16,
+/// Periodically save the tracker's state to `path`, to be restored when the program next starts
+fn save_state(
     tracker: Arc<Mutex<Tracker>>,
+    path: PathBuf,
+    shutdown: Shutdown,
 ) -> JoinHandle<Result<()>> {
     thread::spawn(move || {
-        let stream = TcpStream::connect((host.as_str(), port))?;
-        let mut reader = BufReader::new(stream);
-        let mut input = String::new();
+        let mut last_saved = Instant::now();
         loop {
-            let _ = reader.read_line(&mut input)?;
-            let mut tracker = tracker.lock().unwrap();
-            let _ = tracker.update_with_avr(&input, Utc::now());
-            input.clear();
+            let last = shutdown.is_requested();
+            if last || last_saved.elapsed() >= STATE_INTERVAL {
+                // Written after releasing the lock, so decoding isn't held up by the disk
+                let contents = tracker.lock().unwrap().state_contents();
+                write_atomically(&path, &contents)
+                    .with_context(|| format!("saving state to {}", path.display()))?;
+                last_saved = Instant::now();
+            }
+            if last {
+                return Ok(());
+            }
+            thread::sleep(REFRESH_INTERVAL);
         }
     })
 }
 
-fn write_output(tracker: Arc<Mutex<Tracker>>, expire: Duration) -> JoinHandle<Result<()>> {
-    thread::spawn(move || loop {
-        thread::sleep(REFRESH_INTERVAL);
+/// Listen for TCP connections on `port` on all interfaces
+fn listen(port: u16) -> Result<TcpListener> {
+    TcpListener::bind(("0.0.0.0", port)).with_context(|| format!("listening on port {}", port))
+}
+
+/// Answer every HTTP request to `listener` with the tracker's metrics for Prometheus to scrape.
+/// A client which fails mid-request doesn't stop the server.
+#[cfg(feature = "metrics")]
+fn serve_metrics(tracker: Arc<Mutex<Tracker>>, expire: Duration, listener: TcpListener) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            // Only the request line is read, since the same page is served for any request
+            let mut request = String::new();
+            if BufReader::new(&stream).read_line(&mut request).is_err() {
+                continue;
+            }
+            let metrics = {
+                let tracker = tracker.lock().unwrap();
+                let now = tracker.get_current_time();
+                to_prometheus_metrics(&tracker, &expire, now)
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                metrics.len(),
+                metrics
+            );
+        }
+    });
+}
+
+/// Push the tracker's events to WebSocket clients connecting to `listener`, through its event
+/// callback. Each client is handled on its own thread, so a slow one doesn't hold up the others,
+/// and is dropped once `WEBSOCKET_QUEUE_SIZE` events are waiting to be sent to it.
+fn serve_websocket(tracker: Arc<Mutex<Tracker>>, expire: Duration, listener: TcpListener) {
+    let clients: Arc<Mutex<Vec<SyncSender<String>>>> = Arc::default();
+    let subscribers = clients.clone();
+    tracker.lock().unwrap().set_on_event(Box::new(move |event| {
+        let message = tracker_event_json(&event);
+        // Clients which have disconnected have dropped their receivers, and ones which have
+        // stopped reading are dropped so that their queues don't grow without bound
+        subscribers
+            .lock()
+            .unwrap()
+            .retain(|client| client.try_send(message.clone()).is_ok());
+    }));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let tracker = tracker.clone();
+            let clients = clients.clone();
+            thread::spawn(move || stream_events(stream, &tracker, expire, &clients));
+        }
+    });
+}
+
+/// Complete the WebSocket handshake with a client, then send it the current aircraft in the
+/// format of `aircraft.json` followed by each event, until it disconnects
+fn stream_events(
+    mut stream: TcpStream,
+    tracker: &Mutex<Tracker>,
+    expire: Duration,
+    clients: &Mutex<Vec<SyncSender<String>>>,
+) -> io::Result<()> {
+    let mut headers = String::new();
+    let mut reader = BufReader::new(&stream);
+    while reader.read_line(&mut headers)? > 0 && !headers.ends_with("\r\n\r\n") {}
+    let accept_key = match websocket_key(&headers) {
+        Some(key) => websocket_accept_key(key),
+        None => return write!(stream, "HTTP/1.0 400 Bad Request\r\n\r\n"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key
+    )?;
+    let (sender, receiver) = mpsc::sync_channel(WEBSOCKET_QUEUE_SIZE);
+    let snapshot = {
         let tracker = tracker.lock().unwrap();
-        // print_ascii_table(&tracker, &expire);
-        print_message_stats(&tracker);
+        // Subscribe while the tracker is locked, so the events start right after the snapshot
+        clients.lock().unwrap().push(sender);
+        let now = tracker.get_current_time();
+        to_aircraft_json(&tracker, &expire, now)
+    };
+    stream.write_all(&websocket_text_frame(&snapshot))?;
+    for message in receiver {
+        stream.write_all(&websocket_text_frame(&message))?;
+    }
+    Ok(())
+}
+
+/// Periodically append a summary of each aircraft removed from the tracker as a line of JSON to
+/// the file at `path`, or to stdout if it's `-`
+fn write_flight_summaries(
+    tracker: Arc<Mutex<Tracker>>,
+    path: PathBuf,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let mut output: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("opening {}", path.display()))?,
+            )
+        };
+        loop {
+            let last = shutdown.is_requested();
+            let summaries = tracker.lock().unwrap().take_flight_summaries();
+            for summary in &summaries {
+                writeln!(output, "{}", flight_summary_json(summary))?;
+            }
+            output.flush()?;
+            if last {
+                return Ok(());
+            }
+            thread::sleep(REFRESH_INTERVAL);
+        }
+    })
+}
+
+/// Write the positions decoded by the tracker as CSV, starting the file afresh with a header.
+/// Output is flushed after each batch so the file can be followed as it grows.
+fn write_positions_csv(
+    tracker: Arc<Mutex<Tracker>>,
+    path: PathBuf,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let mut output: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(
+                fs::File::create(&path).with_context(|| format!("creating {}", path.display()))?,
+            )
+        };
+        writeln!(output, "{}", POSITION_CSV_HEADER)?;
+        loop {
+            let last = shutdown.is_requested();
+            let positions = tracker.lock().unwrap().take_positions();
+            for position in &positions {
+                writeln!(output, "{}", position.to_csv())?;
+            }
+            output.flush()?;
+            if last {
+                return Ok(());
+            }
+            thread::sleep(REFRESH_INTERVAL);
+        }
     })
 }
 
+/// Print the positions decoded by the tracker to stdout as CSV in place of the display, for
+/// `--output csv`. Stale aircraft are removed and new ones notified of as by the display, and a
+/// progress line goes to stderr after every `CSV_PROGRESS_MESSAGES` messages, leaving stdout
+/// clean for piping.
+fn print_positions_csv(
+    tracker: Arc<Mutex<Tracker>>,
+    expire: Duration,
+    mut notifier: Option<Notifier>,
+    shutdown: Shutdown,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let stdout = io::stdout();
+        let mut output = stdout.lock();
+        writeln!(output, "{}", POSITION_CSV_HEADER)?;
+        let mut next_progress = CSV_PROGRESS_MESSAGES;
+        loop {
+            let last = shutdown.is_requested();
+            let positions = {
+                let mut tracker = tracker.lock().unwrap();
+                let now = tracker.get_current_time();
+                tracker.remove_stale(expire, now);
+                if let Some(notifier) = notifier.as_mut() {
+                    let acquisitions = tracker.take_acquisitions();
+                    notifier.notify(&tracker, acquisitions);
+                }
+                let messages = tracker.get_num_messages();
+                if messages >= next_progress {
+                    eprintln!(
+                        "{} messages ({} msg/s), {} positions, {} aircraft",
+                        messages,
+                        fmt_rate(tracker.get_messages_per_second_recent()),
+                        tracker.get_num_positions(),
+                        tracker.get_all_aircraft().len()
+                    );
+                    next_progress = (messages / CSV_PROGRESS_MESSAGES + 1) * CSV_PROGRESS_MESSAGES;
+                }
+                tracker.take_positions()
+            };
+            for position in &positions {
+                writeln!(output, "{}", position.to_csv())?;
+            }
+            output.flush()?;
+            if last {
+                return Ok(());
+            }
+            thread::sleep(REFRESH_INTERVAL);
+        }
+    })
+}
+
+/// Write to a temporary file and rename it so readers never see a partially written file
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
 fn fmt_value<T: fmt::Display>(value: Option<T>, precision: usize) -> String {
     value
         .map(|v| format!("{:.1$}", v, precision))
         .unwrap_or_else(|| NA.to_string())
 }
 
-fn print_ascii_table(tracker: &Tracker, expire: &Duration) {
-    let aircraft_list = tracker.get_current_aircraft(expire);
-    // Clear screen
-    print!("\x1B[2J\x1B[H");
+/// Format a rate, treating infinite and NaN values like a missing one so arithmetic edge cases
+/// don't show up as `inf` or `NaN`
+fn fmt_rate(rate: Option<f64>) -> String {
+    fmt_value(rate.filter(|r| r.is_finite()), 1)
+}
+
+/// Pick a stable color for an aircraft, derived from its ICAO address
+fn aircraft_color(icao_address: &ICAOAddress) -> u8 {
+    let address = icao_to_u32(icao_address);
+    AIRCRAFT_COLORS[address as usize % AIRCRAFT_COLORS.len()]
+}
+
+/// Set the foreground color of some text, leaving other attributes such as the background alone
+fn colorize(text: &str, color: u8) -> String {
+    format!("\x1B[{}m{}\x1B[39m", color, text)
+}
+
+/// Pick the highlight color for the first altitude band an aircraft is in, if any
+fn highlight_color(aircraft: &Aircraft, highlight: &[AltitudeBand]) -> Option<u8> {
+    let altitude = aircraft.altitude?;
+    highlight
+        .iter()
+        .position(|band| band.contains(altitude))
+        .map(|i| HIGHLIGHT_COLORS[i % HIGHLIGHT_COLORS.len()])
+}
+
+fn print_ascii_table(
+    tracker: &Tracker,
+    expire: &Duration,
+    now: chrono::DateTime<Utc>,
+    options: &DisplayOptions,
+) {
+    let mut aircraft_list = tracker.get_current_aircraft(expire, now);
+    let receiver = tracker.receiver_position();
+    sort_aircraft(&mut aircraft_list, receiver, options);
+    // Distance and bearing columns, only shown when the receiver's location is known
+    let range_header = match receiver {
+        Some(_) => format!(" {:>5} {:>3}", "dist", "brg"),
+        None => String::new(),
+    };
+    let (altitude_header, speed_header) = match options.units {
+        Units::Imperial => ("alt", "gs"),
+        Units::Metric => ("alt m", "km/h"),
+    };
     println!(
-        "{:>6} {:>10} {:>8} {:>6} {:>5} {:>8} {:>17} {:>5} {:>6} {:>10} {:>10}",
-        "icao", "call", "alt", "hdg", "gs", "vr", "lat/lon", "last",
+        "{:>6} {:>10} {:>8} {:>6} {:>5} {:>8} {:>4} {:>17}{} {:>5} {:>4} {:>5} {:<14} {:>3} {:<5} {:>6} {:>10} {:>10} {:>10}",
+        "icao", "call", altitude_header, "hdg", speed_header, "vr", "sqk", "lat/lon", range_header, "last", "view", "mps", "country", "flg", "label",
         aircraft_list.len(),
         tracker.get_num_messages(),
-        tracker.get_num_unknown_messages()
+        tracker.get_num_unknown_messages(),
+        tracker.get_num_positions()
     );
-    println!("{}", "-".repeat(72));
-    let now = Utc::now();
+    println!("{}", "-".repeat(113 + range_header.len()));
     for aircraft in aircraft_list {
-        println!(
-            "{:>6} {:>10} {:>8} {:>6} {:>5} {:>8} {:>8},{:>8} {:>5}",
+        let callsign = format!(
+            "{:>10}",
+            aircraft.callsign.clone().unwrap_or_else(|| NA.to_string())
+        );
+        let emergency = aircraft.emergency().is_some();
+        let range = match receiver {
+            Some(receiver) => format!(
+                " {:>5} {:>3}",
+                fmt_value(aircraft.distance_from(receiver), 1),
+                fmt_value(aircraft.bearing_from(receiver), 0)
+            ),
+            None => String::new(),
+        };
+        let row = format!(
+            "{:>6} {} {:>8} {:>6} {:>5} {:>8} {:>4} {:>8},{:>8}{} {:>5} {:>4} {:>5} {:<14.14} {:>3} {}",
             aircraft.icao_address,
-            aircraft.callsign.clone().unwrap_or_else(|| NA.to_string()),
-            fmt_value(aircraft.altitude, 0),
+            // An emergency row is all red, so the callsign isn't colored separately
+            if options.plain || emergency {
+                callsign
+            } else {
+                colorize(&callsign, aircraft_color(&aircraft.icao_address))
+            },
+            fmt_value(options.units.altitude(aircraft.altitude), 0),
             fmt_value(aircraft.heading, 0),
-            fmt_value(aircraft.ground_speed, 0),
-            fmt_value(aircraft.vertical_rate, 0),
+            fmt_speed(aircraft, options),
+            fmt_vertical_rate(aircraft),
+            fmt_value(aircraft.squawk, 0),
             fmt_value(aircraft.latitude, 4),
             fmt_value(aircraft.longitude, 4),
+            range,
+            now.signed_duration_since(aircraft.last_seen).num_seconds(),
+            // Time in view in minutes
+            aircraft.time_in_view().num_minutes(),
+            fmt_value(aircraft.messages_per_second(), 1),
+            aircraft.country.unwrap_or(NA),
+            flags(aircraft, options),
+            options.labels.get(&aircraft.icao_address).unwrap_or(NA)
+        );
+        match highlight_color(aircraft, &options.highlight) {
+            _ if emergency && !options.plain => println!("\x1B[1;31m{}\x1B[0m", row),
+            Some(color) if !options.plain => println!("\x1B[{}m{}\x1B[0m", color, row),
+            _ => println!("{}", row),
+        }
+    }
+}
+
+/// Print everything known about a single aircraft, one field per line
+fn print_aircraft_detail(
+    tracker: &Tracker,
+    icao_address: &ICAOAddress,
+    now: chrono::DateTime<Utc>,
+    options: &DisplayOptions,
+) {
+    let aircraft = match tracker.get_aircraft(icao_address) {
+        Some(aircraft) => aircraft,
+        None => {
+            println!("Aircraft {} is not being tracked", icao_address);
+            return;
+        }
+    };
+    let field = |name: &str, value: String| println!("{:<16} {}", name, value);
+    field("ICAO address", aircraft.icao_address.to_string());
+    if let Some(label) = options.labels.get(icao_address) {
+        field("Label", label.to_string());
+    }
+    // Not `fmt_value`, whose precision would cut strings short
+    let text = |value: &Option<String>| value.as_deref().unwrap_or(NA).to_string();
+    field("Callsign", text(&aircraft.callsign));
+    field("Registration", text(&aircraft.registration));
+    field("Type", text(&aircraft.aircraft_type));
+    field("Country", aircraft.country.unwrap_or(NA).to_string());
+    field("Category", fmt_value(aircraft.emitter_category, 0));
+    field("Squawk", fmt_value(aircraft.squawk, 0));
+    let units = options.units;
+    let altitude =
+        |feet: Option<i32>| fmt_with_unit(units.altitude(feet), 0, units.altitude_unit());
+    // The selected altitude goes alongside so the climb or descent still to go can be seen
+    let selected = match (aircraft.altitude, aircraft.selected_altitude) {
+        (Some(current), Some(selected)) => format!(
+            ", selected {} ({:+.0} {})",
+            altitude(Some(selected)),
+            units.altitude(Some(selected - current)).unwrap_or_default(),
+            units.altitude_unit()
+        ),
+        (None, Some(selected)) => format!(", selected {}", altitude(Some(selected))),
+        _ => String::new(),
+    };
+    field("Altitude", altitude(aircraft.altitude) + &selected);
+    field("GNSS altitude", altitude(aircraft.geometric_altitude));
+    let reference = match aircraft.heading_reference {
+        Some(HeadingReference::True) => " (true)",
+        Some(HeadingReference::Magnetic) => " (magnetic)",
+        None => "",
+    };
+    field("Heading", fmt_value(aircraft.heading, 0) + reference);
+    field(
+        "Ground speed",
+        fmt_with_unit(units.speed(aircraft.ground_speed), 0, units.speed_unit()),
+    );
+    let quality = match aircraft.position_quality() {
+        Some(PositionQuality::Consistent) => " (consistent)",
+        Some(PositionQuality::Inconsistent) => " (inconsistent, possibly a bad position)",
+        None => "",
+    };
+    field(
+        "Computed speed",
+        fmt_with_unit(
+            units.speed(aircraft.computed_ground_speed()),
+            0,
+            units.speed_unit(),
+        ) + quality,
+    );
+    let source = match aircraft.vertical_rate_source {
+        Some(VerticalRateSource::BarometricPressureAltitude) => " (barometric)",
+        Some(VerticalRateSource::GeometricAltitude) => " (GNSS)",
+        None => "",
+    };
+    field(
+        "Vertical rate",
+        fmt_with_unit(aircraft.vertical_rate, 0, "ft/min") + source,
+    );
+    let wind = match (aircraft.wind_direction, aircraft.wind_speed) {
+        (Some(direction), Some(speed)) => format!(
+            "{:03.0} at {}",
+            direction,
+            fmt_with_unit(units.speed(Some(speed)), 0, units.speed_unit())
+        ),
+        _ => NA.to_string(),
+    };
+    field("Wind", wind);
+    field(
+        "Temperature",
+        fmt_with_unit(aircraft.static_air_temperature, 1, "C"),
+    );
+    let position = match (aircraft.latitude, aircraft.longitude) {
+        (Some(latitude), Some(longitude)) => {
+            let age = aircraft
+                .track()
+                .last()
+                .map(|point| {
+                    format!(
+                        " ({} s ago)",
+                        now.signed_duration_since(point.time).num_seconds()
+                    )
+                })
+                .unwrap_or_default();
+            format!("{:.4}, {:.4}{}", latitude, longitude, age)
+        }
+        _ => NA.to_string(),
+    };
+    field("Position", position);
+    if let Some(receiver) = tracker.receiver_position() {
+        field(
+            "Distance",
+            fmt_with_unit(aircraft.distance_from(receiver), 1, "nm"),
+        );
+    }
+    let in_view = aircraft.time_in_view();
+    field(
+        "Time in view",
+        format!(
+            "{} min {} s",
+            in_view.num_minutes(),
+            in_view.num_seconds() % 60
+        ),
+    );
+    field(
+        "Last seen",
+        format!(
+            "{} s ago",
             now.signed_duration_since(aircraft.last_seen).num_seconds()
+        ),
+    );
+}
+
+/// Format an optional value followed by its unit
+fn fmt_with_unit<T: fmt::Display>(value: Option<T>, precision: usize, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{:.2$} {}", value, unit, precision),
+        None => NA.to_string(),
+    }
+}
+
+/// Sort the rows of the aircraft table, which otherwise come out in an arbitrary order that changes
+/// from one refresh to the next. Ties are broken by ICAO address to keep the order stable.
+fn sort_aircraft(
+    aircraft_list: &mut [&Aircraft],
+    receiver: Option<&Position>,
+    options: &DisplayOptions,
+) {
+    let reverse = options.reverse;
+    let distance = |aircraft: &Aircraft| receiver.and_then(|r| aircraft.distance_from(r));
+    aircraft_list.sort_by(|a, b| {
+        let ordering = match options.sort {
+            SortKey::Icao => Ordering::Equal,
+            SortKey::Callsign => compare_present(&a.callsign, &b.callsign, reverse),
+            SortKey::Altitude => compare_present(&a.altitude, &b.altitude, reverse),
+            SortKey::Distance => compare_present(&distance(a), &distance(b), reverse),
+            // The most recently seen come first, like the smallest values in the last column
+            SortKey::LastSeen => compare_present(&Some(b.last_seen), &Some(a.last_seen), reverse),
+        };
+        ordering.then_with(|| {
+            let ordering = icao_to_u32(&a.icao_address).cmp(&icao_to_u32(&b.icao_address));
+            if reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        })
+    });
+}
+
+/// Compare two values which may be missing, ordering missing values after present ones whichever
+/// direction the present values are sorted in
+fn compare_present<T: PartialOrd>(a: &Option<T>, b: &Option<T>, reverse: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let ordering = a.partial_cmp(b).unwrap_or(Ordering::Equal);
+            if reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Warn about aircraft using the same callsign at the same time
+fn print_callsign_conflicts(tracker: &Tracker, expire: &Duration, now: chrono::DateTime<Utc>) {
+    for conflict in tracker.get_callsign_conflicts(expire, now) {
+        println!(
+            "Warning: callsign {} used by {} at the same time",
+            conflict.callsign,
+            conflict.icao_addresses.iter().join(", ")
         );
     }
 }
 
-fn print_message_stats(tracker: &Tracker) {
-    // Clear screen
-    print!("\x1B[2J\x1B[H");
+/// Warn about aircraft closer to each other than the given separation
+fn print_proximity_warnings(
+    tracker: &Tracker,
+    expire: &Duration,
+    now: chrono::DateTime<Utc>,
+    separation: Separation,
+) {
+    let (horizontal, vertical) = (separation.horizontal, separation.vertical);
+    let events = tracker.proximity_events(horizontal, vertical, expire, now);
+    for (a, b, distance) in events {
+        println!("Warning: {} and {} within {:.1} nm", a, b, distance);
+    }
+}
+
+/// Format an aircraft's ground speed, or its Mach number if it's above the altitude given by
+/// `--mach-above`. A Mach number derived from ground speed is marked with `~`.
+fn fmt_speed(aircraft: &Aircraft, options: &DisplayOptions) -> String {
+    let is_high = match (aircraft.altitude, options.mach_above) {
+        (Some(altitude), Some(mach_above)) => i64::from(altitude) > i64::from(mach_above),
+        _ => false,
+    };
+    match aircraft.mach() {
+        Some(mach) if is_high => {
+            // Written like M.82 to fit the column, or ~M.82 if approximate
+            let prefix = if mach.approximate { "~" } else { "" };
+            let number = format!("{:.2}", mach.number);
+            format!("{}M{}", prefix, number.strip_prefix('0').unwrap_or(&number))
+        }
+        _ => fmt_value(options.units.speed(aircraft.ground_speed), 0),
+    }
+}
+
+/// Format an aircraft's vertical rate with its sign, e.g. +1200b, followed by b if it's the rate
+/// of change of barometric altitude or g if of GNSS altitude. A rate of zero shows as level.
+fn fmt_vertical_rate(aircraft: &Aircraft) -> String {
+    let source = match aircraft.vertical_rate_source {
+        Some(VerticalRateSource::BarometricPressureAltitude) => "b",
+        Some(VerticalRateSource::GeometricAltitude) => "g",
+        None => "",
+    };
+    match aircraft.vertical_rate {
+        Some(0) => "level".to_string(),
+        Some(rate) => format!("{:+}{}", rate, source),
+        None => NA.to_string(),
+    }
+}
+
+/// Single letter indicators for an aircraft: G = on the ground, O = orbiting, A = arriving at or
+/// D = departing from the home airport
+fn flags(aircraft: &Aircraft, options: &DisplayOptions) -> String {
+    let mut flags = String::new();
+    if aircraft.on_ground == Some(true) {
+        flags.push('G');
+    }
+    if aircraft.is_orbiting {
+        flags.push('O');
+    }
+    let movement = options
+        .home_airport
+        .as_ref()
+        .and_then(|airport| aircraft.movement(airport));
+    match movement {
+        Some(Movement::Arrival) => flags.push('A'),
+        Some(Movement::Departure) => flags.push('D'),
+        None => {}
+    }
+    flags
+}
+
+/// Show how far through a replay we are: a progress bar when the size of the dataset is known,
+/// otherwise the number of messages processed and the time elapsed.
+fn print_replay_progress(progress: &ReplayProgress) {
+    let message_time = progress
+        .message_time
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| NA.to_string());
+    match progress.total {
+        Some(total) if total > 0 => {
+            let fraction = (progress.processed as f64 / total as f64).min(1.0);
+            let filled = (fraction * PROGRESS_BAR_WIDTH as f64).round() as usize;
+            println!(
+                "[{}{}] {:>5.1}% {}",
+                "#".repeat(filled),
+                ".".repeat(PROGRESS_BAR_WIDTH - filled),
+                fraction * 100.0,
+                message_time
+            );
+        }
+        _ => {
+            let elapsed = Utc::now().signed_duration_since(progress.started);
+            println!(
+                "Replayed {} messages in {:02}:{:02}:{:02} {}",
+                progress.processed,
+                elapsed.num_hours(),
+                elapsed.num_minutes() % 60,
+                elapsed.num_seconds() % 60,
+                message_time
+            );
+        }
+    }
+}
+
+fn print_message_stats(
+    tracker: &Tracker,
+    expire: &Duration,
+    now: chrono::DateTime<Utc>,
+    options: &DisplayOptions,
+) {
+    println!(
+        "Messages: {}  Rate: {} msg/s (last 10s), {} msg/s (average)",
+        tracker.get_num_messages(),
+        fmt_rate(tracker.get_messages_per_second_recent()),
+        fmt_rate(tracker.get_messages_per_second_real_time())
+    );
+    let errors = tracker.get_error_statistics();
+    println!(
+        "Errors: {} bad CRC, {} invalid length, {} not AVR, {} unparseable, {} unsupported  Duplicate frames: {}",
+        errors.crc,
+        errors.invalid_length,
+        errors.invalid_avr,
+        errors.parser,
+        errors.unsupported,
+        tracker.get_num_duplicate_messages()
+    );
+    let positions = tracker.get_position_statistics();
+    println!(
+        "Positions: {} decoded ({} locally), {} failed, {} waiting for a pair, {} implausible",
+        positions.decoded, positions.local, positions.failed, positions.waiting, positions.rejected
+    );
+    if let Some((max_range, icao_address, time)) = tracker.get_max_range() {
+        println!(
+            "Max range: {:.1} nm ({} at {})",
+            max_range,
+            icao_address,
+            time.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+    }
+    let squawks = tracker.get_squawk_summary(expire, options.squawk_region, now);
+    println!(
+        "Squawks: {} VFR, {} discrete, {} unknown",
+        squawks.vfr, squawks.discrete, squawks.unknown
+    );
     println!("Unknown messages:");
     let counts = tracker.get_unknown_message_statistics();
     for df in counts.keys().sorted() {
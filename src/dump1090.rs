@@ -0,0 +1,307 @@
+use crate::modes::{icao_from_u32, Squawk};
+use crate::sbs::SbsMessage;
+use std::collections::HashMap;
+
+/// How deeply arrays and objects may be nested, so a malicious document can't overflow the stack
+const MAX_DEPTH: usize = 32;
+
+/// An aircraft in dump1090's `aircraft.json`, whose fields are already decoded. They're the
+/// ones also sent in SBS messages, so the tracker applies them the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Dump1090Aircraft {
+    pub fields: SbsMessage,
+    /// Whether `alt_baro` was given as `"ground"` rather than an altitude
+    pub on_ground: bool,
+    /// Seconds since a message was last received from the aircraft, as of the document's time
+    pub seen: Option<f64>,
+    /// Seconds since its position was last updated
+    pub seen_pos: Option<f64>,
+}
+
+/// Parse the `aircraft` array of an `aircraft.json` document such as
+/// `{"now":1604340192.3,"aircraft":[{"hex":"4840d6","flight":"KLM1023 ","alt_baro":38000}]}`.
+/// Entries without a valid ICAO address are skipped, including the non-ICAO addresses
+/// dump1090 marks with a `~`. Gives `None` if the document isn't valid JSON or has no array of
+/// aircraft.
+pub(crate) fn parse_aircraft_json(document: &str) -> Option<Vec<Dump1090Aircraft>> {
+    let mut parser = Parser {
+        input: document.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    if parser.pos != parser.input.len() {
+        return None;
+    }
+    let entries = match value {
+        Value::Object(mut fields) => match fields.remove("aircraft")? {
+            Value::Array(entries) => entries,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(entries.iter().filter_map(aircraft_from_json).collect())
+}
+
+fn aircraft_from_json(entry: &Value) -> Option<Dump1090Aircraft> {
+    let fields = match entry {
+        Value::Object(fields) => fields,
+        _ => return None,
+    };
+    let number = |name: &str| match fields.get(name) {
+        Some(Value::Number(n)) => Some(*n),
+        _ => None,
+    };
+    let string = |name: &str| match fields.get(name) {
+        Some(Value::String(s)) => Some(s.trim()).filter(|s| !s.is_empty()),
+        _ => None,
+    };
+    let hex = string("hex").filter(|h| h.len() == 6)?;
+    let icao_address = icao_from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+    let squawk = string("squawk").and_then(|s| {
+        let code = u16::from_str_radix(s, 8).ok()?;
+        (s.len() == 4).then_some(Squawk(code))
+    });
+    // Older versions of dump1090 give the barometric altitude as `altitude`
+    let altitude = number("alt_baro").or_else(|| number("altitude"));
+    let on_ground = string("alt_baro").or_else(|| string("altitude")) == Some("ground");
+    let (latitude, longitude) = match (number("lat"), number("lon")) {
+        (Some(lat), Some(lon)) => (Some(lat), Some(lon)),
+        _ => (None, None),
+    };
+    Some(Dump1090Aircraft {
+        fields: SbsMessage {
+            icao_address,
+            callsign: string("flight").map(str::to_string),
+            altitude: altitude.map(|a| a.round() as i32),
+            ground_speed: number("gs").or_else(|| number("speed")),
+            track: number("track"),
+            latitude,
+            longitude,
+            vertical_rate: number("baro_rate")
+                .or_else(|| number("vert_rate"))
+                .map(|v| v.round() as i16),
+            squawk,
+        },
+        on_ground,
+        seen: number("seen"),
+        seen_pos: number("seen_pos"),
+    })
+}
+
+/// A JSON value, keeping only what's needed to read `aircraft.json`
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(HashMap<String, Value>),
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// How many arrays and objects enclose the current position
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Option<Value> {
+        self.whitespace();
+        match *self.input.get(self.pos)? {
+            b'{' | b'[' => {
+                if self.depth == MAX_DEPTH {
+                    return None;
+                }
+                self.depth += 1;
+                let value = if self.input[self.pos] == b'{' {
+                    self.object()
+                } else {
+                    self.array()
+                };
+                self.depth -= 1;
+                value
+            }
+            b'"' => self.string().map(Value::String),
+            b't' => self.literal("true", Value::Bool(true)),
+            b'f' => self.literal("false", Value::Bool(false)),
+            b'n' => self.literal("null", Value::Null),
+            _ => self.number(),
+        }
+    }
+
+    fn object(&mut self) -> Option<Value> {
+        let mut fields = HashMap::new();
+        self.pos += 1;
+        self.whitespace();
+        if self.eat(b'}') {
+            return Some(Value::Object(fields));
+        }
+        loop {
+            self.whitespace();
+            let name = self.string()?;
+            self.whitespace();
+            if !self.eat(b':') {
+                return None;
+            }
+            fields.insert(name, self.value()?);
+            self.whitespace();
+            if self.eat(b'}') {
+                return Some(Value::Object(fields));
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Value> {
+        let mut values = Vec::new();
+        self.pos += 1;
+        self.whitespace();
+        if self.eat(b']') {
+            return Some(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.whitespace();
+            if self.eat(b']') {
+                return Some(Value::Array(values));
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.eat(b'"') {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self.input.get(self.pos)?;
+            self.pos += 1;
+            match byte {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => {
+                    let escaped = *self.input.get(self.pos)?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' | b'\\' | b'/' => escaped as char,
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        // Surrogate pairs aren't combined, as aircraft.json has no use for them
+                        b'u' => {
+                            let hex = self.input.get(self.pos..self.pos + 4)?;
+                            self.pos += 4;
+                            let code = u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16);
+                            std::char::from_u32(code.ok()?).unwrap_or('\u{fffd}')
+                        }
+                        _ => return None,
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<Value> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+        text.parse().ok().map(Value::Number)
+    }
+
+    fn literal(&mut self, text: &str, value: Value) -> Option<Value> {
+        if self.input[self.pos..].starts_with(text.as_bytes()) {
+            self.pos += text.len();
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.input.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_aircraft_json_entries() {
+        let document = r#"{ "now" : 1604340192.3, "messages" : 1234, "aircraft" : [
+            {"hex":"4840d6","flight":"KLM1023 ","alt_baro":38000,"gs":420.5,"track":92.1,
+             "lat":52.2572,"lon":3.91937,"squawk":"7700","baro_rate":-64,"seen_pos":0.4,
+             "seen":0.1,"mlat":[],"category":"A3","emergency":"none","rssi":-20.1},
+            {"hex":"a1b2c3","alt_baro":"ground","seen":12.5,"flight":"N12"},
+            {"hex":"~2b0001","alt_baro":1000},
+            {"hex":"xyz","alt_baro":1000}
+        ]}"#;
+        let aircraft = parse_aircraft_json(document).unwrap();
+        assert_eq!(aircraft.len(), 2);
+        let klm = &aircraft[0];
+        assert_eq!(klm.fields.icao_address, icao_from_u32(0x4840D6).unwrap());
+        assert_eq!(klm.fields.callsign.as_deref(), Some("KLM1023"));
+        assert_eq!(klm.fields.altitude, Some(38000));
+        assert_eq!(klm.fields.ground_speed, Some(420.5));
+        assert_eq!(klm.fields.track, Some(92.1));
+        assert_eq!(klm.fields.latitude, Some(52.2572));
+        assert_eq!(klm.fields.longitude, Some(3.91937));
+        assert_eq!(klm.fields.vertical_rate, Some(-64));
+        assert_eq!(klm.fields.squawk, Some(Squawk(0o7700)));
+        assert_eq!((klm.seen, klm.seen_pos), (Some(0.1), Some(0.4)));
+        assert!(!klm.on_ground);
+        let taxiing = &aircraft[1];
+        assert_eq!(taxiing.fields.altitude, None);
+        assert!(taxiing.on_ground);
+        assert_eq!(taxiing.fields.callsign.as_deref(), Some("N12"));
+        assert_eq!(taxiing.fields.latitude, None);
+        assert_eq!(parse_aircraft_json(r#"{"aircraft":[]}"#), Some(Vec::new()));
+        assert_eq!(parse_aircraft_json(r#"{"now":1}"#), None);
+        assert_eq!(
+            parse_aircraft_json(r#"{"aircraft":[{"hex":"4840d6"}"#),
+            None
+        );
+        assert_eq!(parse_aircraft_json("<html>Not found</html>"), None);
+    }
+
+    #[test]
+    fn parse_aircraft_json_nesting_limited() {
+        let nested = |depth: usize| {
+            format!(
+                r#"{{"aircraft":[{{"hex":"4840d6","extra":{}{}}}]}}"#,
+                "[".repeat(depth),
+                "]".repeat(depth)
+            )
+        };
+        assert_eq!(
+            parse_aircraft_json(&nested(MAX_DEPTH - 3)).unwrap().len(),
+            1
+        );
+        assert_eq!(parse_aircraft_json(&nested(MAX_DEPTH - 2)), None);
+        assert_eq!(parse_aircraft_json(&nested(100_000)), None);
+    }
+}
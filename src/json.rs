@@ -0,0 +1,437 @@
+use crate::modes::HeadingReference;
+use crate::tracker::{Aircraft, FlightSummary, Tracker, TrackerEvent};
+use adsb::VerticalRateSource;
+use chrono::{Duration, Utc};
+use std::fmt::Write;
+
+/// Build a JSON document in the format of dump1090's `aircraft.json` for the aircraft last seen
+/// in the given interval. This is also the format used by tar1090 for its `history_N.json` files.
+pub fn to_aircraft_json(
+    tracker: &Tracker,
+    interval: &Duration,
+    now: chrono::DateTime<Utc>,
+) -> String {
+    let aircraft = tracker
+        .iter_current_aircraft(*interval, now)
+        .map(|a| AircraftJson::new(a, now).to_json())
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{\"now\":{:.1},\"messages\":{},\"aircraft\":[{}]}}",
+        timestamp(now),
+        tracker.get_num_messages(),
+        aircraft
+    )
+}
+
+/// Build a GeoJSON `FeatureCollection` of the aircraft last seen in the given interval, for
+/// plotting on a map. Each aircraft with a position is a `Point` feature, with the same
+/// properties as in `aircraft.json` apart from the position. Aircraft without a position are
+/// left out.
+pub fn to_geojson(tracker: &Tracker, interval: &Duration, now: chrono::DateTime<Utc>) -> String {
+    let features = tracker
+        .iter_current_aircraft(*interval, now)
+        .filter_map(|a| {
+            let mut properties = AircraftJson::new(a, now);
+            let (lat, lon) = (properties.lat.take()?, properties.lon.take()?);
+            let geometry = format!(
+                "{{\"type\":\"Point\",\"coordinates\":[{:.6},{:.6}]}}",
+                lon, lat
+            );
+            Some(format!(
+                "{{\"type\":\"Feature\",\"geometry\":{},\"properties\":{}}}",
+                geometry,
+                properties.to_json()
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{\"type\":\"FeatureCollection\",\"features\":[{}]}}",
+        features
+    )
+}
+
+/// Build a JSON object recording a single flight, for archiving aircraft once they're removed
+/// from the tracker. Times are in seconds since the Unix epoch and each track point is an array
+/// of `[time, lat, lon, alt_baro]`, where the altitude may be `null`. Aircraft which were never
+/// identified or located still get a record, with empty `flights` and `track` arrays.
+pub fn flight_summary_json(summary: &FlightSummary) -> String {
+    let track = summary
+        .track
+        .iter()
+        .map(|point| {
+            format!(
+                "[{:.1},{:.6},{:.6},{}]",
+                timestamp(point.time),
+                point.latitude,
+                point.longitude,
+                point
+                    .altitude
+                    .map_or_else(|| "null".to_string(), |a| a.to_string())
+            )
+        })
+        .collect();
+    let hex = summary.icao_address.to_string().to_lowercase();
+    let flights = summary.callsigns.iter().map(|c| quote(c)).collect();
+    let mut object = JsonObject::default();
+    object.string("hex", Some(&hex));
+    object.array("flights", flights);
+    object.number("first_seen", Some(timestamp(summary.first_seen)), 1);
+    object.number("last_seen", Some(timestamp(summary.last_seen)), 1);
+    object.number("max_alt_baro", summary.max_altitude, 0);
+    object.number("max_gs", summary.max_ground_speed, 1);
+    object.array("track", track);
+    object.finish()
+}
+
+/// Build a JSON object describing a tracker event, for pushing to clients as it happens. The
+/// `type` is one of `new`, `position`, `callsign` and `expired`, `hex` is the aircraft's address
+/// as in `aircraft.json` and `now` the time of the event in seconds since the Unix epoch.
+/// Positions and callsigns are given by the same fields as in `aircraft.json`.
+pub fn tracker_event_json(event: &TrackerEvent) -> String {
+    let mut object = JsonObject::default();
+    let mut header = |kind: &str, icao_address: &adsb::ICAOAddress, time| {
+        object.string("type", Some(kind));
+        object.string("hex", Some(&icao_address.to_string().to_lowercase()));
+        object.number("now", Some(timestamp(time)), 1);
+    };
+    match event {
+        TrackerEvent::NewAircraft { icao_address, time } => header("new", icao_address, *time),
+        TrackerEvent::PositionUpdate(position) => {
+            header("position", &position.icao_address, position.time);
+            object.number("lat", Some(position.latitude), 6);
+            object.number("lon", Some(position.longitude), 6);
+            object.number("alt_baro", position.altitude, 0);
+        }
+        TrackerEvent::CallsignUpdate {
+            icao_address,
+            callsign,
+            time,
+        } => {
+            header("callsign", icao_address, *time);
+            object.string("flight", Some(&format!("{:<8}", callsign)));
+        }
+        TrackerEvent::Expired {
+            icao_address,
+            last_seen,
+        } => header("expired", icao_address, *last_seen),
+    }
+    object.finish()
+}
+
+/// Seconds since the Unix epoch
+fn timestamp(time: chrono::DateTime<Utc>) -> f64 {
+    time.timestamp_millis() as f64 / 1000.0
+}
+
+/// JSON representation of an aircraft in the format of dump1090's `aircraft.json`. This is the
+/// single definition of the wire format: all JSON output goes through it. Fields which are
+/// `None` are left out of the JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AircraftJson {
+    /// ICAO address as six lowercase hex digits
+    pub hex: String,
+    /// Callsign, padded with spaces to 8 characters
+    pub flight: Option<String>,
+    /// Registration
+    pub r: Option<String>,
+    /// ICAO type designator
+    pub t: Option<String>,
+    /// Barometric altitude (feet)
+    pub alt_baro: Option<i32>,
+    /// Geometric (GNSS) altitude (feet)
+    pub alt_geom: Option<i32>,
+    /// Ground speed (knots)
+    pub gs: Option<f64>,
+    /// True airspeed (knots)
+    pub tas: Option<f64>,
+    /// Ground track, relative to true north (degrees)
+    pub track: Option<f64>,
+    /// Heading, relative to magnetic north (degrees)
+    pub mag_heading: Option<f64>,
+    /// Rate of change of barometric altitude (feet per minute)
+    pub baro_rate: Option<i16>,
+    /// Rate of change of geometric altitude (feet per minute)
+    pub geom_rate: Option<i16>,
+    /// Squawk as four octal digits
+    pub squawk: Option<String>,
+    /// Barometric pressure setting (hPa)
+    pub nav_qnh: Option<f64>,
+    /// Heading selected on the autopilot (degrees)
+    pub nav_heading: Option<f64>,
+    /// Wind speed (knots)
+    pub ws: Option<f64>,
+    /// Direction the wind is blowing from (degrees)
+    pub wd: Option<f64>,
+    /// Outside (static) air temperature (degrees Celsius)
+    pub oat: Option<f64>,
+    /// Latitude (degrees)
+    pub lat: Option<f64>,
+    /// Longitude (degrees)
+    pub lon: Option<f64>,
+    /// Time since the last message (seconds)
+    pub seen: f64,
+}
+
+impl AircraftJson {
+    pub fn new(aircraft: &Aircraft, now: chrono::DateTime<Utc>) -> Self {
+        let (track, mag_heading) = match aircraft.heading_reference {
+            Some(HeadingReference::Magnetic) => (None, aircraft.heading),
+            _ => (aircraft.heading, None),
+        };
+        let (baro_rate, geom_rate) = match aircraft.vertical_rate_source {
+            Some(VerticalRateSource::GeometricAltitude) => (None, aircraft.vertical_rate),
+            _ => (aircraft.vertical_rate, None),
+        };
+        let (lat, lon) = match (aircraft.latitude, aircraft.longitude) {
+            (Some(latitude), Some(longitude)) => (Some(latitude), Some(longitude)),
+            _ => (None, None),
+        };
+        let seen = now.signed_duration_since(aircraft.last_seen);
+        AircraftJson {
+            hex: aircraft.icao_address.to_string().to_lowercase(),
+            flight: aircraft.callsign.as_ref().map(|c| format!("{:<8}", c)),
+            r: aircraft.registration.clone(),
+            t: aircraft.aircraft_type.clone(),
+            alt_baro: aircraft.altitude,
+            alt_geom: aircraft.geometric_altitude,
+            gs: aircraft.ground_speed,
+            tas: aircraft.true_airspeed,
+            track,
+            mag_heading,
+            baro_rate,
+            geom_rate,
+            squawk: aircraft.squawk.map(|s| s.to_string()),
+            nav_qnh: aircraft.pressure_setting,
+            nav_heading: aircraft.selected_heading,
+            ws: aircraft.wind_speed,
+            wd: aircraft.wind_direction,
+            oat: aircraft.static_air_temperature,
+            lat,
+            lon,
+            seen: seen.num_milliseconds().max(0) as f64 / 1000.0,
+        }
+    }
+
+    /// Serialize to a JSON object
+    pub fn to_json(&self) -> String {
+        let mut object = JsonObject::default();
+        object.string("hex", Some(&self.hex));
+        object.string("flight", self.flight.as_deref());
+        object.string("r", self.r.as_deref());
+        object.string("t", self.t.as_deref());
+        object.number("alt_baro", self.alt_baro, 0);
+        object.number("alt_geom", self.alt_geom, 0);
+        object.number("gs", self.gs, 1);
+        object.number("tas", self.tas, 0);
+        object.number("track", self.track, 1);
+        object.number("mag_heading", self.mag_heading, 1);
+        object.number("baro_rate", self.baro_rate, 0);
+        object.number("geom_rate", self.geom_rate, 0);
+        object.string("squawk", self.squawk.as_deref());
+        object.number("nav_qnh", self.nav_qnh, 1);
+        object.number("nav_heading", self.nav_heading, 1);
+        object.number("ws", self.ws, 0);
+        object.number("wd", self.wd, 0);
+        object.number("oat", self.oat, 1);
+        object.number("lat", self.lat, 6);
+        object.number("lon", self.lon, 6);
+        object.number("seen", Some(self.seen), 1);
+        object.finish()
+    }
+}
+
+/// Builds a JSON object field by field, leaving out missing values
+#[derive(Default)]
+struct JsonObject {
+    fields: Vec<String>,
+}
+
+impl JsonObject {
+    fn string(&mut self, key: &str, value: Option<&str>) {
+        if let Some(value) = value {
+            self.fields.push(format!("{}:{}", quote(key), quote(value)));
+        }
+    }
+
+    fn number<T: Into<f64>>(&mut self, key: &str, value: Option<T>, precision: usize) {
+        if let Some(value) = value {
+            let value = value.into();
+            if value.is_finite() {
+                self.fields
+                    .push(format!("{}:{:.*}", quote(key), precision, value));
+            }
+        }
+    }
+
+    fn array(&mut self, key: &str, items: Vec<String>) {
+        self.fields
+            .push(format!("{}:[{}]", quote(key), items.join(",")));
+    }
+
+    fn finish(self) -> String {
+        format!("{{{}}}", self.fields.join(","))
+    }
+}
+
+/// Quote and escape a string for inclusion in a JSON document
+pub(crate) fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::{PositionRecord, TrackPoint};
+    use chrono::TimeZone;
+
+    #[test]
+    fn aircraft_json_leaves_out_missing_fields() {
+        let mut aircraft = AircraftJson {
+            hex: "4840d6".to_string(),
+            flight: Some("KLM1023 ".to_string()),
+            r: None,
+            t: None,
+            alt_baro: Some(38000),
+            alt_geom: None,
+            gs: None,
+            tas: None,
+            track: Some(90.25),
+            mag_heading: None,
+            baro_rate: Some(-64),
+            geom_rate: None,
+            squawk: None,
+            nav_qnh: None,
+            nav_heading: None,
+            ws: None,
+            wd: None,
+            oat: None,
+            lat: Some(52.1),
+            lon: Some(4.5),
+            seen: 1.25,
+        };
+        assert_eq!(
+            aircraft.to_json(),
+            "{\"hex\":\"4840d6\",\"flight\":\"KLM1023 \",\"alt_baro\":38000,\"track\":90.2,\
+             \"baro_rate\":-64,\"lat\":52.100000,\"lon\":4.500000,\"seen\":1.2}"
+        );
+        aircraft.gs = Some(f64::NAN);
+        aircraft.flight = Some("\"Q\"".to_string());
+        assert!(aircraft.to_json().contains("\"flight\":\"\\\"Q\\\"\","));
+        assert!(!aircraft.to_json().contains("gs"));
+    }
+
+    #[test]
+    fn aircraft_json_from_tracker() {
+        let mut tracker = Tracker::new();
+        let time = Utc::now();
+        // The callsign is only taken once it has been received twice
+        for _ in 0..2 {
+            tracker
+                .update_with_avr("*8D4840D6202CC371C32CE0576098;", time)
+                .unwrap();
+        }
+        let later = time + Duration::milliseconds(2500);
+        tracker
+            .update_with_avr("*8D485020994409940838175B284F;", later)
+            .unwrap();
+        let now = tracker.get_last_message_time().unwrap();
+        assert_eq!(now, later);
+        let json = to_aircraft_json(&tracker, &Duration::minutes(1), now);
+        assert!(json.contains("{\"hex\":\"4840d6\",\"flight\":\"KLM1023 \",\"seen\":2.5}"));
+        assert!(json.contains("{\"hex\":\"485020\",\"gs\":159.2,\"track\":182.9,"));
+        assert!(json.contains("\"seen\":0.0}"));
+    }
+
+    #[test]
+    fn geojson_leaves_out_aircraft_without_position() {
+        let mut tracker = Tracker::new();
+        let time = Utc::now();
+        for frame in &[
+            "*8D40621D58C382D690C8AC2863A7;",
+            "*8D40621D58C386435CC412692AD6;",
+            "*8D485020994409940838175B284F;",
+        ] {
+            tracker.update_with_avr(frame, time).unwrap();
+        }
+        let geojson = to_geojson(&tracker, &Duration::minutes(1), time);
+        assert!(geojson.starts_with("{\"type\":\"FeatureCollection\",\"features\":[{"));
+        assert_eq!(geojson.matches("\"type\":\"Feature\"").count(), 1);
+        assert!(geojson.contains(
+            "\"geometry\":{\"type\":\"Point\",\"coordinates\":[3.938913,52.265780]},\
+             \"properties\":{\"hex\":\"40621d\",\"alt_baro\":38000,\"seen\":0.0}}"
+        ));
+        assert!(!geojson.contains("485020"));
+        let empty = to_geojson(&Tracker::new(), &Duration::minutes(1), time);
+        assert_eq!(empty, "{\"type\":\"FeatureCollection\",\"features\":[]}");
+    }
+
+    #[test]
+    fn flight_summary_json_includes_sparse_records() {
+        let time = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let mut summary = FlightSummary {
+            icao_address: crate::modes::icao_from_u32(0x4840D6).unwrap(),
+            callsigns: vec![],
+            max_altitude: None,
+            max_ground_speed: None,
+            first_seen: time,
+            last_seen: time,
+            track: vec![],
+        };
+        assert_eq!(
+            flight_summary_json(&summary),
+            "{\"hex\":\"4840d6\",\"flights\":[],\"first_seen\":1600000000.0,\
+             \"last_seen\":1600000000.0,\"track\":[]}"
+        );
+        summary.callsigns = vec!["KLM1023".to_string(), "KLM1024".to_string()];
+        summary.max_altitude = Some(38000);
+        summary.track = vec![TrackPoint {
+            time: time + Duration::seconds(30),
+            latitude: 52.25,
+            longitude: 3.9,
+            altitude: None,
+        }];
+        let json = flight_summary_json(&summary);
+        assert!(json.contains("\"flights\":[\"KLM1023\",\"KLM1024\"]"));
+        assert!(json.contains("\"max_alt_baro\":38000"));
+        assert!(json.contains("\"track\":[[1600000030.0,52.250000,3.900000,null]]"));
+    }
+
+    #[test]
+    fn tracker_events_as_json() {
+        let time = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let icao_address = crate::modes::icao_from_u32(0x4840D6).unwrap();
+        let event = TrackerEvent::CallsignUpdate {
+            icao_address,
+            callsign: "KLM1023".to_string(),
+            time,
+        };
+        assert_eq!(
+            tracker_event_json(&event),
+            "{\"type\":\"callsign\",\"hex\":\"4840d6\",\"now\":1600000000.0,\"flight\":\"KLM1023 \"}"
+        );
+        let event = TrackerEvent::PositionUpdate(PositionRecord {
+            time,
+            icao_address,
+            latitude: 52.25,
+            longitude: 3.9,
+            altitude: Some(38000),
+        });
+        let json = tracker_event_json(&event);
+        assert!(json.ends_with("\"lat\":52.250000,\"lon\":3.900000,\"alt_baro\":38000}"));
+    }
+}
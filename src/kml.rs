@@ -0,0 +1,103 @@
+use crate::modes::METERS_PER_FOOT;
+use crate::tracker::Aircraft;
+
+/// Build a KML document with the aircraft's track as a `LineString` in a placemark named after
+/// its callsign, or its ICAO address if it has none, for opening in Google Earth. Altitudes are
+/// absolute, in meters. A point received without an altitude takes the one before it. Gives
+/// `None` unless the track has at least two points.
+pub fn aircraft_to_kml(aircraft: &Aircraft) -> Option<String> {
+    let track = aircraft.track();
+    if track.len() < 2 {
+        return None;
+    }
+    let mut altitude = 0;
+    let coordinates: Vec<String> = track
+        .iter()
+        .map(|point| {
+            altitude = point.altitude.unwrap_or(altitude);
+            format!(
+                "{:.6},{:.6},{:.0}",
+                point.longitude,
+                point.latitude,
+                f64::from(altitude) * METERS_PER_FOOT
+            )
+        })
+        .collect();
+    let name = match &aircraft.callsign {
+        Some(callsign) => callsign.clone(),
+        None => aircraft.icao_address.to_string(),
+    };
+    Some(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <kml xmlns=\"http://www.opengis.net/kml/2.2\">\n\
+         <Document>\n\
+         <Placemark>\n\
+         <name>{}</name>\n\
+         <LineString>\n\
+         <altitudeMode>absolute</altitudeMode>\n\
+         <coordinates>{}</coordinates>\n\
+         </LineString>\n\
+         </Placemark>\n\
+         </Document>\n\
+         </kml>\n",
+        escape(&name),
+        coordinates.join(" ")
+    ))
+}
+
+/// Escape the characters with a special meaning in XML text
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::Tracker;
+    use chrono::Utc;
+
+    const POSITION_EVEN: &str = "*8D40621D58C382D690C8AC2863A7;";
+    const POSITION_ODD: &str = "*8D40621D58C386435CC412692AD6;";
+
+    /// Check that every tag is closed in the order it was opened
+    fn assert_well_formed(xml: &str) {
+        let mut open = Vec::new();
+        for tag in xml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').unwrap()];
+            if tag.starts_with('?') {
+                continue;
+            }
+            match tag.strip_prefix('/') {
+                Some(name) => assert_eq!(open.pop(), Some(name)),
+                None => open.push(tag.split_whitespace().next().unwrap()),
+            }
+        }
+        assert!(open.is_empty());
+    }
+
+    #[test]
+    fn track_as_kml_line_string() {
+        let mut tracker = Tracker::new();
+        let now = Utc::now();
+        for frame in &[POSITION_EVEN, POSITION_ODD] {
+            tracker.update_with_avr(frame, now).unwrap();
+        }
+        let icao_address = crate::modes::icao_from_u32(0x40621D).unwrap();
+        // A single position isn't a line
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft_to_kml(aircraft), None);
+        tracker.update_with_avr(POSITION_EVEN, now).unwrap();
+        let kml = tracker.aircraft_to_kml(&icao_address).unwrap();
+        assert_well_formed(&kml);
+        assert!(kml.contains("<name>40621D</name>"));
+        assert!(kml.contains("<altitudeMode>absolute</altitudeMode>"));
+        let start = kml.find("<coordinates>").unwrap() + "<coordinates>".len();
+        let end = kml.find("</coordinates>").unwrap();
+        let tuples: Vec<&str> = kml[start..end].split(' ').collect();
+        assert_eq!(tuples.len(), 2);
+        assert!(tuples[0].starts_with("3.93891"));
+        assert!(tuples[0].ends_with(",11582"));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::modes::icao_from_u32;
+use adsb::ICAOAddress;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Free-text labels for aircraft of interest, e.g. "Police Heli", keyed by ICAO address
+#[derive(Debug, Clone, Default)]
+pub struct Labels {
+    map: HashMap<ICAOAddress, String>,
+}
+
+impl Labels {
+    /// Load labels from a file in the format read by `Labels::parse`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Labels::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parse lines of the form `icao,label`, e.g. `A1B2C3,News Chopper`. The label is everything
+    /// after the first comma and may be quoted. Blank lines, lines starting with `#` and lines
+    /// without a valid address (such as a header) are skipped.
+    pub fn parse(text: &str) -> Self {
+        let map = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (icao, label) = line.split_once(',')?;
+                let icao = icao.trim();
+                if icao.len() != 6 {
+                    return None;
+                }
+                let icao_address = icao_from_u32(u32::from_str_radix(icao, 16).ok()?)?;
+                let label = label.trim();
+                let label = label
+                    .strip_prefix('"')
+                    .and_then(|l| l.strip_suffix('"'))
+                    .unwrap_or(label);
+                Some((icao_address, label.to_string()))
+            })
+            .collect();
+        Labels { map }
+    }
+
+    /// Get the label for an aircraft, if there is one
+    pub fn get(&self, icao_address: &ICAOAddress) -> Option<&str> {
+        self.map.get(icao_address).map(String::as_str)
+    }
+
+    /// Number of aircraft with a label
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether no aircraft has a label
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_labels() {
+        let labels = Labels::parse(
+            "icao,label\n# Helicopters\na1b2c3,Police Heli\n\nABCDEF, \"News, Chopper\" \nXYZ,Bad\n",
+        );
+        assert_eq!(labels.len(), 2);
+        let icao = icao_from_u32(0xA1B2C3).unwrap();
+        assert_eq!(labels.get(&icao), Some("Police Heli"));
+        let icao = icao_from_u32(0xABCDEF).unwrap();
+        assert_eq!(labels.get(&icao), Some("News, Chopper"));
+        let icao = icao_from_u32(0x123456).unwrap();
+        assert_eq!(labels.get(&icao), None);
+    }
+}
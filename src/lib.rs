@@ -1,5 +1,35 @@
 //! Track aircraft using ADSB.
 
+mod beast;
+mod country;
+mod dump1090;
+mod json;
+mod kml;
+mod labels;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod modes;
+mod pipeline;
+mod registry;
+mod sbs;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod timestamp;
 mod tracker;
+mod websocket;
 
+pub use beast::*;
+pub use country::*;
+pub use json::*;
+pub use kml::*;
+pub use labels::*;
+#[cfg(feature = "metrics")]
+pub use metrics::*;
+pub use modes::*;
+pub use pipeline::*;
+pub use registry::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{FrameDatabase, SqliteError};
+pub use timestamp::*;
 pub use tracker::*;
+pub use websocket::*;
@@ -0,0 +1,86 @@
+use crate::tracker::Tracker;
+use chrono::{Duration, Utc};
+use std::fmt::Write;
+
+/// Build a page of metrics in the Prometheus text exposition format, counting the aircraft last
+/// seen in the given interval as tracked. The message rate is left out until messages have been
+/// received.
+pub fn to_prometheus_metrics(
+    tracker: &Tracker,
+    interval: &Duration,
+    now: chrono::DateTime<Utc>,
+) -> String {
+    let mut metrics = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        let _ = write!(
+            metrics,
+            "# HELP flight_tracker_{0} {1}\n# TYPE flight_tracker_{0} {2}\nflight_tracker_{0} {3}\n",
+            name, help, kind, value
+        );
+    };
+    let num_aircraft = tracker.iter_current_aircraft(*interval, now).count();
+    metric(
+        "messages_total",
+        "counter",
+        "Messages received",
+        tracker.get_num_messages() as f64,
+    );
+    metric(
+        "unknown_messages_total",
+        "counter",
+        "Messages received which couldn't be decoded",
+        tracker.get_num_unknown_messages() as f64,
+    );
+    metric(
+        "positions_total",
+        "counter",
+        "Positions decoded",
+        tracker.get_num_positions() as f64,
+    );
+    metric(
+        "aircraft",
+        "gauge",
+        "Aircraft currently tracked",
+        num_aircraft as f64,
+    );
+    if let Some(rate) = tracker.get_messages_per_second_recent() {
+        metric(
+            "messages_per_second",
+            "gauge",
+            "Messages received per second over the last few seconds",
+            rate,
+        );
+    }
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_from_tracker() {
+        let mut tracker = Tracker::new();
+        let empty = to_prometheus_metrics(&tracker, &Duration::minutes(1), Utc::now());
+        assert!(empty.contains("\nflight_tracker_messages_total 0\n"));
+        assert!(!empty.contains("messages_per_second"));
+        for frame in &[
+            "*8D4840D6202CC371C32CE0576098;",
+            "*8D40621D58C382D690C8AC2863A7;",
+            "*8D40621D58C386435CC412692AD6;",
+            "*02E197B00179C3;",
+        ] {
+            let _ = tracker.update_with_avr(frame, Utc::now());
+        }
+        let metrics = to_prometheus_metrics(&tracker, &Duration::minutes(1), Utc::now());
+        assert!(metrics.contains(
+            "# HELP flight_tracker_messages_total Messages received\n\
+             # TYPE flight_tracker_messages_total counter\n\
+             flight_tracker_messages_total 4\n"
+        ));
+        assert!(metrics.contains("\nflight_tracker_unknown_messages_total 1\n"));
+        assert!(metrics.contains("\nflight_tracker_positions_total 1\n"));
+        assert!(metrics.contains("\nflight_tracker_aircraft 2\n"));
+        assert!(metrics.contains("# TYPE flight_tracker_messages_per_second gauge\n"));
+    }
+}
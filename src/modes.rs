@@ -0,0 +1,924 @@
+//! Decoding of the parts of Mode-S replies which aren't handled by the `adsb` crate.
+
+use adsb::{CPRFrame, ICAOAddress, Parity, Position};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// Mode-S CRC generator polynomial
+const CRC_GENERATOR: u32 = 0x1FF_F409;
+/// Number of latitude zones between the equator and a pole in CPR encoding
+const CPR_NZ: f64 = 15.0;
+/// Range of the 17-bit CPR coordinates
+const CPR_MAX: f64 = 131_072.0;
+/// Size of the area an airborne position is encoded within (degrees)
+const AIRBORNE_CPR_ZONE: f64 = 360.0;
+/// Size of the area a surface position is encoded within, a quarter of that of an airborne one
+/// (degrees)
+const SURFACE_CPR_ZONE: f64 = 90.0;
+/// Meters in a foot, the international foot being defined as exactly 0.3048 meters
+pub const METERS_PER_FOOT: f64 = 0.3048;
+
+/// Compute the Mode-S CRC-24 of some data
+pub(crate) fn crc(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0;
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= CRC_GENERATOR;
+            }
+        }
+    }
+    crc & 0xFF_FFFF
+}
+
+/// Recover the address of the aircraft which sent a reply with an address/parity (AP) field,
+/// which is the address overlaid on the CRC of the rest of the frame. A corrupted frame yields a
+/// wrong address, so the result should only be trusted if it matches a known aircraft.
+pub(crate) fn address_from_parity(frame: &[u8]) -> Option<u32> {
+    if frame.len() < 4 {
+        return None;
+    }
+    let (data, parity) = frame.split_at(frame.len() - 3);
+    let parity = u32::from(parity[0]) << 16 | u32::from(parity[1]) << 8 | u32::from(parity[2]);
+    Some(parity ^ crc(data))
+}
+
+/// Check the parity of an extended squitter (DF 17 or 18), which is the CRC of the rest of the
+/// frame with nothing overlaid on it. Frames of other downlink formats pass unchecked, since
+/// their parity hides an address or interrogator code.
+pub(crate) fn parity_matches(frame: &[u8]) -> bool {
+    match frame.first().map(|byte| byte >> 3) {
+        Some(17) | Some(18) => address_from_parity(frame) == Some(0),
+        _ => true,
+    }
+}
+
+/// Get the address from a reply which carries it in the clear, such as an extended squitter
+pub(crate) fn address_from_frame(frame: &[u8]) -> u32 {
+    frame[1..4]
+        .iter()
+        .fold(0, |address, &byte| address << 8 | u32::from(byte))
+}
+
+/// Get the numeric value of an ICAO address
+pub fn icao_to_u32(icao_address: &ICAOAddress) -> u32 {
+    u32::from_str_radix(&icao_address.to_string(), 16).unwrap_or(0)
+}
+
+/// Build an ICAO address from its numeric value. The `adsb` crate doesn't expose a constructor,
+/// so this goes through its parser with a minimal identification message for the address.
+pub fn icao_from_u32(address: u32) -> Option<ICAOAddress> {
+    let mut frame = [0u8; 14];
+    frame[0] = 0x8D;
+    frame[1] = (address >> 16) as u8;
+    frame[2] = (address >> 8) as u8;
+    frame[3] = address as u8;
+    frame[4] = 0x20;
+    match adsb::parse_binary(&frame) {
+        Ok((
+            adsb::Message {
+                kind: adsb::MessageKind::ADSBMessage { icao_address, .. },
+                ..
+            },
+            _,
+        )) => Some(icao_address),
+        _ => None,
+    }
+}
+
+/// Mode A code set by the pilot, as four octal digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Squawk(pub u16);
+
+impl Squawk {
+    /// Decode the 13-bit identity (ID) field of a surveillance or Comm-B identity reply, in
+    /// which the bits of the four digits are interleaved as C1 A1 C2 A2 C4 A4 X B1 D1 B2 D2 B4 D4
+    pub(crate) fn from_identity(id: u16) -> Self {
+        let bit = |n: u16| (id >> n) & 1;
+        let a = bit(7) << 2 | bit(9) << 1 | bit(11);
+        let b = bit(1) << 2 | bit(3) << 1 | bit(5);
+        let c = bit(8) << 2 | bit(10) << 1 | bit(12);
+        let d = bit(0) << 2 | bit(2) << 1 | bit(4);
+        Squawk(a << 9 | b << 6 | c << 3 | d)
+    }
+
+    /// Classify the code according to the VFR conventions of a region
+    pub fn classify(&self, region: SquawkRegion) -> SquawkClass {
+        match (self.0, region) {
+            (0o1200, SquawkRegion::Us) | (0o7000, SquawkRegion::Europe) => SquawkClass::Vfr,
+            // Unassigned, Mode S conspicuity, entering SSR airspace, emergencies and interception
+            (0o0000, _)
+            | (0o1000, _)
+            | (0o2000, _)
+            | (0o7500, _)
+            | (0o7600, _)
+            | (0o7700, _)
+            | (0o7777, _) => SquawkClass::Special,
+            _ => SquawkClass::Discrete,
+        }
+    }
+
+    /// Get the emergency declared by the code, if it's one of the emergency codes
+    pub fn emergency(&self) -> Option<Emergency> {
+        match self.0 {
+            0o7500 => Some(Emergency::Hijack),
+            0o7600 => Some(Emergency::RadioFailure),
+            0o7700 => Some(Emergency::General),
+            _ => None,
+        }
+    }
+}
+
+/// Emergency declared by squawking one of the emergency codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emergency {
+    /// 7500, unlawful interference
+    Hijack,
+    /// 7600, loss of radio communication
+    RadioFailure,
+    /// 7700, general emergency
+    General,
+}
+
+impl fmt::Display for Squawk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+/// Get the squawk from a surveillance identity reply (DF 5) or Comm-B identity reply (DF 21)
+pub(crate) fn decode_identity_reply(frame: &[u8]) -> Option<Squawk> {
+    if frame.len() < 4 {
+        return None;
+    }
+    let id = (u16::from(frame[2]) << 8 | u16::from(frame[3])) & 0x1FFF;
+    Some(Squawk::from_identity(id))
+}
+
+/// Reference direction of a heading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeadingReference {
+    /// Relative to true north
+    True,
+    /// Relative to magnetic north
+    Magnetic,
+}
+
+/// Kind of aircraft or vehicle, from the emitter category of an identification message (type
+/// codes 1-4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitterCategory {
+    /// Under 15500 lb
+    Light,
+    /// 15500 to 75000 lb
+    Small,
+    /// 75000 to 300000 lb
+    Large,
+    /// Large aircraft with high wake vortex, such as the Boeing 757
+    HighVortexLarge,
+    /// Over 300000 lb
+    Heavy,
+    /// Capable of over 5g and 400 knots
+    HighPerformance,
+    Rotorcraft,
+    Glider,
+    LighterThanAir,
+    Parachutist,
+    Ultralight,
+    Unmanned,
+    SpaceVehicle,
+    EmergencyVehicle,
+    ServiceVehicle,
+    /// Fixed or tethered obstruction
+    Obstacle,
+}
+
+impl EmitterCategory {
+    /// Get the category from the type code and 3-bit category field of an identification
+    /// message. Returns `None` where no category is given or the code is reserved.
+    pub fn from_identification(type_code: u8, category: u8) -> Option<Self> {
+        use EmitterCategory::*;
+        match (type_code, category) {
+            (4, 1) => Some(Light),
+            (4, 2) => Some(Small),
+            (4, 3) => Some(Large),
+            (4, 4) => Some(HighVortexLarge),
+            (4, 5) => Some(Heavy),
+            (4, 6) => Some(HighPerformance),
+            (4, 7) => Some(Rotorcraft),
+            (3, 1) => Some(Glider),
+            (3, 2) => Some(LighterThanAir),
+            (3, 3) => Some(Parachutist),
+            (3, 4) => Some(Ultralight),
+            (3, 6) => Some(Unmanned),
+            (3, 7) => Some(SpaceVehicle),
+            (2, 1) => Some(EmergencyVehicle),
+            (2, 3) => Some(ServiceVehicle),
+            (2, 4..=7) => Some(Obstacle),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EmitterCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EmitterCategory::*;
+        let name = match self {
+            Light => "light",
+            Small => "small",
+            Large => "large",
+            HighVortexLarge => "high vortex large",
+            Heavy => "heavy",
+            HighPerformance => "high performance",
+            Rotorcraft => "rotorcraft",
+            Glider => "glider",
+            LighterThanAir => "lighter than air",
+            Parachutist => "parachutist",
+            Ultralight => "ultralight",
+            Unmanned => "unmanned",
+            SpaceVehicle => "space vehicle",
+            EmergencyVehicle => "emergency vehicle",
+            ServiceVehicle => "service vehicle",
+            Obstacle => "obstacle",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Get an accessor for the bits of the 56-bit message field of an extended squitter or Comm-B
+/// reply. The bits are numbered from 1 at the most significant end, and `field(start, len)` gets
+/// `len` bits from `start` onwards.
+fn message_field(frame: &[u8]) -> Option<impl Fn(u32, u32) -> u64> {
+    if frame.len() < 11 {
+        return None;
+    }
+    let me = frame[4..11]
+        .iter()
+        .fold(0u64, |me, &byte| me << 8 | u64::from(byte));
+    Some(move |start: u32, len: u32| (me >> (57 - start - len)) & ((1 << len) - 1))
+}
+
+/// Heading and airspeed from an airborne velocity message which reports airspeed (type code 19,
+/// subtypes 3 and 4) instead of ground speed. The `adsb` crate only decodes the ground speed
+/// subtypes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct AirspeedVelocity {
+    /// Direction the aircraft is pointing (degrees). It's relative to magnetic north unless the
+    /// aircraft says otherwise in its operational status, which isn't decoded, so it's always
+    /// taken to be magnetic.
+    pub heading: Option<f64>,
+    /// True airspeed (knots), if the aircraft reports true rather than indicated airspeed
+    pub true_airspeed: Option<f64>,
+}
+
+/// Decode an airborne velocity message which reports airspeed. Returns `None` for any other
+/// message.
+pub(crate) fn decode_airspeed_velocity(frame: &[u8]) -> Option<AirspeedVelocity> {
+    let field = message_field(frame)?;
+    let (type_code, subtype) = (field(1, 5), field(6, 3));
+    if type_code != 19 || !(subtype == 3 || subtype == 4) {
+        return None;
+    }
+    let heading = (field(14, 1) == 1).then_some(field(15, 10) as f64 * 360.0 / 1024.0);
+    // Zero means no airspeed, and supersonic aircraft (subtype 4) report in units of 4 knots
+    let scale = if subtype == 4 { 4.0 } else { 1.0 };
+    let is_true_airspeed = field(25, 1) == 1;
+    let true_airspeed = match field(26, 10) {
+        0 => None,
+        airspeed if is_true_airspeed => Some((airspeed - 1) as f64 * scale),
+        _ => None,
+    };
+    Some(AirspeedVelocity {
+        heading,
+        true_airspeed,
+    })
+}
+
+/// CPR encoded position from a surface position message (type codes 5-8), which isn't decoded
+/// by the `adsb` crate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SurfacePosition {
+    /// Odd rather than even CPR format
+    pub odd: bool,
+    pub lat_cpr: u32,
+    pub lon_cpr: u32,
+}
+
+impl SurfacePosition {
+    /// Resolve the position relative to a reference position, such as the receiver's, which must
+    /// be within 45 nautical miles of it. Unlike airborne positions, surface positions can't be
+    /// resolved globally from a pair of frames without knowing roughly where they are, since the
+    /// encoding repeats every 90 degrees.
+    pub fn resolve(&self, reference: &Position) -> Option<Position> {
+        local_cpr_position(
+            self.odd,
+            self.lat_cpr,
+            self.lon_cpr,
+            reference,
+            SURFACE_CPR_ZONE,
+        )
+    }
+}
+
+/// Decode a surface position message. Returns `None` for any other message.
+pub(crate) fn decode_surface_position(frame: &[u8]) -> Option<SurfacePosition> {
+    let field = message_field(frame)?;
+    if !(5..=8).contains(&field(1, 5)) {
+        return None;
+    }
+    Some(SurfacePosition {
+        odd: field(22, 1) == 1,
+        lat_cpr: field(23, 17) as u32,
+        lon_cpr: field(40, 17) as u32,
+    })
+}
+
+/// Decode the altitude and CPR frame of an airborne position message (type codes 9-18). The
+/// `adsb` crate decodes these itself, but rejects altitudes below sea level, so this is only
+/// needed for the frames it fails to parse. Returns `None` for any other message, and for
+/// altitudes in 100 ft Gillham code, which aren't decoded.
+pub(crate) fn decode_airborne_position(frame: &[u8]) -> Option<(i32, CPRFrame)> {
+    let field = message_field(frame)?;
+    if frame[0] >> 3 != 17 || !(9..=18).contains(&field(1, 5)) || field(16, 1) == 0 {
+        return None;
+    }
+    let n = field(9, 7) << 4 | field(17, 4);
+    Some((n as i32 * 25 - 1000, position_cpr_frame(&field)))
+}
+
+/// Decode the geometric altitude (feet) and CPR frame of an airborne position message with GNSS
+/// height (type codes 20-22), which isn't decoded by the `adsb` crate. Returns `None` for any
+/// other message.
+pub(crate) fn decode_gnss_position(frame: &[u8]) -> Option<(i32, CPRFrame)> {
+    let field = message_field(frame)?;
+    if frame[0] >> 3 != 17 || !(20..=22).contains(&field(1, 5)) {
+        return None;
+    }
+    // The height is a plain binary number of meters
+    let altitude = (field(9, 12) as f64 / METERS_PER_FOOT).round() as i32;
+    Some((altitude, position_cpr_frame(&field)))
+}
+
+/// Get the CPR frame from the message field of an airborne position message
+fn position_cpr_frame(field: &impl Fn(u32, u32) -> u64) -> CPRFrame {
+    let parity = if field(22, 1) == 1 {
+        Parity::Odd
+    } else {
+        Parity::Even
+    };
+    CPRFrame {
+        position: Position {
+            latitude: field(23, 17) as f64,
+            longitude: field(40, 17) as f64,
+        },
+        parity,
+    }
+}
+
+/// Resolve a single airborne CPR frame relative to a reference position, such as the receiver's
+/// or the aircraft's last known position, which must be within 180 nautical miles of it. This
+/// gives a position without waiting for a frame of the other parity.
+pub(crate) fn local_airborne_position(
+    cpr_frame: &CPRFrame,
+    reference: &Position,
+) -> Option<Position> {
+    local_cpr_position(
+        cpr_frame.parity == Parity::Odd,
+        cpr_frame.position.latitude as u32,
+        cpr_frame.position.longitude as u32,
+        reference,
+        AIRBORNE_CPR_ZONE,
+    )
+}
+
+/// Get the difference between geometric and barometric altitude (feet) from an airborne velocity
+/// message (type code 19), if the aircraft reports it
+pub(crate) fn decode_altitude_difference(frame: &[u8]) -> Option<i32> {
+    let field = message_field(frame)?;
+    if field(1, 5) != 19 {
+        return None;
+    }
+    // Zero means no information, and the largest value means more than 3125 ft
+    let difference = match field(50, 7) {
+        0 | 127 => return None,
+        n => (n as i32 - 1) * 25,
+    };
+    Some(if field(49, 1) == 1 {
+        -difference
+    } else {
+        difference
+    })
+}
+
+/// Number of longitude zones at a latitude in CPR encoding
+fn cpr_nl(latitude: f64) -> f64 {
+    if latitude.abs() >= 87.0 {
+        return if latitude.abs() == 87.0 { 2.0 } else { 1.0 };
+    }
+    let a = 1.0 - (PI / (2.0 * CPR_NZ)).cos();
+    let b = latitude.to_radians().cos().powi(2);
+    (2.0 * PI / (1.0 - a / b).acos()).floor()
+}
+
+/// Decode a single CPR frame to the position nearest a reference position. `zone` is the size of
+/// the area the encoding repeats over: 360 degrees for airborne and 90 for surface positions.
+fn local_cpr_position(
+    odd: bool,
+    lat_cpr: u32,
+    lon_cpr: u32,
+    reference: &Position,
+    zone: f64,
+) -> Option<Position> {
+    let i = if odd { 1.0 } else { 0.0 };
+    let (lat_cpr, lon_cpr) = (f64::from(lat_cpr) / CPR_MAX, f64::from(lon_cpr) / CPR_MAX);
+    let d_lat = zone / (4.0 * CPR_NZ - i);
+    let j = (reference.latitude / d_lat).floor()
+        + (0.5 + reference.latitude.rem_euclid(d_lat) / d_lat - lat_cpr).floor();
+    let latitude = d_lat * (j + lat_cpr);
+    if latitude.abs() > 90.0 {
+        return None;
+    }
+    let d_lon = zone / (cpr_nl(latitude) - i).max(1.0);
+    let m = (reference.longitude / d_lon).floor()
+        + (0.5 + reference.longitude.rem_euclid(d_lon) / d_lon - lon_cpr).floor();
+    let longitude = d_lon * (m + lon_cpr);
+    Some(Position {
+        latitude,
+        longitude,
+    })
+}
+
+/// Autopilot settings from a target state and status message (type code 29, subtype 1), which
+/// isn't decoded by the `adsb` crate. Settings the aircraft marks as unavailable are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TargetState {
+    /// Altitude selected on the MCP/FCU or in the FMS (feet)
+    pub selected_altitude: Option<i32>,
+    /// Barometric pressure setting (hPa)
+    pub pressure_setting: Option<f64>,
+    /// Heading selected on the MCP/FCU (degrees)
+    pub selected_heading: Option<f64>,
+}
+
+/// Decode a target state and status message. Returns `None` for any other message.
+pub(crate) fn decode_target_state(frame: &[u8]) -> Option<TargetState> {
+    let field = message_field(frame)?;
+    if field(1, 5) != 29 || field(6, 2) != 1 {
+        return None;
+    }
+    // Zero means no data for the altitude and pressure setting, while the heading has its own
+    // status bit
+    let selected_altitude = match field(10, 11) {
+        0 => None,
+        altitude => Some(((altitude - 1) * 32) as i32),
+    };
+    let pressure_setting = match field(21, 9) {
+        0 => None,
+        pressure => Some(800.0 + (pressure - 1) as f64 * 0.8),
+    };
+    let selected_heading = (field(30, 1) == 1).then_some(field(31, 9) as f64 * 180.0 / 256.0);
+    Some(TargetState {
+        selected_altitude,
+        pressure_setting,
+        selected_heading,
+    })
+}
+
+/// Selected vertical intention from a Comm-B reply (BDS 4,0). Values the aircraft marks as
+/// unavailable are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct SelectedVerticalIntention {
+    /// Altitude selected on the MCP/FCU (feet)
+    pub mcp_altitude: Option<i32>,
+    /// Altitude selected in the FMS (feet)
+    pub fms_altitude: Option<i32>,
+    /// Barometric pressure setting (hPa)
+    pub pressure_setting: Option<f64>,
+}
+
+/// Track and turn report from a Comm-B reply (BDS 5,0). Values the aircraft marks as
+/// unavailable are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct TrackAndTurn {
+    /// Roll angle, positive for right wing down (degrees)
+    pub roll_angle: Option<f64>,
+    /// Ground track, relative to true north (degrees)
+    pub track: Option<f64>,
+    /// Ground speed (knots)
+    pub ground_speed: Option<f64>,
+    /// Rate of change of the ground track, positive when turning right (degrees per second)
+    pub track_angle_rate: Option<f64>,
+    /// True airspeed (knots)
+    pub true_airspeed: Option<f64>,
+}
+
+/// Heading and speed report from a Comm-B reply (BDS 6,0). Values the aircraft marks as
+/// unavailable are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct HeadingAndSpeed {
+    /// Heading, relative to magnetic north (degrees)
+    pub heading: Option<f64>,
+    /// Indicated airspeed (knots)
+    pub indicated_airspeed: Option<f64>,
+    /// Mach number
+    pub mach: Option<f64>,
+    /// Rate of change of barometric altitude (feet per minute)
+    pub barometric_vertical_rate: Option<i16>,
+    /// Rate of change of altitude from inertial systems (feet per minute)
+    pub inertial_vertical_rate: Option<i16>,
+}
+
+/// Meteorological routine air report from a Comm-B reply (BDS 4,4). The wind is `None` when
+/// the aircraft marks it as unavailable.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct MeteorologicalRoutineReport {
+    /// Wind speed (knots)
+    pub wind_speed: Option<f64>,
+    /// Direction the wind is blowing from, relative to true north (degrees)
+    pub wind_direction: Option<f64>,
+    /// Static air temperature (degrees Celsius)
+    pub static_air_temperature: f64,
+}
+
+/// Meteorological hazard report from a Comm-B reply (BDS 4,5). Only the temperature is kept of
+/// the values it can carry, and it's `None` when the aircraft marks it as unavailable.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct MeteorologicalHazardReport {
+    /// Static air temperature (degrees Celsius)
+    pub static_air_temperature: Option<f64>,
+}
+
+/// Register carried by a Comm-B reply
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CommB {
+    SelectedVerticalIntention(SelectedVerticalIntention),
+    MeteorologicalRoutineReport(MeteorologicalRoutineReport),
+    MeteorologicalHazardReport(MeteorologicalHazardReport),
+    TrackAndTurn(TrackAndTurn),
+    HeadingAndSpeed(HeadingAndSpeed),
+}
+
+/// Decode the message field of a Comm-B reply (DF 20 or 21). The reply doesn't say which
+/// register it carries, so it's taken to be whichever of BDS 4,0, 4,4, 4,5, 5,0 and 6,0 gives
+/// plausible values, with every unavailable value's bits clear. Returns `None` unless exactly
+/// one does.
+pub(crate) fn decode_comm_b(frame: &[u8]) -> Option<CommB> {
+    match frame.first().map(|byte| byte >> 3) {
+        Some(20) | Some(21) if frame.len() == 14 => {}
+        _ => return None,
+    }
+    let registers = [
+        decode_selected_vertical_intention(frame).map(CommB::SelectedVerticalIntention),
+        decode_meteorological_routine_report(frame).map(CommB::MeteorologicalRoutineReport),
+        decode_meteorological_hazard_report(frame).map(CommB::MeteorologicalHazardReport),
+        decode_track_and_turn(frame).map(CommB::TrackAndTurn),
+        decode_heading_and_speed(frame).map(CommB::HeadingAndSpeed),
+    ];
+    let mut registers = registers.iter().flatten();
+    match (registers.next(), registers.next()) {
+        (Some(&register), None) => Some(register),
+        _ => None,
+    }
+}
+
+/// Get a value from the message field which has a status bit at `status` followed by `len`
+/// bits, the first of them a sign bit if `signed`. Gives `Err` if the status bit is clear but
+/// the value isn't zero, which means the field isn't laid out the way it's being read.
+fn status_field(
+    field: &impl Fn(u32, u32) -> u64,
+    status: u32,
+    len: u32,
+    signed: bool,
+) -> Result<Option<i64>, ()> {
+    let value = field(status + 1, len);
+    if field(status, 1) == 0 {
+        return if value == 0 { Ok(None) } else { Err(()) };
+    }
+    let value = value as i64;
+    if signed && value >> (len - 1) == 1 {
+        Ok(Some(value - (1 << len)))
+    } else {
+        Ok(Some(value))
+    }
+}
+
+fn decode_selected_vertical_intention(frame: &[u8]) -> Option<SelectedVerticalIntention> {
+    let field = message_field(frame)?;
+    let mcp_altitude = status_field(&field, 1, 12, false).ok()?;
+    let fms_altitude = status_field(&field, 14, 12, false).ok()?;
+    let pressure_setting = status_field(&field, 27, 12, false).ok()?;
+    // Autopilot modes and the source of the target altitude, which are only checked
+    status_field(&field, 48, 3, false).ok()?;
+    status_field(&field, 54, 2, false).ok()?;
+    if field(40, 8) != 0 || field(52, 2) != 0 {
+        return None;
+    }
+    let report = SelectedVerticalIntention {
+        mcp_altitude: mcp_altitude.map(|altitude| altitude as i32 * 16),
+        fms_altitude: fms_altitude.map(|altitude| altitude as i32 * 16),
+        pressure_setting: pressure_setting.map(|pressure| 800.0 + pressure as f64 * 0.1),
+    };
+    let plausible_altitude = |altitude: Option<i32>| altitude.is_none_or(|a| a <= 50_000);
+    let plausible = plausible_altitude(report.mcp_altitude)
+        && plausible_altitude(report.fms_altitude)
+        && report
+            .pressure_setting
+            .is_none_or(|pressure| pressure <= 1100.0)
+        && (report.mcp_altitude.is_some() || report.fms_altitude.is_some());
+    plausible.then_some(report)
+}
+
+/// Check that a temperature is one an aircraft could plausibly meet (degrees Celsius)
+fn plausible_temperature(temperature: f64) -> bool {
+    (-80.0..=60.0).contains(&temperature)
+}
+
+fn decode_meteorological_routine_report(frame: &[u8]) -> Option<MeteorologicalRoutineReport> {
+    let field = message_field(frame)?;
+    // Zero is an invalid source, and higher values than 4 are reserved
+    if !(1..=4).contains(&field(1, 4)) {
+        return None;
+    }
+    let wind = status_field(&field, 5, 18, false).ok()?;
+    // The temperature has no status bit
+    let mut temperature = field(24, 11) as i64;
+    if temperature >> 10 == 1 {
+        temperature -= 1 << 11;
+    }
+    // Pressure, turbulence and humidity, which are only checked
+    status_field(&field, 35, 11, false).ok()?;
+    status_field(&field, 47, 2, false).ok()?;
+    status_field(&field, 50, 6, false).ok()?;
+    let report = MeteorologicalRoutineReport {
+        wind_speed: wind.map(|wind| (wind >> 9) as f64),
+        wind_direction: wind.map(|wind| (wind & 0x1FF) as f64 * 180.0 / 256.0),
+        static_air_temperature: temperature as f64 * 0.25,
+    };
+    let plausible = report.wind_speed.is_none_or(|speed| speed <= 250.0)
+        && plausible_temperature(report.static_air_temperature);
+    plausible.then_some(report)
+}
+
+fn decode_meteorological_hazard_report(frame: &[u8]) -> Option<MeteorologicalHazardReport> {
+    let field = message_field(frame)?;
+    // Turbulence, wind shear, microburst, icing and wake vortex hazards, which are only checked
+    let mut any_hazard = false;
+    for status in (1..=13).step_by(3) {
+        any_hazard |= status_field(&field, status, 2, false).ok()?.is_some();
+    }
+    let temperature = status_field(&field, 16, 10, true).ok()?;
+    // Average static pressure and radio height, which are only checked
+    let pressure = status_field(&field, 27, 11, false).ok()?;
+    let radio_height = status_field(&field, 39, 12, false).ok()?;
+    if field(52, 5) != 0 {
+        return None;
+    }
+    let report = MeteorologicalHazardReport {
+        static_air_temperature: temperature.map(|temperature| temperature as f64 * 0.25),
+    };
+    let plausible = report
+        .static_air_temperature
+        .is_none_or(plausible_temperature)
+        && (any_hazard
+            || report.static_air_temperature.is_some()
+            || pressure.is_some()
+            || radio_height.is_some());
+    plausible.then_some(report)
+}
+
+fn decode_track_and_turn(frame: &[u8]) -> Option<TrackAndTurn> {
+    let field = message_field(frame)?;
+    let roll_angle = status_field(&field, 1, 10, true).ok()?;
+    let track = status_field(&field, 12, 11, true).ok()?;
+    let ground_speed = status_field(&field, 24, 10, false).ok()?;
+    let track_angle_rate = status_field(&field, 35, 10, true).ok()?;
+    let true_airspeed = status_field(&field, 46, 10, false).ok()?;
+    let report = TrackAndTurn {
+        roll_angle: roll_angle.map(|roll| roll as f64 * 45.0 / 256.0),
+        track: track.map(|track| (track as f64 * 90.0 / 512.0).rem_euclid(360.0)),
+        ground_speed: ground_speed.map(|speed| speed as f64 * 2.0),
+        track_angle_rate: track_angle_rate.map(|rate| rate as f64 * 8.0 / 256.0),
+        true_airspeed: true_airspeed.map(|speed| speed as f64 * 2.0),
+    };
+    let plausible = report.roll_angle.is_none_or(|roll| roll.abs() <= 50.0)
+        && report.ground_speed.is_none_or(|speed| speed <= 600.0)
+        && report.true_airspeed.is_none_or(|speed| speed <= 600.0)
+        && match (report.ground_speed, report.true_airspeed) {
+            (Some(ground_speed), Some(true_airspeed)) => {
+                (ground_speed - true_airspeed).abs() <= 200.0
+            }
+            _ => true,
+        };
+    (plausible && report != TrackAndTurn::default()).then_some(report)
+}
+
+fn decode_heading_and_speed(frame: &[u8]) -> Option<HeadingAndSpeed> {
+    let field = message_field(frame)?;
+    let heading = status_field(&field, 1, 11, true).ok()?;
+    let indicated_airspeed = status_field(&field, 13, 10, false).ok()?;
+    let mach = status_field(&field, 24, 10, false).ok()?;
+    let barometric_vertical_rate = status_field(&field, 35, 10, true).ok()?;
+    let inertial_vertical_rate = status_field(&field, 46, 10, true).ok()?;
+    let report = HeadingAndSpeed {
+        heading: heading.map(|heading| (heading as f64 * 90.0 / 512.0).rem_euclid(360.0)),
+        indicated_airspeed: indicated_airspeed.map(|speed| speed as f64),
+        mach: mach.map(|mach| mach as f64 * 2.048 / 512.0),
+        barometric_vertical_rate: barometric_vertical_rate.map(|rate| (rate * 32) as i16),
+        inertial_vertical_rate: inertial_vertical_rate.map(|rate| (rate * 32) as i16),
+    };
+    let plausible_rate = |rate: Option<i16>| rate.is_none_or(|rate| rate.abs() <= 6000);
+    let plausible = report.indicated_airspeed.is_none_or(|speed| speed <= 500.0)
+        && report.mach.is_none_or(|mach| mach <= 1.0)
+        && plausible_rate(report.barometric_vertical_rate)
+        && plausible_rate(report.inertial_vertical_rate);
+    (plausible && report != HeadingAndSpeed::default()).then_some(report)
+}
+
+/// Region whose convention is used to tell VFR squawks apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SquawkRegion {
+    /// VFR traffic squawks 1200
+    Us,
+    /// VFR traffic squawks 7000
+    Europe,
+}
+
+impl FromStr for SquawkRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "us" => Ok(SquawkRegion::Us),
+            "eu" => Ok(SquawkRegion::Europe),
+            _ => Err(format!("unknown region '{}' (expected us or eu)", s)),
+        }
+    }
+}
+
+/// Rough classification of a squawk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SquawkClass {
+    /// Code used by VFR traffic
+    Vfr,
+    /// Discrete code assigned by ATC, usually to IFR traffic
+    Discrete,
+    /// Conspicuity, emergency or other special purpose code
+    Special,
+}
+
+/// Counts of aircraft by the class of their squawk
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SquawkSummary {
+    /// Aircraft squawking a VFR code
+    pub vfr: u64,
+    /// Aircraft squawking a discrete code
+    pub discrete: u64,
+    /// Aircraft with no known squawk
+    pub unknown: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_matches_adsb_parity() {
+        let frame = b"\x8D\x48\x40\xD6\x20\x2C\xC3\x71\xC3\x2C\xE0\x57\x60\x98";
+        assert_eq!(crc(&frame[..11]), 0x57_6098);
+        assert_eq!(crc(frame), 0);
+    }
+
+    #[test]
+    fn decode_squawk() {
+        let frame = b"\x2A\x00\x51\x6D\x49\x2B\x80";
+        let squawk = decode_identity_reply(frame).unwrap();
+        assert_eq!(squawk.to_string(), "0356");
+        assert_eq!(address_from_parity(frame), Some(0x510AF9));
+    }
+
+    #[test]
+    fn decode_airspeed_velocity_message() {
+        let frame = b"\x8D\xA0\x5F\x21\x9B\x06\xB6\xAF\x18\x94\x00\xCB\xC3\x3F";
+        let velocity = decode_airspeed_velocity(frame).unwrap();
+        assert!((velocity.heading.unwrap() - 243.98).abs() < 0.01);
+        assert_eq!(velocity.true_airspeed, Some(375.0));
+        let frame = b"\x8D\x48\x50\x20\x99\x44\x09\x94\x08\x38\x17\x5B\x28\x4F";
+        assert_eq!(decode_airspeed_velocity(frame), None);
+    }
+
+    #[test]
+    fn decode_target_state_message() {
+        let frame = b"\x8D\xA0\x56\x29\xEA\x21\x48\x5C\xBF\x3F\x8C\xAD\xAE\xEB";
+        let target_state = decode_target_state(frame).unwrap();
+        assert_eq!(target_state.selected_altitude, Some(16992));
+        assert!((target_state.pressure_setting.unwrap() - 1012.8).abs() < 0.01);
+        assert!((target_state.selected_heading.unwrap() - 66.8).abs() < 0.01);
+        // Same message with the heading status bit cleared
+        let frame = b"\x8D\xA0\x56\x29\xEA\x21\x48\x58\xBF\x3F\x8C\xAD\xAE\xEB";
+        assert_eq!(decode_target_state(frame).unwrap().selected_heading, None);
+    }
+
+    #[test]
+    fn decode_comm_b_registers() {
+        let frame = b"\xA0\x00\x13\x93\x81\x95\x15\x36\xE0\x24\xD4\xCC\xF6\xB5";
+        let report = match decode_comm_b(frame) {
+            Some(CommB::TrackAndTurn(report)) => report,
+            other => panic!("expected a track and turn report, got {:?}", other),
+        };
+        assert!((report.roll_angle.unwrap() - 2.1).abs() < 0.01);
+        assert!((report.track.unwrap() - 114.258).abs() < 0.01);
+        assert_eq!(report.ground_speed, Some(438.0));
+        assert_eq!(report.track_angle_rate, Some(0.125));
+        assert_eq!(report.true_airspeed, Some(424.0));
+        let frame = b"\xA0\x00\x04\x12\x8F\x39\xF9\x1A\x7E\x27\xC4\x6A\xDC\x21";
+        let report = match decode_comm_b(frame) {
+            Some(CommB::HeadingAndSpeed(report)) => report,
+            other => panic!("expected a heading and speed report, got {:?}", other),
+        };
+        assert!((report.heading.unwrap() - 42.715).abs() < 0.01);
+        assert_eq!(report.indicated_airspeed, Some(252.0));
+        assert!((report.mach.unwrap() - 0.42).abs() < 0.001);
+        assert_eq!(report.barometric_vertical_rate, Some(-1920));
+        assert_eq!(report.inertial_vertical_rate, Some(-1920));
+        let frame = b"\xA0\x00\x02\x9C\x85\xE4\x2F\x31\x30\x00\x00\x70\x47\xD3";
+        let report = match decode_comm_b(frame) {
+            Some(CommB::SelectedVerticalIntention(report)) => report,
+            other => panic!("expected a selected vertical intention, got {:?}", other),
+        };
+        assert_eq!(report.mcp_altitude, Some(3008));
+        assert_eq!(report.fms_altitude, Some(3008));
+        assert!((report.pressure_setting.unwrap() - 1020.0).abs() < 0.01);
+        // Same report with the MCP/FCU altitude's status bit cleared but its bits left set
+        let frame = b"\xA0\x00\x02\x9C\x05\xE4\x2F\x31\x30\x00\x00\x70\x47\xD3";
+        assert_eq!(decode_comm_b(frame), None);
+        // Example from "The 1090 Megahertz Riddle"
+        let frame = b"\xA0\x00\x16\x92\x18\x5B\xD5\xCF\x40\x00\x00\xDF\xC6\x96";
+        let report = match decode_comm_b(frame) {
+            Some(CommB::MeteorologicalRoutineReport(report)) => report,
+            other => panic!("expected a meteorological routine report, got {:?}", other),
+        };
+        assert_eq!(report.wind_speed, Some(22.0));
+        assert!((report.wind_direction.unwrap() - 344.5).abs() < 0.1);
+        assert_eq!(report.static_air_temperature, -48.75);
+        let frame = b"\xA0\x00\x00\x00\xA0\x01\xE2\x00\x00\x00\x00\xE5\xC3\xD2";
+        let report = match decode_comm_b(frame) {
+            Some(CommB::MeteorologicalHazardReport(report)) => report,
+            other => panic!("expected a meteorological hazard report, got {:?}", other),
+        };
+        assert_eq!(report.static_air_temperature, Some(-30.0));
+        // An empty message field could be any register
+        let frame = b"\xA0\x00\x04\x12\x00\x00\x00\x00\x00\x00\x00\x6A\xDC\x21";
+        assert_eq!(decode_comm_b(frame), None);
+    }
+
+    #[test]
+    fn decode_surface_position_message() {
+        // Surface position of a taxiing aircraft at Schiphol, resolved against a receiver in Delft
+        let frame = b"\x8C\x48\x41\x75\x3A\xAB\x23\x87\x33\xC8\xCD\x40\x20\xB1";
+        let surface = decode_surface_position(frame).unwrap();
+        let reference = Position {
+            latitude: 51.990,
+            longitude: 4.375,
+        };
+        let position = surface.resolve(&reference).unwrap();
+        assert!((position.latitude - 52.32304).abs() < 1e-5);
+        assert!((position.longitude - 4.73047).abs() < 1e-5);
+        let airborne = b"\x8D\x40\x62\x1D\x58\xC3\x82\xD6\x90\xC8\xAC\x28\x63\xA7";
+        assert_eq!(decode_surface_position(airborne), None);
+    }
+
+    #[test]
+    fn decode_airborne_position_locally() {
+        let frame = b"\x8D\x40\x62\x1D\x58\xC3\x82\xD6\x90\xC8\xAC\x28\x63\xA7";
+        let (_, cpr_frame) = decode_airborne_position(frame).unwrap();
+        let reference = Position {
+            latitude: 52.258,
+            longitude: 3.918,
+        };
+        let position = local_airborne_position(&cpr_frame, &reference).unwrap();
+        assert!((position.latitude - 52.25720).abs() < 1e-5);
+        assert!((position.longitude - 3.91937).abs() < 1e-5);
+    }
+
+    #[test]
+    fn decode_geometric_altitude() {
+        let frame = b"\x8D\x40\x62\x1D\xA0\x3E\x82\xD6\x90\xC8\xAC\xFB\x5B\x43";
+        let (altitude, cpr_frame) = decode_gnss_position(frame).unwrap();
+        assert_eq!(altitude, 3281);
+        assert_eq!(cpr_frame.parity, Parity::Even);
+        let barometric = b"\x8D\x40\x62\x1D\x58\xC3\x82\xD6\x90\xC8\xAC\x28\x63\xA7";
+        assert!(decode_gnss_position(barometric).is_none());
+        let velocity = b"\x8D\x48\x50\x20\x99\x44\x09\x94\x08\x38\x17\x5B\x28\x4F";
+        assert_eq!(decode_altitude_difference(velocity), Some(550));
+    }
+
+    #[test]
+    fn icao_round_trip() {
+        let icao = icao_from_u32(0xA1B2C3).unwrap();
+        assert_eq!(icao.to_string(), "A1B2C3");
+        assert_eq!(icao_to_u32(&icao), 0xA1B2C3);
+    }
+}
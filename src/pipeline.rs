@@ -0,0 +1,165 @@
+use crate::tracker::{DecodedFrame, Tracker};
+use chrono::Utc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Number of frames handed to a worker at a time
+const BATCH_SIZE: usize = 256;
+/// Longest a frame waits in a partly filled batch before the batch is handed to a worker
+const MAX_BATCH_DELAY: Duration = Duration::from_millis(100);
+/// Number of batches which can be queued up for each worker before `DecodePool::push` blocks
+const QUEUE_DEPTH: usize = 4;
+
+/// A frame as read from a feed, before decoding
+#[derive(Debug, Clone)]
+pub enum RawFrame {
+    /// Frame in AVR format, e.g. `*8D4840D6202CC371C32CE0576098;`
+    Avr(String),
+    /// Frame in binary format
+    Binary(Vec<u8>),
+}
+
+impl RawFrame {
+    fn decode(&self, time: chrono::DateTime<Utc>) -> DecodedFrame {
+        match self {
+            RawFrame::Avr(frame) => DecodedFrame::from_avr(frame, time),
+            RawFrame::Binary(frame) => DecodedFrame::from_binary(frame.clone(), time),
+        }
+    }
+}
+
+type Batch<T> = Vec<(T, chrono::DateTime<Utc>)>;
+
+/// Decodes frames on a pool of worker threads and applies them to a tracker from a single
+/// merging thread, so the tracker is only locked for the cheap state update and not for parsing.
+///
+/// Frames are collected into batches which are handed to the workers in turn, and the merging
+/// thread collects the decoded batches from the workers in the same turn, so frames are applied
+/// in the order they were pushed. A batch is handed over once it's full or, checked as frames
+/// are pushed, once its first frame has waited `MAX_BATCH_DELAY`. Call `flush` when there may be
+/// no more frames for a while.
+///
+/// Handing frames between threads isn't free, so a pool only decodes faster than the reader
+/// thread can when there are cores to spare. On a single core it's slower, as the
+/// `decode_throughput` example shows.
+pub struct DecodePool {
+    workers: Vec<SyncSender<Batch<RawFrame>>>,
+    next_worker: usize,
+    batch: Batch<RawFrame>,
+    batch_started: Instant,
+    merger: JoinHandle<()>,
+}
+
+impl DecodePool {
+    /// Start a pool of `threads` decoding workers (at least one) feeding `tracker`
+    pub fn new(tracker: Arc<Mutex<Tracker>>, threads: usize) -> Self {
+        let (workers, decoded): (Vec<_>, Vec<_>) = (0..threads.max(1))
+            .map(|_| {
+                let (raw_sender, raw_receiver) = mpsc::sync_channel(QUEUE_DEPTH);
+                let (decoded_sender, decoded_receiver) = mpsc::sync_channel(QUEUE_DEPTH);
+                thread::spawn(move || decode_batches(raw_receiver, decoded_sender));
+                (raw_sender, decoded_receiver)
+            })
+            .unzip();
+        let merger = thread::spawn(move || merge_batches(decoded, tracker));
+        DecodePool {
+            workers,
+            next_worker: 0,
+            batch: Vec::with_capacity(BATCH_SIZE),
+            batch_started: Instant::now(),
+            merger,
+        }
+    }
+
+    /// Queue a frame received at the given time for decoding
+    pub fn push(&mut self, frame: RawFrame, time: chrono::DateTime<Utc>) {
+        if self.batch.is_empty() {
+            self.batch_started = Instant::now();
+        }
+        self.batch.push((frame, time));
+        if self.batch.len() >= BATCH_SIZE || self.batch_started.elapsed() >= MAX_BATCH_DELAY {
+            self.flush();
+        }
+    }
+
+    /// Hand any queued frames to a worker without waiting for the batch to fill
+    pub fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        // Workers only stop once the pool is dropped, so sending can't fail
+        let _ = self.workers[self.next_worker].send(batch);
+        self.next_worker = (self.next_worker + 1) % self.workers.len();
+    }
+
+    /// Apply all queued frames to the tracker and stop the threads
+    pub fn finish(mut self) {
+        self.flush();
+        drop(self.workers);
+        let _ = self.merger.join();
+    }
+}
+
+fn decode_batches(raw: Receiver<Batch<RawFrame>>, decoded: SyncSender<Vec<DecodedFrame>>) {
+    for batch in raw {
+        let batch = batch
+            .into_iter()
+            .map(|(frame, time)| frame.decode(time))
+            .collect();
+        if decoded.send(batch).is_err() {
+            return;
+        }
+    }
+}
+
+fn merge_batches(decoded: Vec<Receiver<Vec<DecodedFrame>>>, tracker: Arc<Mutex<Tracker>>) {
+    // Once a worker has stopped, the workers after it in turn have no more batches either
+    for receiver in decoded.iter().cycle() {
+        let batch = match receiver.recv() {
+            Ok(batch) => batch,
+            Err(_) => return,
+        };
+        let mut tracker = tracker.lock().unwrap();
+        for frame in batch {
+            let _ = tracker.update_with_decoded(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES: [&str; 4] = [
+        "*8D4840D6202CC371C32CE0576098;",
+        "*8D40621D58C382D690C8AC2863A7;",
+        "*8D40621D58C386435CC412692AD6;",
+        "*8D485020994409940838175B284F;",
+    ];
+
+    #[test]
+    fn pool_matches_serial_decoding() {
+        let mut serial = Tracker::new();
+        let pooled = Arc::new(Mutex::new(Tracker::new()));
+        let mut pool = DecodePool::new(pooled.clone(), 3);
+        let time = Utc::now();
+        for i in 0..1000 {
+            let frame = FRAMES[i % FRAMES.len()];
+            serial.update_with_avr(frame, time).unwrap();
+            pool.push(RawFrame::Avr(frame.to_string()), time);
+        }
+        pool.push(RawFrame::Binary(vec![0x8D, 0x48]), time);
+        pool.finish();
+        let pooled = pooled.lock().unwrap();
+        assert_eq!(pooled.get_num_messages(), serial.get_num_messages());
+        assert_eq!(pooled.get_num_invalid_length_frames(), 1);
+        assert_eq!(
+            pooled.get_position_statistics(),
+            serial.get_position_statistics()
+        );
+        assert_eq!(pooled.get_all_aircraft().len(), 3);
+    }
+}
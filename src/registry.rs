@@ -0,0 +1,124 @@
+use crate::modes::icao_from_u32;
+use adsb::ICAOAddress;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// What a registry knows about an aircraft
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Registration {
+    /// National registration (tail number), e.g. `PH-BXA`
+    pub registration: Option<String>,
+    /// ICAO type designator, e.g. `B738`
+    pub aircraft_type: Option<String>,
+}
+
+/// Source of registrations for ICAO addresses, such as a database of aircraft. The tracker looks
+/// up each aircraft when it's first seen.
+pub trait RegistryLookup: Send {
+    fn lookup(&self, icao_address: &ICAOAddress) -> Option<Registration>;
+}
+
+/// Registry loaded from a BaseStation-style `aircraft.csv`
+#[derive(Debug, Clone, Default)]
+pub struct CsvRegistry {
+    map: HashMap<ICAOAddress, Registration>,
+}
+
+impl CsvRegistry {
+    /// Load a registry from a file in the format read by `CsvRegistry::parse`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(CsvRegistry::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parse comma-separated lines with a header naming the columns. The address is taken from
+    /// the `icao24`, `icao` or `modes` column, the registration from `registration` or `reg`
+    /// and the type from `icaotypecode`, `typecode` or `type`, ignoring case. Other columns are
+    /// ignored, as are lines without a valid address. Fields may be quoted, but can't contain
+    /// commas.
+    pub fn parse(text: &str) -> Self {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        let header: Vec<String> = match lines.next() {
+            Some(header) => split_fields(header).map(str::to_lowercase).collect(),
+            None => return CsvRegistry::default(),
+        };
+        let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+        let icao_column = match column(&["icao24", "icao", "modes"]) {
+            Some(icao_column) => icao_column,
+            None => return CsvRegistry::default(),
+        };
+        let registration_column = column(&["registration", "reg"]);
+        let type_column = column(&["icaotypecode", "typecode", "type"]);
+        let map = lines
+            .filter_map(|line| {
+                let fields: Vec<&str> = split_fields(line).collect();
+                let icao = fields.get(icao_column)?;
+                if icao.len() != 6 {
+                    return None;
+                }
+                let icao_address = icao_from_u32(u32::from_str_radix(icao, 16).ok()?)?;
+                let field = |column: Option<usize>| {
+                    let value = fields.get(column?)?;
+                    (!value.is_empty()).then(|| value.to_string())
+                };
+                let registration = Registration {
+                    registration: field(registration_column),
+                    aircraft_type: field(type_column),
+                };
+                Some((icao_address, registration))
+            })
+            .collect();
+        CsvRegistry { map }
+    }
+
+    /// Number of aircraft in the registry
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether the registry has no aircraft
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl RegistryLookup for CsvRegistry {
+    fn lookup(&self, icao_address: &ICAOAddress) -> Option<Registration> {
+        self.map.get(icao_address).cloned()
+    }
+}
+
+/// Split a line into trimmed fields, removing any quotes around them
+fn split_fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(',').map(|field| {
+        let field = field.trim();
+        field
+            .strip_prefix('"')
+            .and_then(|f| f.strip_suffix('"'))
+            .unwrap_or(field)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_registry() {
+        let registry = CsvRegistry::parse(
+            "\"ModeS\",\"Registration\",\"ICAOTypeCode\",\"Operator\"\n\
+             \"4840D6\",\"PH-BXA\",\"B738\",\"KLM\"\n\
+             485020,,A320,\n\
+             XYZ,N1,C172,\n",
+        );
+        assert_eq!(registry.len(), 2);
+        let registration = registry.lookup(&icao_from_u32(0x4840D6).unwrap()).unwrap();
+        assert_eq!(registration.registration.as_deref(), Some("PH-BXA"));
+        assert_eq!(registration.aircraft_type.as_deref(), Some("B738"));
+        let registration = registry.lookup(&icao_from_u32(0x485020).unwrap()).unwrap();
+        assert_eq!(registration.registration, None);
+        assert_eq!(registration.aircraft_type.as_deref(), Some("A320"));
+        assert!(CsvRegistry::parse("registration,type\nPH-BXA,B738\n").is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::modes::{icao_from_u32, Squawk};
+use adsb::ICAOAddress;
+
+/// Fields of a transmission message (`MSG`) in the SBS BaseStation format, as sent by dump1090
+/// and readsb on port 30003. Each transmission type fills in a different subset of the fields,
+/// so any of them may be missing.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SbsMessage {
+    pub icao_address: ICAOAddress,
+    pub callsign: Option<String>,
+    /// Barometric altitude (feet)
+    pub altitude: Option<i32>,
+    /// Ground speed (knots)
+    pub ground_speed: Option<f64>,
+    /// Ground track (degrees)
+    pub track: Option<f64>,
+    /// Latitude (degrees)
+    pub latitude: Option<f64>,
+    /// Longitude (degrees)
+    pub longitude: Option<f64>,
+    /// Vertical rate (feet per minute)
+    pub vertical_rate: Option<i16>,
+    pub squawk: Option<Squawk>,
+}
+
+/// Parse a line such as
+/// `MSG,3,1,1,4840D6,1,2020/11/02,18:03:12.345,2020/11/02,18:03:12.345,,38000,,,52.25720,3.91937,,,0,0,0,0`.
+/// Other message types (`STA`, `CLK`, ...) and malformed lines give `None`.
+pub(crate) fn parse_sbs(line: &str) -> Option<SbsMessage> {
+    let fields: Vec<&str> = line.trim().split(',').map(str::trim).collect();
+    if fields.len() < 18 || fields[0] != "MSG" {
+        return None;
+    }
+    let field = |n: usize| Some(fields[n]).filter(|f| !f.is_empty());
+    if fields[4].len() != 6 {
+        return None;
+    }
+    let icao_address = icao_from_u32(u32::from_str_radix(fields[4], 16).ok()?)?;
+    let squawk = field(17).and_then(|s| {
+        let code = u16::from_str_radix(s, 8).ok()?;
+        (s.len() == 4).then_some(Squawk(code))
+    });
+    Some(SbsMessage {
+        icao_address,
+        callsign: field(10).map(str::to_string),
+        altitude: field(11).and_then(|a| a.parse().ok()),
+        ground_speed: field(12).and_then(|s| s.parse().ok()),
+        track: field(13).and_then(|t| t.parse().ok()),
+        latitude: field(14).and_then(|l| l.parse().ok()),
+        longitude: field(15).and_then(|l| l.parse().ok()),
+        vertical_rate: field(16).and_then(|v| v.parse().ok()),
+        squawk,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sbs_messages() {
+        let position = parse_sbs(
+            "MSG,3,1,1,4840D6,1,2020/11/02,18:03:12.345,2020/11/02,18:03:12.345,,38000,,,52.25720,3.91937,,,0,0,0,0\r\n",
+        )
+        .unwrap();
+        assert_eq!(position.icao_address, icao_from_u32(0x4840D6).unwrap());
+        assert_eq!(position.altitude, Some(38000));
+        assert_eq!(position.latitude, Some(52.2572));
+        assert_eq!(position.longitude, Some(3.91937));
+        assert_eq!(position.callsign, None);
+        let identity = parse_sbs("MSG,6,1,1,A1B2C3,1,,,,,KLM1023 ,,,,,,,7700,1,1,0,0").unwrap();
+        assert_eq!(identity.callsign.as_deref(), Some("KLM1023"));
+        assert_eq!(identity.squawk, Some(Squawk(0o7700)));
+        assert_eq!(parse_sbs("STA,,1,1,4840D6,1,,,,,RM"), None);
+        assert_eq!(parse_sbs("CLK,,,,,,,,,,,,,,,,,,,,,"), None);
+        assert_eq!(parse_sbs("MSG,3,1,1,XYZ,1,,,,,,,,,,,,"), None);
+        assert_eq!(parse_sbs("MSG,3,1"), None);
+    }
+}
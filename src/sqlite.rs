@@ -0,0 +1,474 @@
+//! Just enough of SQLite's C API to record frames to a local database and read them back,
+//! linking to the system's libsqlite3. Only built with the `sqlite` feature.
+
+use crate::timestamp::{parse_timestamp, TimeFormat};
+use crate::tracker::FrameRecord;
+use chrono::{SecondsFormat, Utc};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
+use std::ptr;
+
+#[allow(non_camel_case_types)]
+enum sqlite3 {}
+#[allow(non_camel_case_types)]
+enum sqlite3_stmt {}
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_INTEGER: c_int = 1;
+const SQLITE_FLOAT: c_int = 2;
+const SQLITE_TEXT: c_int = 3;
+const SQLITE_BLOB: c_int = 4;
+const SQLITE_OPEN_READONLY: c_int = 0x01;
+const SQLITE_OPEN_READWRITE: c_int = 0x02;
+const SQLITE_OPEN_CREATE: c_int = 0x04;
+/// Destructor telling SQLite to copy bound values, which it defines as a function pointer of -1
+const SQLITE_TRANSIENT: isize = -1;
+/// How long to wait for a lock held by another connection before failing (milliseconds)
+const BUSY_TIMEOUT_MS: c_int = 5000;
+/// Schema of the table frames are recorded to, matching the columns read from Postgres. Times
+/// are stored as RFC 3339 text, which sorts in time order.
+const FRAME_SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS pings (timestamp TEXT NOT NULL, data BLOB NOT NULL)",
+    "CREATE INDEX IF NOT EXISTS pings_timestamp ON pings (timestamp)",
+];
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut sqlite3,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close(db: *mut sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
+    fn sqlite3_busy_timeout(db: *mut sqlite3, ms: c_int) -> c_int;
+    fn sqlite3_prepare_v2(
+        db: *mut sqlite3,
+        sql: *const c_char,
+        len: c_int,
+        stmt: *mut *mut sqlite3_stmt,
+        tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_finalize(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_reset(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_step(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_bind_text(
+        stmt: *mut sqlite3_stmt,
+        index: c_int,
+        text: *const c_char,
+        len: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_bind_blob(
+        stmt: *mut sqlite3_stmt,
+        index: c_int,
+        data: *const c_void,
+        len: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_column_type(stmt: *mut sqlite3_stmt, column: c_int) -> c_int;
+    fn sqlite3_column_int64(stmt: *mut sqlite3_stmt, column: c_int) -> i64;
+    fn sqlite3_column_double(stmt: *mut sqlite3_stmt, column: c_int) -> f64;
+    fn sqlite3_column_text(stmt: *mut sqlite3_stmt, column: c_int) -> *const u8;
+    fn sqlite3_column_blob(stmt: *mut sqlite3_stmt, column: c_int) -> *const c_void;
+    fn sqlite3_column_bytes(stmt: *mut sqlite3_stmt, column: c_int) -> c_int;
+}
+
+/// Error reported by SQLite, with its result code
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqliteError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl fmt::Display for SqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SQLite error {}: {}", self.code, self.message)
+    }
+}
+
+impl Error for SqliteError {}
+
+impl SqliteError {
+    fn new(message: String) -> Self {
+        SqliteError { code: 0, message }
+    }
+}
+
+/// Database of frames recorded with the time each was received, in a `pings` table with the
+/// same columns as the Postgres one
+pub struct FrameDatabase {
+    connection: SqliteConnection,
+}
+
+impl FrameDatabase {
+    /// Open a database to record frames to, creating it and its table if needed. Write-ahead
+    /// logging lets the database be read, such as to replay it, while recording.
+    pub fn create(path: &Path) -> Result<Self, SqliteError> {
+        let connection = SqliteConnection::open(path)?;
+        connection.execute("PRAGMA journal_mode = WAL")?;
+        for statement in FRAME_SCHEMA {
+            connection.execute(statement)?;
+        }
+        Ok(FrameDatabase { connection })
+    }
+
+    /// Open an existing database to replay frames from
+    pub fn open_read_only(path: &Path) -> Result<Self, SqliteError> {
+        let connection = SqliteConnection::open_read_only(path)?;
+        Ok(FrameDatabase { connection })
+    }
+
+    /// Insert frames in a single transaction, so that recording keeps up with high message
+    /// rates
+    pub fn record(&self, frames: &[FrameRecord]) -> Result<(), SqliteError> {
+        let mut insert = self
+            .connection
+            .prepare("INSERT INTO pings (timestamp, data) VALUES (?1, ?2)")?;
+        self.connection.execute("BEGIN")?;
+        for FrameRecord { time, data } in frames {
+            insert.bind_text(1, &time.to_rfc3339_opts(SecondsFormat::Micros, true))?;
+            insert.bind_blob(2, data)?;
+            insert.step()?;
+        }
+        self.connection.execute("COMMIT")
+    }
+
+    /// Count the rows a query returns, if SQLite can
+    pub fn count(&self, query: &str) -> Result<Option<u64>, SqliteError> {
+        let query = query.trim().trim_end_matches(';');
+        let mut count = self
+            .connection
+            .prepare(&format!("SELECT count(*) FROM ({})", query))?;
+        match (count.step()?, count.column(0)) {
+            (true, SqliteValue::Integer(total)) => Ok(Some(total as u64)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Run a query returning the time each frame was received and the frame, passing each to
+    /// `replay` until it returns `false`. The time can be text in RFC 3339 or SQLite's
+    /// `YYYY-MM-DD HH:MM:SS` format, or seconds since the Unix epoch, and the frame must be a
+    /// blob.
+    pub fn replay(
+        &self,
+        query: &str,
+        mut replay: impl FnMut(FrameRecord) -> bool,
+    ) -> Result<(), SqliteError> {
+        let mut rows = self
+            .connection
+            .prepare(query.trim().trim_end_matches(';'))?;
+        while rows.step()? {
+            let timestamp = rows.column(0);
+            let time = frame_time(&timestamp)
+                .ok_or_else(|| SqliteError::new(format!("invalid timestamp {:?}", timestamp)))?;
+            let data = match rows.column(1) {
+                SqliteValue::Blob(data) => data,
+                value => {
+                    let message = format!("expected a blob for the frame, got {:?}", value);
+                    return Err(SqliteError::new(message));
+                }
+            };
+            if !replay(FrameRecord { time, data }) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Interpret a time read from SQLite, which has no type of its own for them, as for timestamped
+/// input in the auto format or in the format of SQLite's date and time functions
+fn frame_time(value: &SqliteValue) -> Option<chrono::DateTime<Utc>> {
+    let text = match value {
+        SqliteValue::Text(text) => text.clone(),
+        SqliteValue::Integer(number) => number.to_string(),
+        SqliteValue::Real(number) => number.to_string(),
+        _ => return None,
+    };
+    parse_timestamp(&text, TimeFormat::Auto).or_else(|| {
+        chrono::NaiveDateTime::parse_from_str(&text, "%Y-%m-%d %H:%M:%S%.f")
+            .ok()
+            .map(|time| chrono::DateTime::from_utc(time, Utc))
+    })
+}
+
+/// Value of a column in a result row
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SqliteValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Connection to an SQLite database file
+pub(crate) struct SqliteConnection {
+    db: *mut sqlite3,
+}
+
+impl SqliteConnection {
+    /// Open a database, creating it if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self, SqliteError> {
+        SqliteConnection::open_with_flags(path, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)
+    }
+
+    /// Open an existing database for reading only
+    pub fn open_read_only(path: &Path) -> Result<Self, SqliteError> {
+        SqliteConnection::open_with_flags(path, SQLITE_OPEN_READONLY)
+    }
+
+    fn open_with_flags(path: &Path, flags: c_int) -> Result<Self, SqliteError> {
+        let filename = c_string(&path.to_string_lossy())?;
+        let mut db = ptr::null_mut();
+        let code = unsafe { sqlite3_open_v2(filename.as_ptr(), &mut db, flags, ptr::null()) };
+        // A handle is returned even on failure, to get the error from and then close
+        let connection = SqliteConnection { db };
+        if db.is_null() {
+            return Err(SqliteError {
+                code,
+                message: "out of memory".to_string(),
+            });
+        }
+        connection.check(code)?;
+        unsafe { sqlite3_busy_timeout(db, BUSY_TIMEOUT_MS) };
+        Ok(connection)
+    }
+
+    /// Run a statement to completion, ignoring any rows it returns
+    pub fn execute(&self, sql: &str) -> Result<(), SqliteError> {
+        let mut statement = self.prepare(sql)?;
+        while statement.step()? {}
+        Ok(())
+    }
+
+    /// Compile a single statement, whose parameters are numbered from 1
+    pub fn prepare(&self, sql: &str) -> Result<SqliteStatement<'_>, SqliteError> {
+        let sql = c_string(sql)?;
+        let mut stmt = ptr::null_mut();
+        let code =
+            unsafe { sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) };
+        self.check(code)?;
+        if stmt.is_null() {
+            return Err(SqliteError {
+                code,
+                message: "no statement given".to_string(),
+            });
+        }
+        Ok(SqliteStatement {
+            connection: self,
+            stmt,
+        })
+    }
+
+    fn check(&self, code: c_int) -> Result<(), SqliteError> {
+        if code == SQLITE_OK {
+            return Ok(());
+        }
+        let message = unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) };
+        Err(SqliteError {
+            code,
+            message: message.to_string_lossy().into_owned(),
+        })
+    }
+}
+
+impl Drop for SqliteConnection {
+    fn drop(&mut self) {
+        unsafe { sqlite3_close(self.db) };
+    }
+}
+
+/// A compiled statement, which can be run repeatedly with different parameters
+pub(crate) struct SqliteStatement<'a> {
+    connection: &'a SqliteConnection,
+    stmt: *mut sqlite3_stmt,
+}
+
+impl SqliteStatement<'_> {
+    pub fn bind_text(&mut self, index: usize, text: &str) -> Result<(), SqliteError> {
+        let code = unsafe {
+            sqlite3_bind_text(
+                self.stmt,
+                index as c_int,
+                text.as_ptr() as *const c_char,
+                length(text.len())?,
+                SQLITE_TRANSIENT,
+            )
+        };
+        self.connection.check(code)
+    }
+
+    pub fn bind_blob(&mut self, index: usize, data: &[u8]) -> Result<(), SqliteError> {
+        let code = unsafe {
+            sqlite3_bind_blob(
+                self.stmt,
+                index as c_int,
+                data.as_ptr() as *const c_void,
+                length(data.len())?,
+                SQLITE_TRANSIENT,
+            )
+        };
+        self.connection.check(code)
+    }
+
+    /// Run the statement up to its next row. Returns `false` once it's done, after which it can
+    /// be run again, with the same parameters unless others are bound.
+    pub fn step(&mut self) -> Result<bool, SqliteError> {
+        match unsafe { sqlite3_step(self.stmt) } {
+            SQLITE_ROW => Ok(true),
+            SQLITE_DONE => {
+                unsafe { sqlite3_reset(self.stmt) };
+                Ok(false)
+            }
+            // The error is only reported once the statement is reset
+            _ => {
+                let code = unsafe { sqlite3_reset(self.stmt) };
+                self.connection.check(code).and(Err(SqliteError {
+                    code,
+                    message: "statement failed".to_string(),
+                }))
+            }
+        }
+    }
+
+    /// Get a column of the current row, numbered from 0
+    pub fn column(&self, column: usize) -> SqliteValue {
+        let (stmt, column) = (self.stmt, column as c_int);
+        unsafe {
+            match sqlite3_column_type(stmt, column) {
+                SQLITE_INTEGER => SqliteValue::Integer(sqlite3_column_int64(stmt, column)),
+                SQLITE_FLOAT => SqliteValue::Real(sqlite3_column_double(stmt, column)),
+                SQLITE_TEXT => {
+                    let text = sqlite3_column_text(stmt, column);
+                    let len = sqlite3_column_bytes(stmt, column) as usize;
+                    let bytes = std::slice::from_raw_parts(text, len);
+                    SqliteValue::Text(String::from_utf8_lossy(bytes).into_owned())
+                }
+                SQLITE_BLOB => {
+                    let data = sqlite3_column_blob(stmt, column) as *const u8;
+                    let len = sqlite3_column_bytes(stmt, column) as usize;
+                    // An empty blob comes back as a null pointer
+                    if data.is_null() {
+                        SqliteValue::Blob(Vec::new())
+                    } else {
+                        SqliteValue::Blob(std::slice::from_raw_parts(data, len).to_vec())
+                    }
+                }
+                _ => SqliteValue::Null,
+            }
+        }
+    }
+}
+
+impl Drop for SqliteStatement<'_> {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.stmt) };
+    }
+}
+
+fn c_string(s: &str) -> Result<CString, SqliteError> {
+    CString::new(s).map_err(|_| SqliteError::new(format!("'{}' contains a NUL character", s)))
+}
+
+fn length(len: usize) -> Result<c_int, SqliteError> {
+    c_int::try_from(len)
+        .map_err(|_| SqliteError::new(format!("value of {} bytes is too long", len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_written_and_read_back() {
+        let path = std::env::temp_dir().join(format!("tracker-{}.sqlite", std::process::id()));
+        let connection = SqliteConnection::open(&path).unwrap();
+        connection
+            .execute("CREATE TABLE pings (timestamp TEXT, data BLOB)")
+            .unwrap();
+        let mut insert = connection
+            .prepare("INSERT INTO pings VALUES (?1, ?2)")
+            .unwrap();
+        for (timestamp, data) in &[("a", &b"\x8D\x48"[..]), ("b", &b""[..])] {
+            insert.bind_text(1, timestamp).unwrap();
+            insert.bind_blob(2, data).unwrap();
+            assert!(!insert.step().unwrap());
+        }
+        drop(insert);
+        let reader = SqliteConnection::open_read_only(&path).unwrap();
+        let mut select = reader
+            .prepare("SELECT timestamp, data, 1, 0.5, NULL FROM pings ORDER BY rowid")
+            .unwrap();
+        assert!(select.step().unwrap());
+        assert_eq!(select.column(0), SqliteValue::Text("a".to_string()));
+        assert_eq!(select.column(1), SqliteValue::Blob(vec![0x8D, 0x48]));
+        assert_eq!(select.column(2), SqliteValue::Integer(1));
+        assert_eq!(select.column(3), SqliteValue::Real(0.5));
+        assert_eq!(select.column(4), SqliteValue::Null);
+        assert!(select.step().unwrap());
+        assert_eq!(select.column(1), SqliteValue::Blob(Vec::new()));
+        assert!(!select.step().unwrap());
+        let error = reader.execute("INSERT INTO pings VALUES ('c', x'00')");
+        assert!(error.is_err());
+        assert!(reader.prepare("SELECT nothing FROM pings").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frames_recorded_and_replayed() {
+        let name = format!("tracker-frames-{}.sqlite", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let time = parse_timestamp("2020-11-02T18:03:12.345Z", TimeFormat::Auto).unwrap();
+        let frames = vec![
+            FrameRecord {
+                time,
+                data: vec![0x8D, 0x48],
+            },
+            FrameRecord {
+                time: time + chrono::Duration::milliseconds(1),
+                data: vec![0x5D],
+            },
+        ];
+        FrameDatabase::create(&path)
+            .unwrap()
+            .record(&frames)
+            .unwrap();
+        let database = FrameDatabase::open_read_only(&path).unwrap();
+        let query = "SELECT timestamp, data FROM pings ORDER BY timestamp;";
+        assert_eq!(database.count(query).unwrap(), Some(2));
+        let mut replayed = Vec::new();
+        database
+            .replay(query, |frame| {
+                replayed.push(frame);
+                true
+            })
+            .unwrap();
+        assert_eq!(replayed, frames);
+        let sqlite_time = "SELECT '2020-11-02 18:03:12.345', data FROM pings LIMIT 1";
+        database
+            .replay(sqlite_time, |frame| {
+                assert_eq!(frame.time, time);
+                false
+            })
+            .unwrap();
+        assert!(database
+            .replay("SELECT 'soon', data FROM pings", |_| true)
+            .is_err());
+        assert!(database
+            .replay("SELECT timestamp, 1 FROM pings", |_| true)
+            .is_err());
+        drop(database);
+        for suffix in &["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
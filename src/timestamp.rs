@@ -0,0 +1,167 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+/// Numeric timestamps with a magnitude at or above this value are taken to be milliseconds since
+/// the Unix epoch, anything below it seconds. 10^11 seconds is beyond the year 5000 while 10^11
+/// milliseconds is in March 1973, so any recorded timestamp falls clearly on one side: current
+/// times are 10 digits long in seconds and 13 digits long in milliseconds.
+pub const EPOCH_MILLIS_CUTOFF: f64 = 1e11;
+
+/// Format of the timestamps prefixed to recorded frames
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimeFormat {
+    /// Detect the format from each timestamp
+    #[default]
+    Auto,
+    /// Seconds since the Unix epoch, optionally with a fractional part
+    EpochSeconds,
+    /// Milliseconds since the Unix epoch
+    EpochMillis,
+    /// RFC 3339 date and time, e.g. `2020-11-02T18:03:12.345Z`
+    Rfc3339,
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(TimeFormat::Auto),
+            "epoch-s" => Ok(TimeFormat::EpochSeconds),
+            "epoch-ms" => Ok(TimeFormat::EpochMillis),
+            "rfc3339" => Ok(TimeFormat::Rfc3339),
+            _ => Err(format!(
+                "unknown time format '{}' (expected auto, epoch-s, epoch-ms or rfc3339)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TimeFormat::Auto => "auto",
+            TimeFormat::EpochSeconds => "epoch-s",
+            TimeFormat::EpochMillis => "epoch-ms",
+            TimeFormat::Rfc3339 => "rfc3339",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parse a timestamp in the given format. In `TimeFormat::Auto` mode numeric values are told
+/// apart using `EPOCH_MILLIS_CUTOFF` and anything else is parsed as RFC 3339.
+pub fn parse_timestamp(value: &str, format: TimeFormat) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    match format {
+        TimeFormat::Auto => match value.parse::<f64>() {
+            Ok(number) if number.abs() >= EPOCH_MILLIS_CUTOFF => from_epoch_millis(number),
+            Ok(number) => from_epoch_millis(number * 1000.0),
+            Err(_) => parse_timestamp(value, TimeFormat::Rfc3339),
+        },
+        TimeFormat::EpochSeconds => from_epoch_millis(value.parse::<f64>().ok()? * 1000.0),
+        TimeFormat::EpochMillis => from_epoch_millis(value.parse::<f64>().ok()?),
+        TimeFormat::Rfc3339 => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+    }
+}
+
+fn from_epoch_millis(millis: f64) -> Option<DateTime<Utc>> {
+    if !millis.is_finite() {
+        return None;
+    }
+    Utc.timestamp_millis_opt(millis.round() as i64).single()
+}
+
+/// Split a recorded line of the form `timestamp,frame` (or separated by whitespace) and parse its
+/// timestamp. Returns `None` if the line has no separator or the timestamp can't be parsed.
+pub fn parse_timestamped_line(line: &str, format: TimeFormat) -> Option<(DateTime<Utc>, &str)> {
+    let line = line.trim();
+    let (timestamp, frame) = line.split_once(|c: char| c == ',' || c.is_whitespace())?;
+    let time = parse_timestamp(timestamp, format)?;
+    Some((time, frame.trim()))
+}
+
+/// Get the offset which shifts the timestamps of recorded lines so that the latest one is `now`,
+/// or `None` if none of the lines has a timestamp. Adding the same offset to every timestamp keeps
+/// the intervals between them.
+pub fn offset_to_end_at<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+    format: TimeFormat,
+    now: DateTime<Utc>,
+) -> Option<Duration> {
+    let latest = lines
+        .into_iter()
+        .filter_map(|line| parse_timestamped_line(line, format))
+        .map(|(time, _)| time)
+        .max()?;
+    Some(now - latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_auto_detected_formats() {
+        let expected = Utc.timestamp_millis(1_604_340_192_345);
+        let seconds = parse_timestamp("1604340192.345", TimeFormat::Auto);
+        let millis = parse_timestamp("1604340192345", TimeFormat::Auto);
+        let rfc3339 = parse_timestamp("2020-11-02T18:03:12.345Z", TimeFormat::Auto);
+        assert_eq!(seconds, Some(expected));
+        assert_eq!(millis, Some(expected));
+        assert_eq!(rfc3339, Some(expected));
+    }
+
+    #[test]
+    fn parse_forced_format() {
+        let time = parse_timestamp("1604340192345", TimeFormat::EpochSeconds);
+        assert_eq!(time.map(|t| t.timestamp()), Some(1_604_340_192_345));
+        let time = parse_timestamp("1604340192", TimeFormat::EpochMillis);
+        assert_eq!(time, Some(Utc.timestamp_millis(1_604_340_192)));
+        let time = parse_timestamp("1604340192", TimeFormat::Rfc3339);
+        assert_eq!(time, None);
+    }
+
+    #[test]
+    fn parse_line() {
+        let line = "1604340192,*8D4840D6202CC371C32CE0576098;\n";
+        let (time, frame) = parse_timestamped_line(line, TimeFormat::Auto).unwrap();
+        assert_eq!(time, Utc.timestamp(1_604_340_192, 0));
+        assert_eq!(frame, "*8D4840D6202CC371C32CE0576098;");
+        let line = "*8D4840D6202CC371C32CE0576098;";
+        assert!(parse_timestamped_line(line, TimeFormat::Auto).is_none());
+        // Any whitespace separates, including multi-byte characters such as a no-break space
+        let line = "1604340192\u{a0}*8D4840D6202CC371C32CE0576098;";
+        let (time, frame) = parse_timestamped_line(line, TimeFormat::Auto).unwrap();
+        assert_eq!(time, Utc.timestamp(1_604_340_192, 0));
+        assert_eq!(frame, "*8D4840D6202CC371C32CE0576098;");
+    }
+
+    #[test]
+    fn recording_shifted_to_end_now() {
+        let lines = [
+            "1604340192.5,*8D4840D6202CC371C32CE0576098;",
+            "garbage",
+            "1604340195,*8D40621D58C382D690C8AC2863A7;",
+            "1604340201.25,*8D40621D58C386435CC412692AD6;",
+        ];
+        let now = Utc.timestamp_millis(1_700_000_000_000);
+        let offset = offset_to_end_at(lines.iter().copied(), TimeFormat::Auto, now).unwrap();
+        let shifted: Vec<_> = lines
+            .iter()
+            .filter_map(|line| parse_timestamped_line(line, TimeFormat::Auto))
+            .map(|(time, _)| time + offset)
+            .collect();
+        assert_eq!(shifted[2], now);
+        assert_eq!(shifted[1] - shifted[0], Duration::milliseconds(2500));
+        assert_eq!(shifted[2] - shifted[1], Duration::milliseconds(6250));
+        assert_eq!(
+            offset_to_end_at(vec!["garbage"], TimeFormat::Auto, now),
+            None
+        );
+    }
+}
@@ -1,8 +1,86 @@
+use crate::beast::{BeastDecoder, BeastFrameKind};
+use crate::country::country_for_icao;
+use crate::dump1090::parse_aircraft_json;
+use crate::kml;
+use crate::modes::*;
+use crate::registry::RegistryLookup;
+use crate::sbs::{parse_sbs, SbsMessage};
 use adsb::*;
-use chrono::{Utc, Duration};
-use std::collections::HashMap;
+use chrono::{Utc, Duration, SecondsFormat};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 use MessageKind::*;
 
+mod state;
+
+/// Length of the window used for the rolling message rate (seconds)
+const RATE_WINDOW_SECS: i64 = 10;
+/// Number of consecutive identification messages needed before a callsign is accepted
+const CALLSIGN_CONFIRMATIONS: u32 = 2;
+/// Length of the heading history used to detect orbits (minutes)
+const ORBIT_WINDOW_MINS: i64 = 15;
+/// Minimum interval between headings kept in the orbit history (seconds)
+const ORBIT_SAMPLE_INTERVAL_SECS: i64 = 5;
+/// Maximum distance from the center of an orbit for positions within it (nautical miles)
+const ORBIT_MAX_RADIUS_NM: f64 = 5.0;
+/// Largest standard deviation of the distances from the center of an orbit, as a fraction of
+/// their mean, for the radius to count as roughly constant. Racetrack holds are well within it.
+const ORBIT_MAX_RADIUS_SPREAD: f64 = 0.5;
+/// Default maximum number of points kept in an aircraft's track
+const DEFAULT_MAX_TRACK_POINTS: usize = 500;
+/// Default maximum distance from the receiver of a plausible position (nautical miles)
+const DEFAULT_MAX_RANGE_NM: f64 = 400.0;
+/// Maximum range for which a lone airborne frame is decoded against the receiver's position
+/// (nautical miles). Local decoding is only unambiguous within about 180 nm of the reference, so
+/// beyond that an aircraft could decode to a wrong position well within range.
+const LOCAL_DECODE_MAX_RANGE_NM: f64 = 180.0;
+/// Maximum speed implied by the distance from an aircraft's last position to a new one for the
+/// new one to be plausible (knots)
+const MAX_PLAUSIBLE_SPEED_KT: f64 = 1000.0;
+/// Shortest interval the speed between positions is measured over (seconds), as positions can
+/// come in bunched together, such as when replayed with coarse timestamps, and decoding jitters
+/// slightly
+const PLAUSIBILITY_MIN_INTERVAL_SECS: i64 = 10;
+/// Maximum age of an aircraft's last position for it to be used as the reference to resolve a
+/// single CPR frame against, or to check the plausibility of a new position (seconds). Even a
+/// fast aircraft stays well within the 180 nautical miles allowed for the former in this time.
+const LOCAL_CPR_MAX_AGE_SECS: i64 = 300;
+/// Default maximum time between the even and odd CPR frames paired to decode a position
+/// (seconds)
+const DEFAULT_CPR_WINDOW_SECS: i64 = 10;
+/// Maximum difference between the reported ground speed and the one computed from the last two
+/// positions for them to be consistent (knots)
+const GROUND_SPEED_MAX_DISCREPANCY_KT: f64 = 100.0;
+/// Range of intervals between the last two positions over which the computed ground speed is
+/// compared against the reported one (seconds). Closer positions are dominated by decoding
+/// jitter, and over longer intervals the aircraft may have turned or changed speed.
+const GROUND_SPEED_CHECK_MIN_SECS: i64 = 1;
+const GROUND_SPEED_CHECK_MAX_SECS: i64 = 60;
+/// Mean radius of the Earth (nautical miles)
+const EARTH_RADIUS_NM: f64 = 3440.065;
+/// ISA temperature at sea level (kelvin), the lapse rate up to the tropopause (kelvin per foot)
+/// and the altitude of the tropopause (feet), above which the temperature is constant
+const ISA_SEA_LEVEL_TEMPERATURE: f64 = 288.15;
+const ISA_LAPSE_RATE: f64 = 0.0019812;
+const ISA_TROPOPAUSE_FT: f64 = 36_089.0;
+/// Radius around the home airport within which arrivals and departures are tagged (nautical
+/// miles)
+const HOME_AIRPORT_RADIUS_NM: f64 = 10.0;
+/// Maximum height above the home airport for arrivals and departures (feet)
+const HOME_AIRPORT_MAX_HEIGHT_FT: f64 = 5000.0;
+/// Minimum rate of climb or descent for arrivals and departures (feet per minute)
+const MOVEMENT_MIN_VERTICAL_RATE: i16 = 300;
+/// Period over which the change in distance to the home airport is measured (seconds)
+const MOVEMENT_TREND_SECS: i64 = 60;
+/// Minimum change in distance to the home airport over that period (nautical miles)
+const MOVEMENT_MIN_DISTANCE_CHANGE_NM: f64 = 0.5;
+/// Speed of sound per square root of the air temperature (knots per square root of a kelvin)
+const SPEED_OF_SOUND_FACTOR: f64 = 38.967854;
+
 /// A tracked aircraft
 #[derive(Debug, Clone)]
 pub struct Aircraft {
@@ -10,24 +88,397 @@ pub struct Aircraft {
     pub icao_address: ICAOAddress,
     /// Current aircraft callsign
     pub callsign: Option<String>,
-    /// Current altitude (feet)
-    pub altitude: Option<u16>,
-    /// Current heading (degrees)
+    /// National registration (tail number), if known to the tracker's registry
+    pub registration: Option<String>,
+    /// ICAO type designator, if known to the tracker's registry
+    pub aircraft_type: Option<String>,
+    /// Country the ICAO address was allocated to
+    pub country: Option<&'static str>,
+    /// Type of aircraft or vehicle, as reported in identification messages
+    pub emitter_category: Option<EmitterCategory>,
+    /// Current barometric altitude (feet)
+    pub altitude: Option<i32>,
+    /// Current geometric (GNSS) altitude (feet)
+    pub geometric_altitude: Option<i32>,
+    /// Current heading (degrees, from 0 up to but not including 360)
     pub heading: Option<f64>,
+    /// Reference direction of the current heading. Ground track from ADS-B velocity messages is
+    /// always relative to true north, while headings reported with airspeed are magnetic.
+    pub heading_reference: Option<HeadingReference>,
     /// Current ground speed (knots)
     pub ground_speed: Option<f64>,
+    /// Current true airspeed (knots), for aircraft which report it
+    pub true_airspeed: Option<f64>,
+    /// Current indicated airspeed (knots), from Comm-B heading and speed reports
+    pub indicated_airspeed: Option<f64>,
+    /// Current Mach number, from Comm-B heading and speed reports. See `mach()` for one which
+    /// falls back on an estimate.
+    pub mach: Option<f64>,
+    /// Current roll angle, positive for right wing down (degrees), from Comm-B track and turn
+    /// reports
+    pub roll_angle: Option<f64>,
+    /// Current rate of turn, positive when turning right (degrees per second), from Comm-B
+    /// track and turn reports
+    pub track_angle_rate: Option<f64>,
+    /// Current wind speed (knots), from Comm-B meteorological reports
+    pub wind_speed: Option<f64>,
+    /// Direction the wind is blowing from, relative to true north (degrees), from Comm-B
+    /// meteorological reports
+    pub wind_direction: Option<f64>,
+    /// Current static air temperature (degrees Celsius), from Comm-B meteorological reports
+    pub static_air_temperature: Option<f64>,
     /// Current vertical rate (feet per minute)
     pub vertical_rate: Option<i16>,
+    /// Current squawk
+    pub squawk: Option<Squawk>,
+    /// Altitude selected on the autopilot (feet)
+    pub selected_altitude: Option<i32>,
+    /// Heading selected on the autopilot (degrees)
+    pub selected_heading: Option<f64>,
+    /// Barometric pressure setting (hPa)
+    pub pressure_setting: Option<f64>,
     /// Current latitude (degrees)
     pub latitude: Option<f64>,
     /// Current longitude (degrees)
     pub longitude: Option<f64>,
     /// Source for vertical rate information
     pub vertical_rate_source: Option<VerticalRateSource>,
+    /// Altitude reported along with the current position (feet)
+    pub position_altitude: Option<i32>,
+    /// Whether the last position message was a surface rather than an airborne one
+    pub on_ground: Option<bool>,
+    /// Aircraft has turned at least a full circle in the same direction within a small area
+    /// during the last few minutes, e.g. flying a holding pattern or orbiting a point
+    pub is_orbiting: bool,
+    /// Timestamp for first received message
+    pub first_seen: chrono::DateTime<Utc>,
+    /// Timestamp for last received message
+    pub last_seen: chrono::DateTime<Utc>,
+    /// Number of messages received from the aircraft
+    pub num_messages: u64,
+    last_cpr_even: Option<PositionFrame>,
+    last_cpr_odd: Option<PositionFrame>,
+    heading_history: VecDeque<HeadingSample>,
+    /// Messages received in each second (by message time) of the last `RATE_WINDOW_SECS`
+    recent_message_counts: VecDeque<(i64, u64)>,
+    pending_callsign: Option<(String, u32)>,
+    track: Vec<TrackPoint>,
+    callsigns: Vec<String>,
+    max_altitude: Option<i32>,
+    max_ground_speed: Option<f64>,
+    last_logged_position: Option<chrono::DateTime<Utc>>,
+}
+
+/// Result of handling a CPR frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PositionUpdate {
+    /// No frame of the opposite parity has been received yet
+    Waiting,
+    /// A new position was decoded
+    Decoded,
+    /// A new position was decoded from a single frame relative to a reference position
+    DecodedLocally,
+    /// The pair of frames didn't decode to a position
+    Failed,
+    /// The decoded position was implausible, so it was discarded
+    Rejected,
+}
+
+/// Tracker settings used when decoding positions
+struct PositionDecoding<'a> {
+    /// Maximum time between the paired even and odd frames
+    window: Duration,
+    /// Location of the receiver, if known
+    receiver: Option<&'a Position>,
+    /// Maximum distance from the receiver of a plausible position (nautical miles)
+    max_range: f64,
+}
+
+/// Airport whose arrivals and departures are tagged
+#[derive(Debug, Clone, PartialEq)]
+pub struct Airport {
+    pub position: Position,
+    /// Elevation above sea level (feet)
+    pub elevation: f64,
+}
+
+impl FromStr for Airport {
+    type Err = String;
+
+    /// Parse `LAT,LON` or `LAT,LON,ELEVATION` (degrees and feet)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid airport '{}' (expected LAT,LON[,ELEVATION])", s);
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>().map_err(|_| error()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (latitude, longitude, elevation) = match values.as_slice() {
+            [latitude, longitude] => (*latitude, *longitude, 0.0),
+            [latitude, longitude, elevation] => (*latitude, *longitude, *elevation),
+            _ => return Err(error()),
+        };
+        if latitude.abs() > 90.0 || longitude.abs() > 180.0 {
+            return Err(error());
+        }
+        Ok(Airport {
+            position: Position {
+                latitude,
+                longitude,
+            },
+            elevation,
+        })
+    }
+}
+
+/// Likely movement of an aircraft at the home airport
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Movement {
+    Arrival,
+    Departure,
+}
+
+/// An aircraft heard for the first time, or again after a long silence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acquisition {
+    pub icao_address: ICAOAddress,
+    /// Time of the message the aircraft was acquired with
+    pub time: chrono::DateTime<Utc>,
+    /// Aircraft was already known but had been silent for longer than the reacquisition interval
+    pub reacquired: bool,
+}
+
+/// Agreement between an aircraft's positions and its reported ground speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PositionQuality {
+    Consistent,
+    /// The speed between the last two positions is far from the reported ground speed, as
+    /// happens with a bad CPR decode or spoofed data
+    Inconsistent,
+}
+
+/// Speed as a fraction of the speed of sound
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mach {
+    pub number: f64,
+    /// Calculated from ground speed rather than true airspeed
+    pub approximate: bool,
+}
+
+/// Aircraft with different addresses broadcasting the same callsign at the same time, which is
+/// either a callsign reused too soon or a spoofed one
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallsignConflict {
+    pub callsign: String,
+    /// Addresses of the aircraft sharing the callsign, in ascending order
+    pub icao_addresses: Vec<ICAOAddress>,
+}
+
+/// Something that happened to a tracked aircraft, passed to the callback set with
+/// `Tracker::set_on_event`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackerEvent {
+    /// A message was received from an aircraft not being tracked
+    NewAircraft {
+        icao_address: ICAOAddress,
+        time: chrono::DateTime<Utc>,
+    },
+    /// A new position was decoded
+    PositionUpdate(PositionRecord),
+    /// A new callsign was confirmed
+    CallsignUpdate {
+        icao_address: ICAOAddress,
+        callsign: String,
+        time: chrono::DateTime<Utc>,
+    },
+    /// An aircraft was removed by `Tracker::remove_stale`
+    Expired {
+        icao_address: ICAOAddress,
+        last_seen: chrono::DateTime<Utc>,
+    },
+}
+
+/// Callback receiving the events of a tracker
+pub type EventCallback = Box<dyn FnMut(TrackerEvent) + Send>;
+
+/// Source of the current time, used by a tracker for its message rates and for which aircraft
+/// are current
+pub type Clock = Box<dyn Fn() -> chrono::DateTime<Utc> + Send>;
+
+/// Counts of the outcomes of position decoding
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PositionStatistics {
+    /// Frames which produced a new position
+    pub decoded: u64,
+    /// Of the frames which produced a new position, those resolved on their own relative to a
+    /// reference position rather than paired with a frame of the opposite parity
+    pub local: u64,
+    /// Frames which together with the last frame of the opposite parity failed to decode
+    pub failed: u64,
+    /// Frames received before any frame of the opposite parity to pair them with, and without a
+    /// reference position to resolve them against
+    pub waiting: u64,
+    /// Frames which decoded to an implausible position: too far from the receiver, or too far
+    /// from the aircraft's last position to have flown there in the time since
+    pub rejected: u64,
+}
+
+/// Counts of the frames which couldn't be used, by the reason why
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorStatistics {
+    /// Frames which weren't in AVR format
+    pub invalid_avr: u64,
+    /// Frames which weren't the length of a short or long Mode-S message
+    pub invalid_length: u64,
+    /// Extended squitters whose parity didn't match their CRC
+    pub crc: u64,
+    /// Frames the `adsb` crate failed to parse
+    pub parser: u64,
+    /// Messages of a downlink format or type which the tracker doesn't use. These are also
+    /// counted as unknown messages, by downlink format.
+    pub unsupported: u64,
+}
+
+/// A decoded position in an aircraft's track
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackPoint {
+    /// Time the position was received
+    pub time: chrono::DateTime<Utc>,
+    /// Latitude (degrees)
+    pub latitude: f64,
+    /// Longitude (degrees)
+    pub longitude: f64,
+    /// Altitude reported along with the position (feet)
+    pub altitude: Option<i32>,
+}
+
+/// A heading received in a velocity message and the position known at the time
+#[derive(Debug, Clone)]
+struct HeadingSample {
+    time: chrono::DateTime<Utc>,
+    heading: f64,
+    position: Option<(f64, f64)>,
+}
+
+/// An owned copy of the state of an aircraft at some point in time, without any of the internal
+/// decoding state, which can be passed to other threads once the tracker is unlocked
+#[derive(Debug, Clone)]
+pub struct AircraftSnapshot {
+    /// Unique 24-bit ICAO address assigned to an aircraft upon national registration
+    pub icao_address: ICAOAddress,
+    /// Current aircraft callsign
+    pub callsign: Option<String>,
+    /// National registration (tail number), if known to the tracker's registry
+    pub registration: Option<String>,
+    /// ICAO type designator, if known to the tracker's registry
+    pub aircraft_type: Option<String>,
+    /// Country the ICAO address was allocated to
+    pub country: Option<&'static str>,
+    /// Type of aircraft or vehicle, as reported in identification messages
+    pub emitter_category: Option<EmitterCategory>,
+    /// Current barometric altitude (feet)
+    pub altitude: Option<i32>,
+    /// Current geometric (GNSS) altitude (feet)
+    pub geometric_altitude: Option<i32>,
+    /// Current heading (degrees, from 0 up to but not including 360)
+    pub heading: Option<f64>,
+    /// Reference direction of the current heading. Ground track from ADS-B velocity messages is
+    /// always relative to true north, while headings reported with airspeed are magnetic.
+    pub heading_reference: Option<HeadingReference>,
+    /// Current ground speed (knots)
+    pub ground_speed: Option<f64>,
+    /// Current true airspeed (knots), for aircraft which report it
+    pub true_airspeed: Option<f64>,
+    /// Current vertical rate (feet per minute)
+    pub vertical_rate: Option<i16>,
+    /// Source for vertical rate information
+    pub vertical_rate_source: Option<VerticalRateSource>,
+    /// Current squawk
+    pub squawk: Option<Squawk>,
+    /// Altitude selected on the autopilot (feet)
+    pub selected_altitude: Option<i32>,
+    /// Heading selected on the autopilot (degrees)
+    pub selected_heading: Option<f64>,
+    /// Barometric pressure setting (hPa)
+    pub pressure_setting: Option<f64>,
+    /// Current position
+    pub position: Option<Position>,
+    /// Altitude reported along with the current position (feet)
+    pub position_altitude: Option<i32>,
+    /// Whether the last position message was a surface rather than an airborne one
+    pub on_ground: Option<bool>,
+    /// Aircraft is flying a holding pattern or orbiting a point
+    pub is_orbiting: bool,
+    /// Timestamp for last received message
+    pub last_seen: chrono::DateTime<Utc>,
+    /// Time elapsed since the last received message, as of when the snapshot was taken
+    pub seen: Duration,
+}
+
+/// Everything observed of an aircraft between it being acquired and being removed from the
+/// tracker, as a record of a single flight
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlightSummary {
+    pub icao_address: ICAOAddress,
+    /// Distinct callsigns used, in the order they were first confirmed
+    pub callsigns: Vec<String>,
+    /// Highest altitude reported (feet)
+    pub max_altitude: Option<i32>,
+    /// Highest ground speed reported (knots)
+    pub max_ground_speed: Option<f64>,
+    /// Timestamp for first received message
+    pub first_seen: chrono::DateTime<Utc>,
     /// Timestamp for last received message
     pub last_seen: chrono::DateTime<Utc>,
-    last_cpr_even: Option<CPRFrame>,
-    last_cpr_odd: Option<CPRFrame>,
+    /// Decoded positions, as kept in the aircraft's track
+    pub track: Vec<TrackPoint>,
+}
+
+/// Header of the CSV lines written for position records
+pub const POSITION_CSV_HEADER: &str = "time,icao,lat,lon,alt";
+
+/// A frame in binary format, as recorded in the frame log
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameRecord {
+    /// Time the frame was received
+    pub time: chrono::DateTime<Utc>,
+    pub data: Vec<u8>,
+}
+
+/// A decoded position of an aircraft, as recorded in the position log
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionRecord {
+    /// Time the position was received
+    pub time: chrono::DateTime<Utc>,
+    pub icao_address: ICAOAddress,
+    /// Latitude (degrees)
+    pub latitude: f64,
+    /// Longitude (degrees)
+    pub longitude: f64,
+    /// Altitude reported along with the position (feet)
+    pub altitude: Option<i32>,
+}
+
+impl PositionRecord {
+    /// Format the record as a CSV line matching `POSITION_CSV_HEADER`, with the time in RFC 3339
+    /// format and an empty altitude if it isn't known
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{:.5},{:.5},{}",
+            self.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.icao_address,
+            self.latitude,
+            self.longitude,
+            self.altitude.map(|a| a.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+/// A CPR frame along with the altitude reported in the same message and when it was received
+#[derive(Debug, Clone)]
+struct PositionFrame {
+    cpr_frame: CPRFrame,
+    altitude: Option<i32>,
+    time: chrono::DateTime<Utc>,
 }
 
 impl Aircraft {
@@ -35,162 +486,3059 @@ impl Aircraft {
         Aircraft {
             icao_address,
             callsign: None,
+            registration: None,
+            aircraft_type: None,
+            country: country_for_icao(&icao_address),
+            emitter_category: None,
             altitude: None,
+            geometric_altitude: None,
             heading: None,
+            heading_reference: None,
             ground_speed: None,
+            true_airspeed: None,
+            indicated_airspeed: None,
+            mach: None,
+            roll_angle: None,
+            track_angle_rate: None,
+            wind_speed: None,
+            wind_direction: None,
+            static_air_temperature: None,
             vertical_rate: None,
+            squawk: None,
+            selected_altitude: None,
+            selected_heading: None,
+            pressure_setting: None,
             latitude: None,
             longitude: None,
             vertical_rate_source: None,
+            position_altitude: None,
+            on_ground: None,
+            is_orbiting: false,
+            first_seen: time,
             last_seen: time,
+            num_messages: 0,
             last_cpr_even: None,
             last_cpr_odd: None,
+            heading_history: VecDeque::new(),
+            recent_message_counts: VecDeque::new(),
+            pending_callsign: None,
+            track: Vec::new(),
+            callsigns: Vec::new(),
+            max_altitude: None,
+            max_ground_speed: None,
+            last_logged_position: None,
         }
     }
 
-    fn update_position(&mut self, cpr_frame: CPRFrame) {
-        let last_parity = cpr_frame.parity.clone();
-        match last_parity {
-            Parity::Even => {
-                self.last_cpr_even = Some(cpr_frame);
+    /// Store a CPR frame and decode the position from it and the last frame of the other parity,
+    /// if that was received within the window of it. Failing that, the frame is resolved on its
+    /// own relative to the aircraft's recent position or else the receiver's, if known.
+    fn update_position(
+        &mut self,
+        cpr_frame: CPRFrame,
+        altitude: Option<i32>,
+        time: chrono::DateTime<Utc>,
+        decoding: &PositionDecoding,
+    ) -> PositionUpdate {
+        let frame = PositionFrame {
+            cpr_frame,
+            altitude,
+            time,
+        };
+        let other = match frame.cpr_frame.parity {
+            Parity::Even => &self.last_cpr_odd,
+            Parity::Odd => &self.last_cpr_even,
+        };
+        // The aircraft may have moved too far in between for the frames to match
+        let other = other.as_ref().filter(|other| {
+            let gap = time.signed_duration_since(other.time);
+            gap <= decoding.window && -gap <= decoding.window
+        });
+        let global =
+            other.and_then(|other| cpr::get_position((&other.cpr_frame, &frame.cpr_frame)));
+        let (position, update) = match global {
+            Some(position) => (Some(position), PositionUpdate::Decoded),
+            None => {
+                let local = self
+                    .recent_track_point(time)
+                    .map(|point| Position {
+                        latitude: point.latitude,
+                        longitude: point.longitude,
+                    })
+                    .or_else(|| {
+                        let in_range = decoding.max_range <= LOCAL_DECODE_MAX_RANGE_NM;
+                        decoding.receiver.filter(|_| in_range).cloned()
+                    })
+                    .and_then(|reference| local_airborne_position(&frame.cpr_frame, &reference));
+                match local {
+                    Some(position) => (Some(position), PositionUpdate::DecodedLocally),
+                    None if other.is_some() => (None, PositionUpdate::Failed),
+                    None => (None, PositionUpdate::Waiting),
+                }
             }
-            Parity::Odd => {
-                self.last_cpr_odd = Some(cpr_frame);
+        };
+        let update = match position {
+            Some(position) if !self.is_plausible(&position, time, decoding) => {
+                PositionUpdate::Rejected
+            }
+            Some(position) => {
+                self.latitude = Some(position.latitude);
+                self.longitude = Some(position.longitude);
+                // Keep the altitude of the last fix if this frame didn't carry one
+                if let Some(altitude) = frame.altitude {
+                    self.position_altitude = Some(altitude);
+                }
+                update
             }
+            None => update,
+        };
+        match frame.cpr_frame.parity {
+            Parity::Even => self.last_cpr_even = Some(frame),
+            Parity::Odd => self.last_cpr_odd = Some(frame),
         }
-        if let (Some(even), Some(odd)) = (&self.last_cpr_even, &self.last_cpr_odd) {
-            let position = match last_parity {
-                Parity::Even => cpr::get_position((&odd, &even)),
-                Parity::Odd => cpr::get_position((&even, &odd)),
-            };
-            if let Some(Position {
-                latitude,
-                longitude,
-            }) = position
+        update
+    }
+
+    /// Check a decoded position against the receiver's range and the aircraft's recent position.
+    /// Only a recent position is compared against, so that a wrong one which got through can't
+    /// cause later positions to be rejected for long.
+    fn is_plausible(
+        &self,
+        position: &Position,
+        time: chrono::DateTime<Utc>,
+        decoding: &PositionDecoding,
+    ) -> bool {
+        let position = (position.latitude, position.longitude);
+        if let Some(receiver) = decoding.receiver {
+            if haversine_nm((receiver.latitude, receiver.longitude), position) > decoding.max_range
             {
-                self.latitude = Some(latitude);
-                self.longitude = Some(longitude);
+                return false;
             }
         }
+        match self.recent_track_point(time) {
+            Some(point) => {
+                let distance = haversine_nm((point.latitude, point.longitude), position);
+                let elapsed = time
+                    .signed_duration_since(point.time)
+                    .num_seconds()
+                    .max(PLAUSIBILITY_MIN_INTERVAL_SECS);
+                distance / (elapsed as f64 / 3600.0) <= MAX_PLAUSIBLE_SPEED_KT
+            }
+            None => true,
+        }
     }
-}
 
-/// Stores the set of currently tracked aircraft
-#[derive(Default)]
-pub struct Tracker {
-    map: HashMap<ICAOAddress, Aircraft>,
-    num_messages: u64,
-    num_unknown_messages: u64,
-    unknown_message_counts: HashMap<u8, u64>,
-    known_message_counts: HashMap<u8, u64>,
-}
+    /// Get the last point of the aircraft's track, if it's recent enough to compare new
+    /// positions against
+    fn recent_track_point(&self, time: chrono::DateTime<Utc>) -> Option<&TrackPoint> {
+        let point = self.track.last()?;
+        if time.signed_duration_since(point.time) > Duration::seconds(LOCAL_CPR_MAX_AGE_SECS) {
+            return None;
+        }
+        Some(point)
+    }
 
-impl Tracker {
-    /// Create a new tracker
-    pub fn new() -> Self {
-        Tracker::default()
+    /// Append the current position to the track, then drop the oldest points beyond
+    /// `max_points` and those more than `max_age` older than the new point. The new point is
+    /// always kept so the aircraft can still be plotted.
+    ///
+    /// With a `min_distance` (nautical miles), a run of points at the same altitude each within
+    /// that distance of the one before, such as from a parked aircraft, is reduced to its first
+    /// and most recent points.
+    fn push_track_point(
+        &mut self,
+        time: chrono::DateTime<Utc>,
+        max_points: usize,
+        max_age: Option<Duration>,
+        min_distance: Option<f64>,
+    ) {
+        let (latitude, longitude) = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => (latitude, longitude),
+            _ => return,
+        };
+        let point = TrackPoint {
+            time,
+            latitude,
+            longitude,
+            altitude: self.position_altitude,
+        };
+        if let (Some(min_distance), [.., before, last]) = (min_distance, self.track.as_slice()) {
+            let is_duplicate = |a: &TrackPoint, b: &TrackPoint| {
+                a.altitude == b.altitude
+                    && haversine_nm((a.latitude, a.longitude), (b.latitude, b.longitude))
+                        <= min_distance
+            };
+            // The last point is in the middle of a run, so the new point replaces it
+            if is_duplicate(before, last) && is_duplicate(last, &point) {
+                self.track.pop();
+            }
+        }
+        self.track.push(point);
+        let mut excess = self.track.len().saturating_sub(max_points.max(1));
+        if let Some(max_age) = max_age {
+            let expired = self.track[..self.track.len() - 1]
+                .iter()
+                .take_while(|p| time.signed_duration_since(p.time) > max_age)
+                .count();
+            excess = excess.max(expired);
+        }
+        self.track.drain(..excess);
     }
 
-    /// Update the tracker with a received ADSB message in AVR format
-    pub fn update_with_avr(&mut self, frame: &str, time: chrono::DateTime<Utc>) -> Result<(), adsb::ParserError> {
-        let (message, _) = adsb::parse_avr(frame)?;
-        self.update_with_message(message, time);
-        Ok(())
+    /// Get the positions decoded for this aircraft, oldest first
+    pub fn track(&self) -> &[TrackPoint] {
+        &self.track
     }
 
-    /// Update the tracker with a received ADSB message in binary format
-    pub fn update_with_binary(&mut self, frame: &[u8], time: chrono::DateTime<Utc>) -> Result<(), adsb::ParserError> {
-        let (message, _) = adsb::parse_binary(frame)?;
-        self.update_with_message(message, time);
-        Ok(())
+    /// Get the emergency declared by the current squawk. This follows the squawk, so it clears
+    /// once the aircraft stops squawking an emergency code.
+    pub fn emergency(&self) -> Option<Emergency> {
+        self.squawk?.emergency()
     }
 
-    fn update_unknown_message_statistics(&mut self, message: Message) {
-        let df = message.downlink_format;
-        *self.unknown_message_counts.entry(df).or_insert(0) += 1;
-        self.num_unknown_messages += 1;
+    /// Time between the first and last messages received from the aircraft. An aircraft which
+    /// was removed as stale starts again from zero when it reappears.
+    pub fn time_in_view(&self) -> Duration {
+        self.last_seen.signed_duration_since(self.first_seen)
     }
 
-    fn update_with_message(&mut self, message: Message, time: chrono::DateTime<Utc>) {
-        use ADSBMessageKind::*;
+    /// Rate of messages received from the aircraft over the `RATE_WINDOW_SECS` seconds up to
+    /// when it was last seen, or over its time in view until that's as long (but never less than
+    /// one second). Unusually high or low rates hint at an aircraft close to the receiver or at
+    /// poor reception. `None` until messages have been received over some length of time.
+    pub fn messages_per_second(&self) -> Option<f64> {
+        let elapsed = self.time_in_view().num_milliseconds() as f64 / 1000.0;
+        if elapsed <= 0.0 {
+            return None;
+        }
+        let cutoff = self.last_seen.timestamp() - RATE_WINDOW_SECS;
+        let count: u64 = self
+            .recent_message_counts
+            .iter()
+            .filter(|(s, _)| *s > cutoff)
+            .map(|(_, count)| count)
+            .sum();
+        Some(count as f64 / elapsed.max(1.0).min(RATE_WINDOW_SECS as f64))
+    }
 
+    /// Count a message received from the aircraft at `time`, in total and in the window of
+    /// recent messages
+    fn count_message(&mut self, time: chrono::DateTime<Utc>) {
         self.num_messages += 1;
-        let (icao_address, kind) = match message {
+        let second = time.timestamp();
+        match self.recent_message_counts.back_mut() {
+            Some((s, count)) if *s == second => *count += 1,
+            _ => self.recent_message_counts.push_back((second, 1)),
+        }
+        while let Some((s, _)) = self.recent_message_counts.front() {
+            if *s > second - RATE_WINDOW_SECS {
+                break;
+            }
+            self.recent_message_counts.pop_front();
+        }
+    }
 
-            Message {
-                kind: ADSBMessage {
-                    icao_address, kind, ..
-                },
-                ..
-            } => {
-                *self.known_message_counts.entry(message.downlink_format).or_insert(0) += 1;
-                (icao_address, kind)
-            },
-            _ => {
-                self.update_unknown_message_statistics(message);
-                return
-            },
+    /// Great-circle distance from a position, such as the receiver's, to the aircraft's current
+    /// position (nautical miles)
+    pub fn distance_from(&self, origin: &Position) -> Option<f64> {
+        let position = (self.latitude?, self.longitude?);
+        Some(haversine_nm((origin.latitude, origin.longitude), position))
+    }
+
+    /// Ground speed computed from the distance between the last two points of the track and the
+    /// time between them (knots), independently of the one the aircraft reports
+    pub fn computed_ground_speed(&self) -> Option<f64> {
+        let (before, last) = self.last_two_track_points()?;
+        let elapsed = last.time.signed_duration_since(before.time);
+        if elapsed <= Duration::zero() {
+            return None;
+        }
+        let distance = haversine_nm(
+            (before.latitude, before.longitude),
+            (last.latitude, last.longitude),
+        );
+        Some(distance / (elapsed.num_milliseconds() as f64 / 3_600_000.0))
+    }
+
+    /// Cross-check the computed ground speed against the reported one. `None` if either is
+    /// missing, or if the last two positions are too close together or too far apart in time
+    /// for a fair comparison.
+    pub fn position_quality(&self) -> Option<PositionQuality> {
+        let computed = self.computed_ground_speed()?;
+        let reported = self.ground_speed?;
+        let (before, last) = self.last_two_track_points()?;
+        let elapsed = last.time.signed_duration_since(before.time);
+        if elapsed < Duration::seconds(GROUND_SPEED_CHECK_MIN_SECS)
+            || elapsed > Duration::seconds(GROUND_SPEED_CHECK_MAX_SECS)
+        {
+            return None;
+        }
+        if (computed - reported).abs() > GROUND_SPEED_MAX_DISCREPANCY_KT {
+            Some(PositionQuality::Inconsistent)
+        } else {
+            Some(PositionQuality::Consistent)
+        }
+    }
+
+    fn last_two_track_points(&self) -> Option<(&TrackPoint, &TrackPoint)> {
+        match self.track.as_slice() {
+            [.., before, last] => Some((before, last)),
+            _ => None,
+        }
+    }
+
+    /// Initial great-circle bearing from a position, such as the receiver's, to the aircraft's
+    /// current position (degrees from true north)
+    pub fn bearing_from(&self, origin: &Position) -> Option<f64> {
+        let (lat1, lat2) = (origin.latitude.to_radians(), self.latitude?.to_radians());
+        let d_lon = (self.longitude? - origin.longitude).to_radians();
+        let y = d_lon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+        Some(y.atan2(x).to_degrees().rem_euclid(360.0))
+    }
+
+    /// Get the Mach number. It's the one the aircraft reports if there is one, otherwise it's
+    /// calculated at the current altitude from the true airspeed when the aircraft reports it,
+    /// or from the ground speed, which ignores the wind.
+    pub fn mach(&self) -> Option<Mach> {
+        if let Some(number) = self.mach {
+            return Some(Mach {
+                number,
+                approximate: false,
+            });
+        }
+        let speed_of_sound = speed_of_sound_kt(f64::from(self.altitude?));
+        match (self.true_airspeed, self.ground_speed) {
+            (Some(true_airspeed), _) => Some(Mach {
+                number: true_airspeed / speed_of_sound,
+                approximate: false,
+            }),
+            (None, Some(ground_speed)) => Some(Mach {
+                number: ground_speed / speed_of_sound,
+                approximate: true,
+            }),
+            (None, None) => None,
+        }
+    }
+
+    /// Guess whether the aircraft is arriving at or departing from an airport. It has to be low
+    /// and near the airport, and its vertical rate has to agree with the change in its distance
+    /// from the airport over the last minute: descending while closing in for an arrival,
+    /// climbing while moving away for a departure. A low overflight, which is level or descends
+    /// while moving away, isn't tagged.
+    pub fn movement(&self, airport: &Airport) -> Option<Movement> {
+        let home = (airport.position.latitude, airport.position.longitude);
+        let last = self.track.last()?;
+        let distance = haversine_nm((last.latitude, last.longitude), home);
+        let height = f64::from(last.altitude?) - airport.elevation;
+        if distance > HOME_AIRPORT_RADIUS_NM || height > HOME_AIRPORT_MAX_HEIGHT_FT {
+            return None;
+        }
+        let earlier = self.track.iter().find(|p| {
+            last.time.signed_duration_since(p.time) <= Duration::seconds(MOVEMENT_TREND_SECS)
+        })?;
+        let change = distance - haversine_nm((earlier.latitude, earlier.longitude), home);
+        let vertical_rate = self.vertical_rate?;
+        if vertical_rate <= -MOVEMENT_MIN_VERTICAL_RATE
+            && change <= -MOVEMENT_MIN_DISTANCE_CHANGE_NM
+        {
+            Some(Movement::Arrival)
+        } else if vertical_rate >= MOVEMENT_MIN_VERTICAL_RATE
+            && change >= MOVEMENT_MIN_DISTANCE_CHANGE_NM
+        {
+            Some(Movement::Departure)
+        } else {
+            None
+        }
+    }
+
+    /// Take an owned snapshot of the aircraft's current state
+    pub fn snapshot(&self, now: chrono::DateTime<Utc>) -> AircraftSnapshot {
+        let position = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => Some(Position {
+                latitude,
+                longitude,
+            }),
+            _ => None,
         };
+        AircraftSnapshot {
+            icao_address: self.icao_address,
+            callsign: self.callsign.clone(),
+            registration: self.registration.clone(),
+            aircraft_type: self.aircraft_type.clone(),
+            country: self.country,
+            emitter_category: self.emitter_category,
+            altitude: self.altitude,
+            geometric_altitude: self.geometric_altitude,
+            heading: self.heading,
+            heading_reference: self.heading_reference,
+            ground_speed: self.ground_speed,
+            true_airspeed: self.true_airspeed,
+            vertical_rate: self.vertical_rate,
+            vertical_rate_source: self.vertical_rate_source.clone(),
+            squawk: self.squawk,
+            selected_altitude: self.selected_altitude,
+            selected_heading: self.selected_heading,
+            pressure_setting: self.pressure_setting,
+            position,
+            position_altitude: self.position_altitude,
+            on_ground: self.on_ground,
+            is_orbiting: self.is_orbiting,
+            last_seen: self.last_seen,
+            seen: now.signed_duration_since(self.last_seen),
+        }
+    }
 
-        let aircraft = self
-            .map
-            .entry(icao_address)
-            .or_insert_with(|| Aircraft::new(icao_address, time));
+    /// Summarize the flight so far
+    pub fn summary(&self) -> FlightSummary {
+        FlightSummary {
+            icao_address: self.icao_address,
+            callsigns: self.callsigns.clone(),
+            max_altitude: self.max_altitude,
+            max_ground_speed: self.max_ground_speed,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+            track: self.track.clone(),
+        }
+    }
 
-        match kind {
-            AircraftIdentification { callsign, .. } => {
-                aircraft.callsign = Some(callsign.trim().to_string());
+    /// Count a received callsign towards confirming it, and return whether it was confirmed as
+    /// a new callsign.
+    ///
+    /// A new callsign is only accepted once it has been received `CALLSIGN_CONFIRMATIONS` times
+    /// in a row, so a single corrupted frame (or a second aircraft sharing the address) doesn't
+    /// make the callsign flap. A genuine change takes effect as soon as it has been confirmed.
+    fn update_callsign(&mut self, callsign: &str) -> bool {
+        let callsign = callsign.trim();
+        if self.callsign.as_deref() == Some(callsign) {
+            self.pending_callsign = None;
+            return false;
+        }
+        let count = match &self.pending_callsign {
+            Some((pending, count)) if pending == callsign => count + 1,
+            _ => 1,
+        };
+        if count >= CALLSIGN_CONFIRMATIONS {
+            self.callsign = Some(callsign.to_string());
+            self.pending_callsign = None;
+            if !self.callsigns.iter().any(|c| c == callsign) {
+                self.callsigns.push(callsign.to_string());
             }
-            AirbornePosition {
-                altitude,
-                cpr_frame,
-            } => {
-                aircraft.altitude = Some(altitude);
-                aircraft.update_position(cpr_frame);
+            true
+        } else {
+            self.pending_callsign = Some((callsign.to_string(), count));
+            false
+        }
+    }
+
+    /// The current position as a record received at `time`, if the position is known
+    fn position_record(&self, time: chrono::DateTime<Utc>) -> Option<PositionRecord> {
+        Some(PositionRecord {
+            time,
+            icao_address: self.icao_address,
+            latitude: self.latitude?,
+            longitude: self.longitude?,
+            altitude: self.position_altitude,
+        })
+    }
+
+    fn update_altitude(&mut self, altitude: i32) {
+        self.altitude = Some(altitude);
+        self.max_altitude = self.max_altitude.max(Some(altitude));
+    }
+
+    fn update_ground_speed(&mut self, ground_speed: f64) {
+        self.ground_speed = Some(ground_speed);
+        self.max_ground_speed = Some(
+            self.max_ground_speed
+                .map_or(ground_speed, |max| max.max(ground_speed)),
+        );
+    }
+
+    /// Set the heading, normalized into [0, 360). Values which aren't finite are ignored.
+    fn update_heading(&mut self, heading: f64, time: chrono::DateTime<Utc>) {
+        if !heading.is_finite() {
+            return;
+        }
+        let heading = heading.rem_euclid(360.0);
+        self.heading = Some(heading);
+        let interval = Duration::seconds(ORBIT_SAMPLE_INTERVAL_SECS);
+        if let Some(last) = self.heading_history.back() {
+            if time.signed_duration_since(last.time) < interval {
+                return;
             }
-            AirborneVelocity {
-                heading,
-                ground_speed,
-                vertical_rate,
-                vertical_rate_source,
-            } => {
-                aircraft.heading = Some(heading);
-                aircraft.ground_speed = Some(ground_speed);
-                aircraft.vertical_rate = Some(vertical_rate);
-                aircraft.vertical_rate_source = Some(vertical_rate_source);
+        }
+        let position = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => Some((latitude, longitude)),
+            _ => None,
+        };
+        self.heading_history.push_back(HeadingSample {
+            time,
+            heading,
+            position,
+        });
+        let window = Duration::minutes(ORBIT_WINDOW_MINS);
+        while let Some(sample) = self.heading_history.front() {
+            if time.signed_duration_since(sample.time) <= window {
+                break;
             }
+            self.heading_history.pop_front();
         }
+        self.is_orbiting = self.detect_orbit();
+    }
 
-        aircraft.last_seen = time;
+    /// Sum up the heading changes over the history. Turns in opposite directions cancel out, so
+    /// only sustained turning in one direction reaches a full circle. A turn onto a new heading
+    /// never does, and neither does a circle flown while covering a large distance. The positions
+    /// must also keep a roughly constant distance from their center.
+    fn detect_orbit(&self) -> bool {
+        let turn: f64 = self
+            .heading_history
+            .iter()
+            .zip(self.heading_history.iter().skip(1))
+            .map(|(a, b)| {
+                let delta = (b.heading - a.heading).rem_euclid(360.0);
+                if delta > 180.0 {
+                    delta - 360.0
+                } else {
+                    delta
+                }
+            })
+            .sum();
+        if turn.abs() < 360.0 {
+            return false;
+        }
+
+        let positions: Vec<(f64, f64)> = self
+            .heading_history
+            .iter()
+            .filter_map(|s| s.position)
+            .collect();
+        if positions.len() < 2 {
+            return false;
+        }
+        let n = positions.len() as f64;
+        let center = (
+            positions.iter().map(|p| p.0).sum::<f64>() / n,
+            positions.iter().map(|p| p.1).sum::<f64>() / n,
+        );
+        let radii: Vec<f64> = positions.iter().map(|p| haversine_nm(center, *p)).collect();
+        if radii.iter().any(|&r| r > ORBIT_MAX_RADIUS_NM) {
+            return false;
+        }
+        let mean = radii.iter().sum::<f64>() / n;
+        let variance = radii.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n;
+        variance.sqrt() <= ORBIT_MAX_RADIUS_SPREAD * mean
     }
+}
 
-    /// Get a list of aircraft last seen in the given interval
-    pub fn get_current_aircraft(&self, interval: &Duration) -> Vec<&Aircraft> {
-        let now = Utc::now();
-        self.map
-            .values()
-            .filter(|a | now.signed_duration_since(a.last_seen) < *interval)
-            .collect()
+/// Start tracking a newly seen aircraft, with its registration if the registry knows it
+fn new_aircraft(
+    icao_address: ICAOAddress,
+    time: chrono::DateTime<Utc>,
+    registry: Option<&dyn RegistryLookup>,
+) -> Aircraft {
+    let mut aircraft = Aircraft::new(icao_address, time);
+    if let Some(registration) = registry.and_then(|registry| registry.lookup(&icao_address)) {
+        aircraft.registration = registration.registration;
+        aircraft.aircraft_type = registration.aircraft_type;
     }
+    aircraft
+}
 
-    // Get a list of all tracked aircraft
-    pub fn get_all_aircraft(&self) -> Vec<&Aircraft> {
-        self.map.values().collect()
+/// Update the record of the farthest position from the receiver with an aircraft's new position
+fn update_max_range(
+    record: &mut Option<(f64, ICAOAddress, chrono::DateTime<Utc>)>,
+    receiver: Option<&Position>,
+    aircraft: &Aircraft,
+    time: chrono::DateTime<Utc>,
+) {
+    let distance = match receiver.and_then(|receiver| aircraft.distance_from(receiver)) {
+        Some(distance) => distance,
+        None => return,
+    };
+    match record {
+        Some((max_range, _, _)) if *max_range >= distance => {}
+        _ => *record = Some((distance, aircraft.icao_address, time)),
     }
+}
 
-    pub fn get_num_messages(&self) -> u64 {
-        self.num_messages
+/// Record an aircraft's new position in the position log, if enabled, unless the aircraft's
+/// last recorded position is more recent than `min_interval`
+fn log_position(
+    log: &mut Option<Vec<PositionRecord>>,
+    min_interval: Option<Duration>,
+    aircraft: &mut Aircraft,
+    time: chrono::DateTime<Utc>,
+) {
+    let log = match log.as_mut() {
+        Some(log) => log,
+        None => return,
+    };
+    let record = match aircraft.position_record(time) {
+        Some(record) => record,
+        None => return,
+    };
+    if let (Some(last), Some(min_interval)) = (aircraft.last_logged_position, min_interval) {
+        if time.signed_duration_since(last) < min_interval {
+            return;
+        }
     }
+    aircraft.last_logged_position = Some(time);
+    log.push(record);
+}
 
-    pub fn get_num_unknown_messages(&self) -> u64 {
-        self.num_unknown_messages
+/// Pass an event to the tracker's callback, if one is set. The event is only made if needed.
+fn emit(on_event: &mut Option<EventCallback>, event: impl FnOnce() -> TrackerEvent) {
+    if let Some(on_event) = on_event.as_mut() {
+        on_event(event());
     }
+}
 
-    pub fn get_unknown_message_statistics(&self) -> &HashMap<u8, u64> {
-        return &self.unknown_message_counts;
+fn callsign_event(aircraft: &Aircraft, time: chrono::DateTime<Utc>) -> TrackerEvent {
+    TrackerEvent::CallsignUpdate {
+        icao_address: aircraft.icao_address,
+        callsign: aircraft.callsign.clone().unwrap_or_default(),
+        time,
     }
+}
 
-    pub fn get_known_message_statistics(&self) -> &HashMap<u8, u64> {
-        return &self.known_message_counts;
+/// Great-circle distance between two (latitude, longitude) points (nautical miles)
+fn haversine_nm(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b.1 - a.1).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_NM * h.sqrt().asin()
+}
+
+/// Speed of sound at an altitude in the International Standard Atmosphere (knots)
+fn speed_of_sound_kt(altitude: f64) -> f64 {
+    let temperature =
+        ISA_SEA_LEVEL_TEMPERATURE - ISA_LAPSE_RATE * altitude.clamp(0.0, ISA_TROPOPAUSE_FT);
+    SPEED_OF_SOUND_FACTOR * temperature.sqrt()
+}
+
+/// Number of hex digits in the MLAT timestamp of a frame in the AVR `@` form
+const MLAT_TIMESTAMP_DIGITS: usize = 12;
+/// Rate of the MLAT timestamp clock
+const MLAT_TICKS_PER_MICROSECOND: u64 = 12;
+/// Maximum difference between the time given by a frame's MLAT timestamp and the time it was
+/// received before the timestamp clock is tied to the received time again (seconds)
+const MLAT_MAX_DRIFT_SECS: i64 = 5;
+
+/// Length of a short Mode-S message (bytes)
+const SHORT_FRAME_LEN: usize = 7;
+/// Length of a long Mode-S message (bytes)
+const LONG_FRAME_LEN: usize = 14;
+
+/// Error returned when a received frame can't be processed
+#[derive(Debug)]
+pub enum FrameError {
+    /// Frame isn't in AVR format: a `*` followed by pairs of hex digits and a `;`
+    InvalidAvr,
+    /// Frame isn't the length of a short or long Mode-S message (bytes)
+    InvalidLength(usize),
+    /// Extended squitter whose parity doesn't match its CRC, so it was corrupted in reception
+    Crc,
+    /// Frame couldn't be parsed as an ADS-B/Mode-S message
+    Parser(adsb::ParserError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::InvalidAvr => write!(f, "invalid AVR frame"),
+            FrameError::InvalidLength(len) => write!(
+                f,
+                "invalid frame length {} (expected {} or {} bytes)",
+                len, SHORT_FRAME_LEN, LONG_FRAME_LEN
+            ),
+            FrameError::Crc => write!(f, "CRC mismatch"),
+            FrameError::Parser(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+impl From<adsb::ParserError> for FrameError {
+    fn from(error: adsb::ParserError) -> Self {
+        FrameError::Parser(error)
+    }
+}
+
+/// Decode the bytes of a frame in AVR format, ignoring surrounding whitespace. Frames in the
+/// `@` form, as sent by dump1090 with MLAT timestamps, start with the timestamp as 12 hex digits
+/// instead of a `*`, and it's returned along with the bytes.
+fn decode_avr(frame: &str) -> Option<(Vec<u8>, Option<u64>)> {
+    let frame = frame.trim();
+    let (hex, mlat_timestamp) = match frame.strip_prefix('@') {
+        Some(timestamped) => {
+            let timestamp = timestamped.get(..MLAT_TIMESTAMP_DIGITS)?;
+            if !timestamp.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let timestamp = u64::from_str_radix(timestamp, 16).ok()?;
+            (timestamped.get(MLAT_TIMESTAMP_DIGITS..)?, Some(timestamp))
+        }
+        None => (frame.strip_prefix('*')?, None),
+    };
+    let hex = hex.strip_suffix(';')?;
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let data = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<_>>()?;
+    Some((data, mlat_timestamp))
+}
+
+/// A frame decoded independently of any tracker state, so that decoding can be done on another
+/// thread than the one updating the tracker. Apply it with `Tracker::update_with_decoded`.
+#[derive(Debug)]
+pub struct DecodedFrame {
+    frame: Vec<u8>,
+    time: chrono::DateTime<Utc>,
+    mlat_timestamp: Option<u64>,
+    message: Result<Message, FrameError>,
+}
+
+impl DecodedFrame {
+    /// Decode a frame in AVR format. The MLAT timestamp of a frame in the `@` form is kept, to
+    /// time the frame by when it's applied to a tracker.
+    pub fn from_avr(frame: &str, time: chrono::DateTime<Utc>) -> Self {
+        match decode_avr(frame) {
+            Some((data, mlat_timestamp)) => DecodedFrame {
+                mlat_timestamp,
+                ..DecodedFrame::from_binary(data, time)
+            },
+            None => DecodedFrame {
+                frame: Vec::new(),
+                time,
+                mlat_timestamp: None,
+                message: Err(FrameError::InvalidAvr),
+            },
+        }
+    }
+
+    /// Decode a frame in binary format
+    pub fn from_binary(frame: Vec<u8>, time: chrono::DateTime<Utc>) -> Self {
+        let message = if frame.len() != SHORT_FRAME_LEN && frame.len() != LONG_FRAME_LEN {
+            Err(FrameError::InvalidLength(frame.len()))
+        } else if !parity_matches(&frame) {
+            Err(FrameError::Crc)
+        } else {
+            adsb::parse_binary(&frame)
+                .map(|(message, _)| message)
+                .map_err(FrameError::from)
+        };
+        DecodedFrame {
+            frame,
+            time,
+            mlat_timestamp: None,
+            message,
+        }
+    }
+}
+
+/// Stores the set of currently tracked aircraft
+#[derive(Default)]
+pub struct Tracker {
+    map: HashMap<ICAOAddress, Aircraft>,
+    seen_aircraft: HashSet<ICAOAddress>,
+    num_messages: u64,
+    num_unknown_messages: u64,
+    error_statistics: ErrorStatistics,
+    num_duplicate_messages: u64,
+    unknown_message_counts: HashMap<u8, u64>,
+    known_message_counts: HashMap<u8, u64>,
+    first_message_real_time: Option<chrono::DateTime<Utc>>,
+    last_message_real_time: Option<chrono::DateTime<Utc>>,
+    last_message_time: Option<chrono::DateTime<Utc>>,
+    recent_message_counts: VecDeque<(i64, u64)>,
+    max_track_points: Option<usize>,
+    max_track_age: Option<Duration>,
+    track_min_distance: Option<f64>,
+    cpr_window: Option<Duration>,
+    dedup_window: Option<Duration>,
+    mlat_clock: Option<(u64, chrono::DateTime<Utc>)>,
+    recent_frames: VecDeque<(chrono::DateTime<Utc>, u64)>,
+    recent_frame_hashes: HashSet<u64>,
+    max_range: Option<f64>,
+    max_range_record: Option<(f64, ICAOAddress, chrono::DateTime<Utc>)>,
+    registry: Option<Box<dyn RegistryLookup>>,
+    receiver_position: Option<Position>,
+    acquisitions: Option<Vec<Acquisition>>,
+    reacquire_after: Option<Duration>,
+    flight_summaries: Option<Vec<FlightSummary>>,
+    position_log: Option<Vec<PositionRecord>>,
+    position_log_interval: Option<Duration>,
+    frame_log: Option<Vec<FrameRecord>>,
+    on_event: Option<EventCallback>,
+    clock: Option<Clock>,
+    beast_decoder: BeastDecoder,
+    position_statistics: PositionStatistics,
+}
+
+impl Tracker {
+    /// Create a new tracker
+    pub fn new() -> Self {
+        Tracker::default()
+    }
+
+    /// Set the maximum number of points kept in each aircraft's track (default 500)
+    pub fn set_max_track_points(&mut self, max_points: usize) {
+        self.max_track_points = Some(max_points);
+    }
+
+    /// Set the maximum age of the points kept in each aircraft's track, relative to its latest
+    /// point. Points are dropped once either this or the maximum number of points is exceeded.
+    pub fn set_max_track_age(&mut self, max_age: Option<Duration>) {
+        self.max_track_age = max_age;
+    }
+
+    /// Set the maximum time between the even and odd CPR frames paired to decode a position.
+    /// Defaults to 10 seconds.
+    pub fn set_cpr_window(&mut self, window: Duration) {
+        self.cpr_window = Some(window);
+    }
+
+    /// Drop frames identical to one received within `window` before, as happens when the feeds
+    /// of nearby receivers are combined. Dropped frames are only counted, by
+    /// `get_num_duplicate_messages`. Off by default.
+    pub fn set_dedup_window(&mut self, window: Option<Duration>) {
+        self.dedup_window = window;
+        self.recent_frames.clear();
+        self.recent_frame_hashes.clear();
+    }
+
+    /// Set the maximum distance (nautical miles) from the receiver of a plausible position.
+    /// Positions decoded farther away are discarded, but only once the receiver's location is
+    /// set. Defaults to 400 nautical miles. Lone airborne frames are only decoded against the
+    /// receiver's location with a range of at most 180 nautical miles.
+    pub fn set_max_range(&mut self, max_range: f64) {
+        self.max_range = Some(max_range);
+    }
+
+    /// Set the distance (nautical miles) within which consecutive points at the same altitude
+    /// are treated as duplicates, so that only the first and latest points of a stationary
+    /// stretch are kept in each aircraft's track. By default all points are kept.
+    pub fn set_track_min_distance(&mut self, min_distance: Option<f64>) {
+        self.track_min_distance = min_distance;
+    }
+
+    /// Set the location of the receiver, from which distances and bearings to aircraft are shown.
+    /// It's also needed to resolve the positions of aircraft on the ground. Moving the receiver
+    /// starts the maximum range afresh.
+    pub fn set_receiver_position(&mut self, latitude: f64, longitude: f64) {
+        self.receiver_position = Some(Position {
+            latitude,
+            longitude,
+        });
+        self.max_range_record = None;
+    }
+
+    /// Set the registry in which aircraft are looked up when first seen, to fill in their
+    /// registration and type
+    pub fn set_registry(&mut self, registry: Box<dyn RegistryLookup>) {
+        self.registry = Some(registry);
+    }
+
+    /// Get the location of the receiver, if it has been set
+    pub fn receiver_position(&self) -> Option<&Position> {
+        self.receiver_position.as_ref()
+    }
+
+    /// Start recording newly acquired aircraft, to be collected with `take_acquisitions`. With
+    /// `reacquire_after`, an aircraft heard again after being silent for longer than that is
+    /// also counted as acquired, otherwise only aircraft never seen before are.
+    pub fn enable_acquisitions(&mut self, reacquire_after: Option<Duration>) {
+        self.acquisitions.get_or_insert_with(Vec::new);
+        self.reacquire_after = reacquire_after;
+    }
+
+    /// Take the aircraft acquired since the last call, oldest first
+    pub fn take_acquisitions(&mut self) -> Vec<Acquisition> {
+        self.acquisitions
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Record the acquisition of an aircraft a message has been received from, if it's new or
+    /// has been silent for long enough and acquisitions are enabled. Called before the aircraft
+    /// is updated with the message, which also counts it as seen and reports it if it's new.
+    fn record_acquisition(&mut self, icao_address: ICAOAddress, time: chrono::DateTime<Utc>) {
+        self.seen_aircraft.insert(icao_address);
+        if !self.map.contains_key(&icao_address) {
+            emit(&mut self.on_event, || TrackerEvent::NewAircraft {
+                icao_address,
+                time,
+            });
+        }
+        let acquisitions = match self.acquisitions.as_mut() {
+            Some(acquisitions) => acquisitions,
+            None => return,
+        };
+        let reacquired = match self.map.get(&icao_address) {
+            None => false,
+            Some(aircraft) => match self.reacquire_after {
+                Some(after) if time.signed_duration_since(aircraft.last_seen) > after => true,
+                _ => return,
+            },
+        };
+        acquisitions.push(Acquisition {
+            icao_address,
+            time,
+            reacquired,
+        });
+    }
+
+    /// Start recording a summary of each aircraft removed by `remove_stale`, to be collected
+    /// with `take_flight_summaries`
+    pub fn enable_flight_summaries(&mut self) {
+        self.flight_summaries.get_or_insert_with(Vec::new);
+    }
+
+    /// Take the summaries of the aircraft removed since the last call
+    pub fn take_flight_summaries(&mut self) -> Vec<FlightSummary> {
+        self.flight_summaries
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Set the clock giving the time messages are processed, which the message rates are
+    /// measured by and against which aircraft are current. Defaults to the system clock.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = Some(clock);
+    }
+
+    fn now(&self) -> chrono::DateTime<Utc> {
+        self.clock.as_ref().map_or_else(Utc::now, |clock| clock())
+    }
+
+    /// Set a callback to be called with each event as it happens, such as a new aircraft or
+    /// position. It's called while the tracker is being updated, so should return quickly.
+    pub fn set_on_event(&mut self, on_event: EventCallback) {
+        self.on_event = Some(on_event);
+    }
+
+    /// Start recording every decoded position, to be collected with `take_positions`. With
+    /// `min_interval`, positions of an aircraft received sooner than that after the last one
+    /// recorded are skipped.
+    pub fn enable_position_log(&mut self, min_interval: Option<Duration>) {
+        self.position_log.get_or_insert_with(Vec::new);
+        self.position_log_interval = min_interval;
+    }
+
+    /// Take the positions recorded since the last call, oldest first
+    pub fn take_positions(&mut self) -> Vec<PositionRecord> {
+        self.position_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Start recording every frame the tracker processes, to be collected with `take_frames`.
+    /// Frames are recorded as received, before any checks other than for valid AVR, so
+    /// duplicates and frames failing their CRC are included.
+    pub fn enable_frame_log(&mut self) {
+        self.frame_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Take the frames recorded since the last call, oldest first
+    pub fn take_frames(&mut self) -> Vec<FrameRecord> {
+        self.frame_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Remove aircraft last seen more than `max_age` before `now`, recording their summaries if
+    /// enabled, and return how many were removed. All of an aircraft's decoding state goes with
+    /// it, so if it's heard again it starts afresh, without pairing new CPR frames with old ones.
+    pub fn remove_stale(&mut self, max_age: Duration, now: chrono::DateTime<Utc>) -> usize {
+        let stale: Vec<_> = self
+            .map
+            .values()
+            .filter(|a| now.signed_duration_since(a.last_seen) > max_age)
+            .map(|a| a.icao_address)
+            .collect();
+        for icao_address in &stale {
+            if let Some(aircraft) = self.map.remove(icao_address) {
+                if let Some(summaries) = self.flight_summaries.as_mut() {
+                    summaries.push(aircraft.summary());
+                }
+                emit(&mut self.on_event, || TrackerEvent::Expired {
+                    icao_address: aircraft.icao_address,
+                    last_seen: aircraft.last_seen,
+                });
+            }
+        }
+        stale.len()
+    }
+
+    /// Update the tracker with a received ADSB message in AVR format
+    pub fn update_with_avr(
+        &mut self,
+        frame: &str,
+        time: chrono::DateTime<Utc>,
+    ) -> Result<(), FrameError> {
+        self.update_with_decoded(DecodedFrame::from_avr(frame, time))
+    }
+
+    /// Update the tracker with a received ADSB message in binary format
+    pub fn update_with_binary(
+        &mut self,
+        frame: &[u8],
+        time: chrono::DateTime<Utc>,
+    ) -> Result<(), FrameError> {
+        self.update_with_decoded(DecodedFrame::from_binary(frame.to_vec(), time))
+    }
+
+    /// Update the tracker with bytes received in the Beast binary format. The bytes needn't be
+    /// whole frames: a frame split across calls is applied once it's complete. Mode A/C replies
+    /// are ignored.
+    pub fn update_with_beast(&mut self, bytes: &[u8], time: chrono::DateTime<Utc>) {
+        for frame in self.beast_decoder.push(bytes) {
+            if frame.kind != BeastFrameKind::ModeAc {
+                let _ = self.update_with_binary(&frame.message, time);
+            }
+        }
+    }
+
+    /// Update the tracker with a line in the SBS BaseStation format, as sent by dump1090 and readsb
+    /// on port 30003. Lines other than transmission messages (`MSG`), and malformed ones, are
+    /// ignored. Callsigns need confirming as with ADS-B identification messages.
+    pub fn update_with_sbs(&mut self, line: &str, time: chrono::DateTime<Utc>) {
+        if let Some(message) = parse_sbs(line) {
+            self.update_with_fields(message, time, true);
+        }
+    }
+
+    /// Update the tracker with a dump1090 `aircraft.json` document, polled at `time`, and return
+    /// how many of its aircraft had been heard from since the last poll, or `None` if it isn't
+    /// in that format. The fields are applied as from SBS messages, as of when each aircraft was
+    /// last heard from according to its `seen` time. Aircraft not heard from since the last poll
+    /// are left alone, so aircraft dropped from the document age out as usual.
+    pub fn update_with_aircraft_json(
+        &mut self,
+        document: &str,
+        time: chrono::DateTime<Utc>,
+    ) -> Option<usize> {
+        let mut updated = 0;
+        for entry in parse_aircraft_json(document)? {
+            let seconds_ago = |seen: f64| Duration::milliseconds((seen * 1000.0) as i64);
+            let seen = time - entry.seen.map_or_else(Duration::zero, seconds_ago);
+            let mut fields = entry.fields;
+            if let Some(aircraft) = self.map.get(&fields.icao_address) {
+                if seen <= aircraft.last_seen {
+                    continue;
+                }
+                // The same position is given until a new one is decoded
+                let position_time = entry.seen_pos.map(|s| time - seconds_ago(s));
+                let last_point = aircraft.track().last().map(|p| p.time);
+                if position_time.is_some_and(|t| Some(t) <= last_point) {
+                    fields.latitude = None;
+                    fields.longitude = None;
+                }
+            }
+            let icao_address = fields.icao_address;
+            let airborne = fields.altitude.is_some();
+            self.update_with_fields(fields, seen, false);
+            if let Some(aircraft) = self.map.get_mut(&icao_address) {
+                if entry.on_ground || airborne {
+                    aircraft.on_ground = Some(entry.on_ground);
+                }
+            }
+            updated += 1;
+        }
+        Some(updated)
+    }
+
+    /// Update the tracker with fields which have already been decoded, as in SBS messages. Fields
+    /// polled from `aircraft.json` aren't messages, so `is_message` is false for them and they
+    /// don't count towards the message totals and rates.
+    fn update_with_fields(
+        &mut self,
+        message: SbsMessage,
+        time: chrono::DateTime<Utc>,
+        is_message: bool,
+    ) {
+        if is_message {
+            self.num_messages += 1;
+            self.update_message_rate(self.now());
+        }
+        self.last_message_time = self.last_message_time.max(Some(time));
+        let icao_address = message.icao_address;
+        self.record_acquisition(icao_address, time);
+        let registry = self.registry.as_deref();
+        let aircraft = self
+            .map
+            .entry(icao_address)
+            .or_insert_with(|| new_aircraft(icao_address, time, registry));
+        if is_message {
+            aircraft.count_message(time);
+        }
+        if let Some(callsign) = &message.callsign {
+            if aircraft.update_callsign(callsign) {
+                emit(&mut self.on_event, || callsign_event(aircraft, time));
+            }
+        }
+        if let Some(altitude) = message.altitude {
+            aircraft.update_altitude(altitude);
+        }
+        if let Some(ground_speed) = message.ground_speed {
+            aircraft.update_ground_speed(ground_speed);
+        }
+        if let Some(track) = message.track {
+            aircraft.update_heading(track, time);
+            aircraft.heading_reference = Some(HeadingReference::True);
+        }
+        if message.vertical_rate.is_some() {
+            aircraft.vertical_rate = message.vertical_rate;
+        }
+        if message.squawk.is_some() {
+            aircraft.squawk = message.squawk;
+        }
+        if let (Some(latitude), Some(longitude)) = (message.latitude, message.longitude) {
+            aircraft.latitude = Some(latitude);
+            aircraft.longitude = Some(longitude);
+            aircraft.position_altitude = message.altitude.or(aircraft.altitude);
+        }
+        aircraft.last_seen = time;
+        if message.latitude.is_some() && message.longitude.is_some() {
+            self.record_position(icao_address, time);
+        }
+    }
+
+    /// Update the tracker with a frame which has already been decoded
+    pub fn update_with_decoded(&mut self, decoded: DecodedFrame) -> Result<(), FrameError> {
+        if let Some(log) = self.frame_log.as_mut() {
+            if !decoded.frame.is_empty() {
+                log.push(FrameRecord {
+                    time: decoded.time,
+                    data: decoded.frame.clone(),
+                });
+            }
+        }
+        match decoded.message {
+            Ok(message) => {
+                let time = self.mlat_time(decoded.mlat_timestamp, decoded.time);
+                self.update_with_message(message, &decoded.frame, time);
+                Ok(())
+            }
+            Err(error) => {
+                let errors = &mut self.error_statistics;
+                match error {
+                    FrameError::InvalidAvr => errors.invalid_avr += 1,
+                    FrameError::InvalidLength(_) => errors.invalid_length += 1,
+                    FrameError::Crc => errors.crc += 1,
+                    FrameError::Parser(_) => errors.parser += 1,
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Time a frame by its MLAT timestamp, if it has one, rather than by when it was received.
+    /// The timestamp counts at 12 MHz from an arbitrary start, so the counter is tied to the
+    /// time the first timestamped frame was received. It's tied again if it goes backwards or
+    /// strays too far from the times frames are received, as when the receiver restarts.
+    fn mlat_time(
+        &mut self,
+        mlat_timestamp: Option<u64>,
+        received: chrono::DateTime<Utc>,
+    ) -> chrono::DateTime<Utc> {
+        let timestamp = match mlat_timestamp {
+            Some(timestamp) => timestamp,
+            None => return received,
+        };
+        if let Some((start, start_time)) = self.mlat_clock {
+            if let Some(ticks) = timestamp.checked_sub(start) {
+                // The timestamps have 48 bits, so this can't overflow
+                let micros = (ticks / MLAT_TICKS_PER_MICROSECOND) as i64;
+                let time = start_time + Duration::microseconds(micros);
+                let drift = time.signed_duration_since(received).num_seconds().abs();
+                if drift <= MLAT_MAX_DRIFT_SECS {
+                    return time;
+                }
+            }
+        }
+        self.mlat_clock = Some((timestamp, received));
+        received
+    }
+
+    fn update_unknown_message_statistics(&mut self, message: Message) {
+        let df = message.downlink_format;
+        *self.unknown_message_counts.entry(df).or_insert(0) += 1;
+        self.num_unknown_messages += 1;
+        self.error_statistics.unsupported += 1;
+    }
+
+    /// Check whether a frame was already received within the deduplication window, remembering
+    /// it if not. Frames are compared by hash.
+    fn is_duplicate(&mut self, frame: &[u8], time: chrono::DateTime<Utc>) -> bool {
+        let window = match self.dedup_window {
+            Some(window) => window,
+            None => return false,
+        };
+        while let Some(&(received, hash)) = self.recent_frames.front() {
+            if received > time - window {
+                break;
+            }
+            self.recent_frames.pop_front();
+            self.recent_frame_hashes.remove(&hash);
+        }
+        let mut hasher = DefaultHasher::new();
+        frame.hash(&mut hasher);
+        let hash = hasher.finish();
+        if !self.recent_frame_hashes.insert(hash) {
+            return true;
+        }
+        self.recent_frames.push_back((time, hash));
+        false
+    }
+
+    fn update_message_rate(&mut self, real_time: chrono::DateTime<Utc>) {
+        self.first_message_real_time.get_or_insert(real_time);
+        self.last_message_real_time = Some(real_time);
+        let second = real_time.timestamp();
+        match self.recent_message_counts.back_mut() {
+            Some((s, count)) if *s == second => *count += 1,
+            _ => self.recent_message_counts.push_back((second, 1)),
+        }
+        while let Some((s, _)) = self.recent_message_counts.front() {
+            if *s > second - RATE_WINDOW_SECS {
+                break;
+            }
+            self.recent_message_counts.pop_front();
+        }
+    }
+
+    /// Handle Mode-S replies which the adsb crate doesn't decode. Replies which only carry the
+    /// address overlaid on the CRC are applied to aircraft already being tracked, since a
+    /// corrupted frame would otherwise create a bogus aircraft. Returns `false` if the reply
+    /// wasn't used.
+    fn update_with_mode_s_reply(
+        &mut self,
+        message: &Message,
+        frame: &[u8],
+        time: chrono::DateTime<Utc>,
+    ) -> bool {
+        let df = message.downlink_format;
+        match df {
+            5 | 20 | 21 => {
+                let aircraft = address_from_parity(frame)
+                    .and_then(icao_from_u32)
+                    .and_then(|icao_address| self.map.get_mut(&icao_address));
+                let aircraft = match aircraft {
+                    Some(aircraft) => aircraft,
+                    None => return false,
+                };
+                aircraft.count_message(time);
+                if df != 20 {
+                    aircraft.squawk = decode_identity_reply(frame);
+                }
+                match decode_comm_b(frame) {
+                    // The MCP/FCU altitude is what the crew has set, which the FMS one may not be
+                    Some(CommB::SelectedVerticalIntention(report)) => {
+                        if let Some(altitude) = report.mcp_altitude.or(report.fms_altitude) {
+                            aircraft.selected_altitude = Some(altitude);
+                        }
+                        aircraft.pressure_setting =
+                            report.pressure_setting.or(aircraft.pressure_setting);
+                    }
+                    // The wind is cleared if it's unavailable, as its speed and direction go
+                    // together
+                    Some(CommB::MeteorologicalRoutineReport(report)) => {
+                        aircraft.wind_speed = report.wind_speed;
+                        aircraft.wind_direction = report.wind_direction;
+                        aircraft.static_air_temperature = Some(report.static_air_temperature);
+                    }
+                    Some(CommB::MeteorologicalHazardReport(report)) => {
+                        aircraft.static_air_temperature = report
+                            .static_air_temperature
+                            .or(aircraft.static_air_temperature);
+                    }
+                    Some(CommB::TrackAndTurn(report)) => {
+                        aircraft.roll_angle = report.roll_angle.or(aircraft.roll_angle);
+                        aircraft.track_angle_rate =
+                            report.track_angle_rate.or(aircraft.track_angle_rate);
+                        aircraft.true_airspeed = report.true_airspeed.or(aircraft.true_airspeed);
+                    }
+                    Some(CommB::HeadingAndSpeed(report)) => {
+                        aircraft.indicated_airspeed =
+                            report.indicated_airspeed.or(aircraft.indicated_airspeed);
+                        aircraft.mach = report.mach.or(aircraft.mach);
+                    }
+                    None => {}
+                }
+                aircraft.last_seen = time;
+            }
+            17 => {
+                let velocity = decode_airspeed_velocity(frame);
+                let target_state = decode_target_state(frame);
+                let surface_position = decode_surface_position(frame);
+                // Only reached by airborne positions the adsb crate rejected, which are those
+                // below sea level
+                let airborne_position = decode_airborne_position(frame);
+                let gnss_position = decode_gnss_position(frame);
+                if velocity.is_none()
+                    && target_state.is_none()
+                    && surface_position.is_none()
+                    && airborne_position.is_none()
+                    && gnss_position.is_none()
+                {
+                    return false;
+                }
+                let icao_address = match icao_from_u32(address_from_frame(frame)) {
+                    Some(icao_address) => icao_address,
+                    None => return false,
+                };
+                self.record_acquisition(icao_address, time);
+                let registry = self.registry.as_deref();
+                let aircraft = self
+                    .map
+                    .entry(icao_address)
+                    .or_insert_with(|| new_aircraft(icao_address, time, registry));
+                aircraft.count_message(time);
+                if let Some(velocity) = velocity {
+                    if let Some(heading) = velocity.heading {
+                        aircraft.update_heading(heading, time);
+                        aircraft.heading_reference = Some(HeadingReference::Magnetic);
+                    }
+                    if velocity.true_airspeed.is_some() {
+                        aircraft.true_airspeed = velocity.true_airspeed;
+                    }
+                }
+                // Settings marked as unavailable are cleared rather than kept from earlier
+                if let Some(target_state) = target_state {
+                    aircraft.selected_altitude = target_state.selected_altitude;
+                    aircraft.selected_heading = target_state.selected_heading;
+                    aircraft.pressure_setting = target_state.pressure_setting;
+                }
+                // Surface positions can only be resolved near a known location
+                let mut surface_resolved = false;
+                if let Some(surface_position) = surface_position {
+                    aircraft.on_ground = Some(true);
+                    let position = self
+                        .receiver_position
+                        .as_ref()
+                        .and_then(|receiver| surface_position.resolve(receiver));
+                    if let Some(position) = position {
+                        aircraft.latitude = Some(position.latitude);
+                        aircraft.longitude = Some(position.longitude);
+                        aircraft.position_altitude = None;
+                        surface_resolved = true;
+                    }
+                }
+                if let Some((geometric_altitude, _)) = gnss_position {
+                    aircraft.geometric_altitude = Some(geometric_altitude);
+                }
+                aircraft.last_seen = time;
+                if surface_resolved {
+                    self.record_position(icao_address, time);
+                }
+                if let Some((altitude, cpr_frame)) = airborne_position {
+                    self.update_airborne_position(icao_address, Some(altitude), cpr_frame, time);
+                }
+                // Positions with GNSS height don't carry a barometric altitude
+                if let Some((_, cpr_frame)) = gnss_position {
+                    self.update_airborne_position(icao_address, None, cpr_frame, time);
+                }
+            }
+            _ => return false,
+        }
+        *self.known_message_counts.entry(df).or_insert(0) += 1;
+        true
+    }
+
+    fn update_with_message(&mut self, message: Message, frame: &[u8], time: chrono::DateTime<Utc>) {
+        use ADSBMessageKind::*;
+
+        if self.is_duplicate(frame, time) {
+            self.num_duplicate_messages += 1;
+            return;
+        }
+        self.num_messages += 1;
+        self.update_message_rate(self.now());
+        self.last_message_time = self.last_message_time.max(Some(time));
+        let (icao_address, type_code, kind) = match message {
+
+            Message {
+                kind: ADSBMessage {
+                    icao_address, type_code, kind, ..
+                },
+                ..
+            } => {
+                *self.known_message_counts.entry(message.downlink_format).or_insert(0) += 1;
+                (icao_address, type_code, kind)
+            },
+            _ => {
+                if !self.update_with_mode_s_reply(&message, frame, time) {
+                    self.update_unknown_message_statistics(message);
+                }
+                return
+            },
+        };
+
+        self.record_acquisition(icao_address, time);
+        let registry = self.registry.as_deref();
+        let aircraft = self
+            .map
+            .entry(icao_address)
+            .or_insert_with(|| new_aircraft(icao_address, time, registry));
+        aircraft.count_message(time);
+
+        match kind {
+            AircraftIdentification {
+                emitter_category,
+                callsign,
+            } => {
+                let category = EmitterCategory::from_identification(type_code, emitter_category);
+                if category.is_some() {
+                    aircraft.emitter_category = category;
+                }
+                if aircraft.update_callsign(&callsign) {
+                    emit(&mut self.on_event, || callsign_event(aircraft, time));
+                }
+            }
+            AirbornePosition {
+                altitude,
+                cpr_frame,
+            } => {
+                let altitude = Some(i32::from(altitude));
+                self.update_airborne_position(icao_address, altitude, cpr_frame, time);
+                return;
+            }
+            AirborneVelocity {
+                heading,
+                ground_speed,
+                vertical_rate,
+                vertical_rate_source,
+            } => {
+                aircraft.update_heading(heading, time);
+                aircraft.heading_reference = Some(HeadingReference::True);
+                aircraft.update_ground_speed(ground_speed);
+                aircraft.vertical_rate = Some(vertical_rate);
+                aircraft.vertical_rate_source = Some(vertical_rate_source);
+                if let (Some(altitude), Some(difference)) =
+                    (aircraft.altitude, decode_altitude_difference(frame))
+                {
+                    aircraft.geometric_altitude = Some(altitude + difference);
+                }
+            }
+        }
+
+        aircraft.last_seen = time;
+    }
+
+    fn update_airborne_position(
+        &mut self,
+        icao_address: ICAOAddress,
+        altitude: Option<i32>,
+        cpr_frame: CPRFrame,
+        time: chrono::DateTime<Utc>,
+    ) {
+        let registry = self.registry.as_deref();
+        let aircraft = self
+            .map
+            .entry(icao_address)
+            .or_insert_with(|| new_aircraft(icao_address, time, registry));
+        if let Some(altitude) = altitude {
+            aircraft.update_altitude(altitude);
+        }
+        aircraft.on_ground = Some(false);
+        let decoding = PositionDecoding {
+            window: self
+                .cpr_window
+                .unwrap_or_else(|| Duration::seconds(DEFAULT_CPR_WINDOW_SECS)),
+            receiver: self.receiver_position.as_ref(),
+            max_range: self.max_range.unwrap_or(DEFAULT_MAX_RANGE_NM),
+        };
+        let update = aircraft.update_position(cpr_frame, altitude, time, &decoding);
+        aircraft.last_seen = time;
+        match update {
+            PositionUpdate::Decoded | PositionUpdate::DecodedLocally => {
+                if update == PositionUpdate::DecodedLocally {
+                    self.position_statistics.local += 1;
+                }
+                self.record_position(icao_address, time);
+            }
+            PositionUpdate::Failed => self.position_statistics.failed += 1,
+            PositionUpdate::Waiting => self.position_statistics.waiting += 1,
+            PositionUpdate::Rejected => self.position_statistics.rejected += 1,
+        }
+    }
+
+    /// Record a newly decoded position of an aircraft: count it, add it to the aircraft's track,
+    /// check it against the farthest position seen, log it and emit a position update.
+    fn record_position(&mut self, icao_address: ICAOAddress, time: chrono::DateTime<Utc>) {
+        let aircraft = match self.map.get_mut(&icao_address) {
+            Some(aircraft) => aircraft,
+            None => return,
+        };
+        self.position_statistics.decoded += 1;
+        let max_points = self.max_track_points.unwrap_or(DEFAULT_MAX_TRACK_POINTS);
+        aircraft.push_track_point(
+            time,
+            max_points,
+            self.max_track_age,
+            self.track_min_distance,
+        );
+        update_max_range(
+            &mut self.max_range_record,
+            self.receiver_position.as_ref(),
+            aircraft,
+            time,
+        );
+        log_position(
+            &mut self.position_log,
+            self.position_log_interval,
+            aircraft,
+            time,
+        );
+        if let Some(record) = aircraft.position_record(time) {
+            emit(&mut self.on_event, || TrackerEvent::PositionUpdate(record));
+        }
+    }
+
+    /// Get a list of aircraft last seen in the given interval before `now`
+    pub fn get_current_aircraft(
+        &self,
+        interval: &Duration,
+        now: chrono::DateTime<Utc>,
+    ) -> Vec<&Aircraft> {
+        self.iter_current_aircraft(*interval, now).collect()
+    }
+
+    /// Iterate over the aircraft last seen in the given interval before `now`, without
+    /// collecting them, so they can be counted or filtered further
+    pub fn iter_current_aircraft(
+        &self,
+        interval: Duration,
+        now: chrono::DateTime<Utc>,
+    ) -> impl Iterator<Item = &Aircraft> {
+        self.map
+            .values()
+            .filter(move |a| now.signed_duration_since(a.last_seen) < interval)
+    }
+
+    /// Find aircraft seen in the given interval before `now` which share a callsign. Aircraft
+    /// only conflict if they were active at the same time, so a callsign reused by one aircraft
+    /// after another has stopped transmitting isn't reported.
+    pub fn get_callsign_conflicts(
+        &self,
+        interval: &Duration,
+        now: chrono::DateTime<Utc>,
+    ) -> Vec<CallsignConflict> {
+        let mut by_callsign: HashMap<&str, Vec<&Aircraft>> = HashMap::new();
+        for aircraft in self.iter_current_aircraft(*interval, now) {
+            if let Some(callsign) = &aircraft.callsign {
+                by_callsign.entry(callsign).or_default().push(aircraft);
+            }
+        }
+        let mut conflicts: Vec<_> = by_callsign
+            .into_iter()
+            .filter_map(|(callsign, aircraft)| {
+                let overlaps = |a: &Aircraft, b: &Aircraft| {
+                    a.first_seen <= b.last_seen && b.first_seen <= a.last_seen
+                };
+                let mut icao_addresses: Vec<_> = aircraft
+                    .iter()
+                    .filter(|a| {
+                        aircraft
+                            .iter()
+                            .any(|b| a.icao_address != b.icao_address && overlaps(a, b))
+                    })
+                    .map(|a| a.icao_address)
+                    .collect();
+                if icao_addresses.is_empty() {
+                    return None;
+                }
+                icao_addresses.sort_by_key(icao_to_u32);
+                Some(CallsignConflict {
+                    callsign: callsign.to_string(),
+                    icao_addresses,
+                })
+            })
+            .collect();
+        conflicts.sort_by(|a, b| a.callsign.cmp(&b.callsign));
+        conflicts
+    }
+
+    /// Find pairs of aircraft within `horiz_nm` nautical miles and `vert_ft` feet of each other,
+    /// along with the horizontal distance between them. Only aircraft with an altitude and a
+    /// position received in the given interval before `now` are compared. Each pair is reported
+    /// once, with the lower address first, and the closest pairs come first.
+    pub fn proximity_events(
+        &self,
+        horiz_nm: f64,
+        vert_ft: i32,
+        interval: &Duration,
+        now: chrono::DateTime<Utc>,
+    ) -> Vec<(ICAOAddress, ICAOAddress, f64)> {
+        let mut positioned: Vec<_> = self
+            .map
+            .values()
+            .filter_map(|a| {
+                let point = a.track.last()?;
+                if now.signed_duration_since(point.time) >= *interval {
+                    return None;
+                }
+                Some((a.icao_address, (a.latitude?, a.longitude?), a.altitude?))
+            })
+            .collect();
+        positioned.sort_by_key(|(icao_address, ..)| icao_to_u32(icao_address));
+        let mut events: Vec<_> = positioned
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| positioned[i + 1..].iter().map(move |b| (a, b)))
+            .filter_map(|(a, b)| {
+                let distance = haversine_nm(a.1, b.1);
+                let close = distance <= horiz_nm && (a.2 - b.2).abs() <= vert_ft;
+                close.then_some((a.0, b.0, distance))
+            })
+            .collect();
+        events.sort_by(|a, b| a.2.total_cmp(&b.2));
+        events
+    }
+
+    /// Get owned snapshots of the aircraft last seen in the given interval before `now`
+    pub fn current_aircraft_owned(
+        &self,
+        interval: &Duration,
+        now: chrono::DateTime<Utc>,
+    ) -> Vec<AircraftSnapshot> {
+        self.map
+            .values()
+            .filter(|a| now.signed_duration_since(a.last_seen) < *interval)
+            .map(|a| a.snapshot(now))
+            .collect()
+    }
+
+    /// Count the aircraft last seen in the given interval before `now` by whether they squawk a
+    /// VFR or a discrete code, following the conventions of the given region. Aircraft squawking
+    /// special codes (conspicuity, emergencies) are left out.
+    pub fn get_squawk_summary(
+        &self,
+        interval: &Duration,
+        region: SquawkRegion,
+        now: chrono::DateTime<Utc>,
+    ) -> SquawkSummary {
+        let mut summary = SquawkSummary::default();
+        for aircraft in self.iter_current_aircraft(*interval, now) {
+            match aircraft.squawk.map(|s| s.classify(region)) {
+                Some(SquawkClass::Vfr) => summary.vfr += 1,
+                Some(SquawkClass::Discrete) => summary.discrete += 1,
+                Some(SquawkClass::Special) => {}
+                None => summary.unknown += 1,
+            }
+        }
+        summary
+    }
+
+    /// Get the aircraft currently squawking an emergency code
+    pub fn get_emergency_aircraft(&self) -> Vec<&Aircraft> {
+        self.map
+            .values()
+            .filter(|a| a.emergency().is_some())
+            .collect()
+    }
+
+    /// Get the aircraft with the given address, if it has been seen
+    pub fn get_aircraft(&self, icao_address: &ICAOAddress) -> Option<&Aircraft> {
+        self.map.get(icao_address)
+    }
+
+    /// Get the track of the aircraft with the given address as KML, see `aircraft_to_kml`
+    pub fn aircraft_to_kml(&self, icao_address: &ICAOAddress) -> Option<String> {
+        kml::aircraft_to_kml(self.get_aircraft(icao_address)?)
+    }
+
+    /// Find the aircraft using a callsign, ignoring case and surrounding spaces, ordered by
+    /// address. Usually there's at most one.
+    pub fn find_by_callsign(&self, callsign: &str) -> Vec<&Aircraft> {
+        let callsign = callsign.trim();
+        let mut found: Vec<_> = self
+            .map
+            .values()
+            .filter(|a| match &a.callsign {
+                Some(c) => c.trim().eq_ignore_ascii_case(callsign),
+                None => false,
+            })
+            .collect();
+        found.sort_by_key(|a| icao_to_u32(&a.icao_address));
+        found
+    }
+
+    /// Find the aircraft whose position is inside a latitude/longitude box (degrees), ordered by
+    /// address. A box with `min_lon` greater than `max_lon` crosses the antimeridian. Aircraft
+    /// without a position are left out.
+    pub fn aircraft_in_bbox(
+        &self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> Vec<&Aircraft> {
+        let mut found: Vec<_> = self
+            .map
+            .values()
+            .filter(|a| match (a.latitude, a.longitude) {
+                (Some(lat), Some(lon)) => {
+                    let in_lon = if min_lon <= max_lon {
+                        min_lon <= lon && lon <= max_lon
+                    } else {
+                        lon >= min_lon || lon <= max_lon
+                    };
+                    min_lat <= lat && lat <= max_lat && in_lon
+                }
+                _ => false,
+            })
+            .collect();
+        found.sort_by_key(|a| icao_to_u32(&a.icao_address));
+        found
+    }
+
+    /// Whether the aircraft with the given address is being tracked
+    pub fn contains(&self, icao_address: &ICAOAddress) -> bool {
+        self.map.contains_key(icao_address)
+    }
+
+    /// Get the number of aircraft being tracked
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether no aircraft are being tracked
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    // Get a list of all tracked aircraft
+    pub fn get_all_aircraft(&self) -> Vec<&Aircraft> {
+        self.map.values().collect()
+    }
+
+    pub fn get_num_messages(&self) -> u64 {
+        self.num_messages
+    }
+
+    pub fn get_num_unknown_messages(&self) -> u64 {
+        self.num_unknown_messages
+    }
+
+    /// Get the number of distinct aircraft heard from, including those no longer tracked
+    pub fn get_num_aircraft_seen(&self) -> usize {
+        self.seen_aircraft.len()
+    }
+
+    /// Get the number of positions decoded. Frames which fail to decode to a position aren't
+    /// counted.
+    pub fn get_num_positions(&self) -> u64 {
+        self.position_statistics.decoded
+    }
+
+    /// Get counts of the outcomes of position decoding
+    pub fn get_position_statistics(&self) -> &PositionStatistics {
+        &self.position_statistics
+    }
+
+    /// Get the farthest distance from the receiver at which a position has been received
+    /// (nautical miles), along with the aircraft and when. `None` until the receiver's location
+    /// is set and a position has been received since.
+    pub fn get_max_range(&self) -> Option<(f64, ICAOAddress, chrono::DateTime<Utc>)> {
+        self.max_range_record
+    }
+
+    /// Get the number of frames dropped as duplicates, see `set_dedup_window`
+    pub fn get_num_duplicate_messages(&self) -> u64 {
+        self.num_duplicate_messages
+    }
+
+    /// Get the number of frames dropped for not being the length of a Mode-S message
+    pub fn get_num_invalid_length_frames(&self) -> u64 {
+        self.error_statistics.invalid_length
+    }
+
+    /// Get counts of the frames which couldn't be used, by the reason why
+    pub fn get_error_statistics(&self) -> &ErrorStatistics {
+        &self.error_statistics
+    }
+
+    pub fn get_unknown_message_statistics(&self) -> &HashMap<u8, u64> {
+        &self.unknown_message_counts
+    }
+
+    pub fn get_known_message_statistics(&self) -> &HashMap<u8, u64> {
+        &self.known_message_counts
+    }
+
+    /// Get the time of the most recent message, by the time it was received rather than the time
+    /// it was processed, which differ when replaying timestamped messages
+    pub fn get_last_message_time(&self) -> Option<chrono::DateTime<Utc>> {
+        self.last_message_time
+    }
+
+    /// Get the time against which aircraft are current: that of the most recent message, so that
+    /// replayed messages are judged by their own timestamps, or the clock before any message
+    pub fn get_current_time(&self) -> chrono::DateTime<Utc> {
+        self.last_message_time.unwrap_or_else(|| self.now())
+    }
+
+    /// Get the average number of messages received per second (wall clock) over the whole session
+    pub fn get_messages_per_second_real_time(&self) -> Option<f64> {
+        let start = self.first_message_real_time?;
+        let end = self.last_message_real_time?;
+        let elapsed = end.signed_duration_since(start).num_milliseconds();
+        if elapsed <= 0 {
+            return None;
+        }
+        Some(self.num_messages as f64 / (elapsed as f64 / 1000.0))
+    }
+
+    /// Get the number of messages received per second (wall clock) over the last few seconds.
+    ///
+    /// Messages are counted in one second buckets over a window of `RATE_WINDOW_SECS`. Until a
+    /// full window has elapsed since the first message the count is averaged over the elapsed
+    /// time instead (but never less than one second), so the rate ramps up rather than spiking.
+    pub fn get_messages_per_second_recent(&self) -> Option<f64> {
+        let start = self.first_message_real_time?;
+        let now = self.now();
+        let cutoff = now.timestamp() - RATE_WINDOW_SECS;
+        let count: u64 = self
+            .recent_message_counts
+            .iter()
+            .filter(|(s, _)| *s > cutoff)
+            .map(|(_, count)| count)
+            .sum();
+        let elapsed = now.signed_duration_since(start).num_milliseconds() as f64 / 1000.0;
+        let span = elapsed.max(1.0).min(RATE_WINDOW_SECS as f64);
+        Some(count as f64 / span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::CsvRegistry;
+    use chrono::TimeZone;
+    use std::sync::{Arc, Mutex};
+
+    const IDENTIFICATION: &str = "*8D4840D6202CC371C32CE0576098;";
+    const POSITION_EVEN: &str = "*8D40621D58C382D690C8AC2863A7;";
+    const POSITION_ODD: &str = "*8D40621D58C386435CC412692AD6;";
+    const VELOCITY: &str = "*8D485020994409940838175B284F;";
+    // DF 11 all-call reply
+    const ALL_CALL: &str = "*5D4840D6A0B2C3;";
+    // DF 4 altitude reply
+    const ALTITUDE_REPLY: &str = "*20000F1F684A6C;";
+    // DF 17 with an unsupported type code (aircraft operational status)
+    const OPERATIONAL_STATUS: &str = "*8D4840D6F8230002004AB8DC727C;";
+    // DF 5 identity replies squawking 7000, from 4840D6 and from an aircraft never seen before
+    const IDENTITY_REPLY: &str = "*28000A800349D8;";
+    const IDENTITY_REPLY_UNKNOWN: &str = "*28000A80E0C4E1;";
+    // Comm-B replies to an interrogation of 4840D6, with a selected vertical intention (BDS 4,0)
+    // and track and turn (BDS 5,0) and heading and speed (BDS 6,0) reports
+    const SELECTED_VERTICAL_INTENTION: &str = "*A000029C85E42F313000007A44D5;";
+    // Meteorological routine (BDS 4,4) and hazard (BDS 4,5) reports from 4840D6
+    const METEOROLOGICAL_ROUTINE: &str = "*A0001692185BD5CF400000ABCB97;";
+    const METEOROLOGICAL_HAZARD: &str = "*A0000000A001E200000000E5C3D2;";
+    const TRACK_AND_TURN: &str = "*A000139381951536E024D4B8FBB1;";
+    const HEADING_AND_SPEED: &str = "*A00004128F39F91A7E27C46ACC88;";
+    // DF 17 velocity with airspeed and magnetic heading
+    const AIRSPEED_VELOCITY: &str = "*8DA05F219B06B6AF189400CBC33F;";
+
+    fn test_aircraft(time: chrono::DateTime<Utc>) -> Aircraft {
+        let (message, _) = adsb::parse_avr(IDENTIFICATION).unwrap();
+        match message.kind {
+            ADSBMessage { icao_address, .. } => Aircraft::new(icao_address, time),
+            _ => unreachable!(),
+        }
+    }
+
+    fn update(tracker: &mut Tracker, frames: &[&str]) {
+        for frame in frames {
+            tracker.update_with_avr(frame, Utc::now()).unwrap();
+        }
+    }
+
+    #[test]
+    fn message_rate_needs_elapsed_time() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[IDENTIFICATION, VELOCITY]);
+        // Pin the processing times, which are taken from the clock, to the same instant
+        let now = Utc::now();
+        tracker.first_message_real_time = Some(now);
+        tracker.last_message_real_time = Some(now);
+        assert_eq!(tracker.get_messages_per_second_real_time(), None);
+        tracker.last_message_real_time = Some(now - Duration::seconds(1));
+        assert_eq!(tracker.get_messages_per_second_real_time(), None);
+        tracker.last_message_real_time = Some(now + Duration::seconds(1));
+        assert_eq!(tracker.get_messages_per_second_real_time(), Some(2.0));
+    }
+
+    #[test]
+    fn message_rates_follow_clock() {
+        let clock = Arc::new(Mutex::new(Utc.timestamp_millis(1_600_000_000_000)));
+        let mut tracker = Tracker::new();
+        let time = clock.clone();
+        tracker.set_clock(Box::new(move || *time.lock().unwrap()));
+        let advance = |seconds| {
+            let mut now = clock.lock().unwrap();
+            *now = *now + Duration::seconds(seconds);
+        };
+        update(&mut tracker, &[IDENTIFICATION, VELOCITY]);
+        assert_eq!(tracker.get_messages_per_second_real_time(), None);
+        // Two messages in the first second are averaged over a second
+        assert_eq!(tracker.get_messages_per_second_recent(), Some(2.0));
+        advance(4);
+        update(&mut tracker, &[IDENTIFICATION, VELOCITY]);
+        assert_eq!(tracker.get_messages_per_second_real_time(), Some(1.0));
+        assert_eq!(tracker.get_messages_per_second_recent(), Some(1.0));
+        // Once the first messages fall out of the window only the later ones count
+        advance(8);
+        assert_eq!(tracker.get_messages_per_second_recent(), Some(0.2));
+        assert_eq!(tracker.get_messages_per_second_real_time(), Some(1.0));
+    }
+
+    #[test]
+    fn message_statistics_add_up() {
+        let mut tracker = Tracker::new();
+        update(
+            &mut tracker,
+            &[
+                IDENTIFICATION,
+                ALL_CALL,
+                POSITION_EVEN,
+                ALTITUDE_REPLY,
+                POSITION_ODD,
+                ALL_CALL,
+                VELOCITY,
+                OPERATIONAL_STATUS,
+            ],
+        );
+
+        let known: u64 = tracker.get_known_message_statistics().values().sum();
+        let unknown: u64 = tracker.get_unknown_message_statistics().values().sum();
+        assert_eq!(tracker.get_num_messages(), 8);
+        assert_eq!(known, 4);
+        assert_eq!(unknown, 4);
+        assert_eq!(tracker.get_num_unknown_messages(), unknown);
+        assert_eq!(known + unknown, tracker.get_num_messages());
+    }
+
+    #[test]
+    fn message_statistics_keyed_by_downlink_format() {
+        let mut tracker = Tracker::new();
+        update(
+            &mut tracker,
+            &[
+                IDENTIFICATION,
+                ALL_CALL,
+                ALL_CALL,
+                ALTITUDE_REPLY,
+                OPERATIONAL_STATUS,
+            ],
+        );
+
+        let known = tracker.get_known_message_statistics();
+        assert_eq!(known.len(), 1);
+        assert_eq!(known[&17], 1);
+
+        let unknown = tracker.get_unknown_message_statistics();
+        assert_eq!(unknown.len(), 3);
+        assert_eq!(unknown[&11], 2);
+        assert_eq!(unknown[&4], 1);
+        assert_eq!(unknown[&17], 1);
+    }
+
+    #[test]
+    fn positions_counted() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[POSITION_EVEN, VELOCITY, POSITION_ODD]);
+        assert_eq!(tracker.get_num_positions(), 1);
+        let statistics = tracker.get_position_statistics();
+        assert_eq!((statistics.waiting, statistics.failed), (1, 0));
+        update(&mut tracker, &[POSITION_EVEN]);
+        assert_eq!(tracker.get_num_positions(), 2);
+    }
+
+    #[test]
+    fn current_aircraft_iterated_lazily() {
+        let mut tracker = Tracker::new();
+        let now = Utc::now();
+        tracker.update_with_avr(VELOCITY, now).unwrap();
+        tracker
+            .update_with_avr(IDENTIFICATION, now - Duration::minutes(5))
+            .unwrap();
+        let interval = Duration::minutes(1);
+        let current: Vec<_> = tracker.iter_current_aircraft(interval, now).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].icao_address, icao_from_u32(0x485020).unwrap());
+        let later = now + Duration::minutes(2);
+        assert_eq!(tracker.iter_current_aircraft(interval, later).count(), 0);
+        assert_eq!(tracker.get_current_aircraft(&interval, now).len(), 1);
+    }
+
+    #[test]
+    fn replayed_aircraft_current_as_of_last_message() {
+        let mut tracker = Tracker::new();
+        let start = Utc.timestamp_millis(1_604_340_192_345);
+        assert!(tracker.get_current_time() > start);
+        for (i, frame) in [IDENTIFICATION, POSITION_EVEN, VELOCITY].iter().enumerate() {
+            let time = start + Duration::seconds(i as i64);
+            tracker.update_with_avr(frame, time).unwrap();
+        }
+        let now = tracker.get_current_time();
+        assert_eq!(now, start + Duration::seconds(2));
+        let interval = Duration::minutes(1);
+        assert_eq!(tracker.get_current_aircraft(&interval, now).len(), 3);
+        let summary = tracker.get_squawk_summary(&interval, SquawkRegion::Europe, now);
+        assert_eq!(summary.unknown, 3);
+    }
+
+    #[test]
+    fn callsign_requires_confirmation() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[IDENTIFICATION]);
+        assert_eq!(tracker.get_all_aircraft()[0].callsign, None);
+        update(&mut tracker, &[IDENTIFICATION]);
+        let callsign = tracker.get_all_aircraft()[0].callsign.clone();
+        assert_eq!(callsign.as_deref(), Some("KLM1023"));
+    }
+
+    #[test]
+    fn avr_frames_of_both_lengths_decoded() {
+        let (data, _) = decode_avr(ALL_CALL).unwrap();
+        assert_eq!(data, vec![0x5D, 0x48, 0x40, 0xD6, 0xA0, 0xB2, 0xC3]);
+        assert_eq!(data[0] >> 3, 11);
+        let (data, _) = decode_avr(&format!(" {}\r\n", IDENTIFICATION)).unwrap();
+        assert_eq!(data.len(), LONG_FRAME_LEN);
+        assert_eq!(data[0] >> 3, 17);
+        assert_eq!(data[LONG_FRAME_LEN - 1], 0x98);
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[ALL_CALL, IDENTIFICATION]);
+        assert_eq!(tracker.get_num_messages(), 2);
+        assert_eq!(tracker.get_unknown_message_statistics()[&11], 1);
+        assert_eq!(tracker.get_known_message_statistics()[&17], 1);
+    }
+
+    #[test]
+    fn short_frame_counted_as_invalid_length() {
+        let mut tracker = Tracker::new();
+        let result = tracker.update_with_avr("*8D4840;", Utc::now());
+        assert!(matches!(result, Err(FrameError::InvalidLength(3))));
+        assert_eq!(tracker.get_num_invalid_length_frames(), 1);
+        assert_eq!(tracker.get_num_messages(), 0);
+        assert!(tracker.get_unknown_message_statistics().is_empty());
+    }
+
+    #[test]
+    fn errors_counted_by_kind() {
+        // IDENTIFICATION with a bit of the callsign flipped
+        const CORRUPTED: &str = "*8D4840D6202CC371C32CE1576098;";
+        let mut tracker = Tracker::new();
+        let result = tracker.update_with_avr(CORRUPTED, Utc::now());
+        assert!(matches!(result, Err(FrameError::Crc)));
+        assert!(tracker.get_all_aircraft().is_empty());
+        let _ = tracker.update_with_avr("*8D4840;", Utc::now());
+        let _ = tracker.update_with_avr("*ZZ;", Utc::now());
+        update(&mut tracker, &[IDENTIFICATION, OPERATIONAL_STATUS]);
+        let errors = tracker.get_error_statistics();
+        let expected = ErrorStatistics {
+            invalid_avr: 1,
+            invalid_length: 1,
+            crc: 1,
+            parser: 0,
+            unsupported: 1,
+        };
+        assert_eq!(*errors, expected);
+    }
+
+    #[test]
+    fn frames_timed_by_mlat_timestamps() {
+        let mut tracker = Tracker::new();
+        let received = Utc::now();
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let mut last_seen = |frame: &str| {
+            tracker.update_with_avr(frame, received).unwrap();
+            tracker.get_aircraft(&icao_address).unwrap().last_seen
+        };
+        let first = last_seen("@00000100000A8D4840D6202CC371C32CE0576098;");
+        assert_eq!(first, received);
+        // 1.5 seconds of the 12 MHz clock later, but received at the same time
+        let later = last_seen("@00000212A88A8D4840D6202CC371C32CE0576098;");
+        assert_eq!(later, received + Duration::milliseconds(1500));
+        // The clock going backwards, as when the receiver restarts
+        let restarted = last_seen("@0000000000008D4840D6202CC371C32CE0576098;");
+        assert_eq!(restarted, received);
+        assert_eq!(last_seen(IDENTIFICATION), received);
+        let result = tracker.update_with_avr("@0000010*8D4840D6202CC371C32CE0576098;", received);
+        assert!(matches!(result, Err(FrameError::InvalidAvr)));
+    }
+
+    #[test]
+    fn odd_length_avr_rejected() {
+        // IDENTIFICATION with its last hex digit missing
+        let frame = "*8D4840D6202CC371C32CE057609;";
+        assert!(adsb::parse_avr(frame).is_err());
+        let mut tracker = Tracker::new();
+        let result = tracker.update_with_avr(frame, Utc::now());
+        assert!(matches!(result, Err(FrameError::InvalidAvr)));
+        assert_eq!(tracker.get_num_messages(), 0);
+        assert_eq!(tracker.get_num_invalid_length_frames(), 0);
+        assert!(tracker.get_all_aircraft().is_empty());
+    }
+
+    #[test]
+    fn malformed_avr_rejected_without_panicking() {
+        let mut tracker = Tracker::new();
+        // Bad hex, empty, missing the delimiter, empty line, reversed delimiters, not ASCII
+        let frames = [
+            "*ZZ;",
+            "*;",
+            "*8D4840D6202CC371C32CE05760",
+            "",
+            ";*",
+            "*8D\u{e9}9;",
+        ];
+        for frame in &frames {
+            let result = tracker.update_with_avr(frame, Utc::now());
+            assert!(result.is_err(), "{}", frame);
+        }
+        update(&mut tracker, &[IDENTIFICATION]);
+        assert_eq!(tracker.get_num_messages(), 1);
+    }
+
+    #[test]
+    fn heading_normalized() {
+        let start = Utc::now();
+        let mut aircraft = test_aircraft(start);
+        aircraft.update_heading(-45.0, start);
+        assert_eq!(aircraft.heading, Some(315.0));
+        aircraft.update_heading(360.0, start);
+        assert_eq!(aircraft.heading, Some(0.0));
+        aircraft.update_heading(725.5, start);
+        assert_eq!(aircraft.heading, Some(5.5));
+        aircraft.update_heading(f64::NAN, start);
+        assert_eq!(aircraft.heading, Some(5.5));
+    }
+
+    #[test]
+    fn track_trimmed_by_count_and_age() {
+        let start = Utc::now();
+        let mut aircraft = test_aircraft(start);
+        aircraft.latitude = Some(52.0);
+        aircraft.longitude = Some(4.0);
+        for i in 0..10 {
+            let time = start + Duration::seconds(i * 10);
+            aircraft.push_track_point(time, 5, Some(Duration::seconds(30)), None);
+        }
+        // The age limit (4 points within 30s) is hit before the count limit
+        let times: Vec<_> = aircraft.track().iter().map(|p| p.time).collect();
+        let expected: Vec<_> = (6..10).map(|i| start + Duration::seconds(i * 10)).collect();
+        assert_eq!(times, expected);
+
+        // After a long gap only the new point is left
+        let time = start + Duration::minutes(10);
+        aircraft.push_track_point(time, 5, Some(Duration::seconds(30)), None);
+        assert_eq!(aircraft.track().len(), 1);
+        assert_eq!(aircraft.track()[0].time, time);
+    }
+
+    #[test]
+    fn track_deduplicates_stationary_points() {
+        let start = Utc::now();
+        let mut aircraft = test_aircraft(start);
+        aircraft.longitude = Some(4.0);
+        // Parked for a while, then moving off
+        let latitudes = [52.0, 52.0, 52.0001, 52.0, 52.0001, 52.1, 52.2];
+        for (i, latitude) in latitudes.iter().enumerate() {
+            aircraft.latitude = Some(*latitude);
+            let time = start + Duration::seconds(i as i64);
+            aircraft.push_track_point(time, 100, None, Some(0.05));
+        }
+        let seconds: Vec<_> = aircraft
+            .track()
+            .iter()
+            .map(|p| p.time.signed_duration_since(start).num_seconds())
+            .collect();
+        assert_eq!(seconds, vec![0, 4, 5, 6]);
+    }
+
+    #[test]
+    fn acquisitions_recorded_once_enabled() {
+        let mut tracker = Tracker::new();
+        let start = Utc::now();
+        tracker.update_with_avr(VELOCITY, start).unwrap();
+        tracker.enable_acquisitions(Some(Duration::minutes(5)));
+        tracker.update_with_avr(IDENTIFICATION, start).unwrap();
+        tracker.update_with_avr(IDENTIFICATION, start).unwrap();
+        tracker
+            .update_with_avr(VELOCITY, start + Duration::minutes(10))
+            .unwrap();
+        let acquisitions = tracker.take_acquisitions();
+        let reacquired: Vec<_> = acquisitions
+            .iter()
+            .map(|a| (a.icao_address.to_string(), a.reacquired))
+            .collect();
+        assert_eq!(
+            reacquired,
+            vec![("4840D6".to_string(), false), ("485020".to_string(), true)]
+        );
+        assert!(tracker.take_acquisitions().is_empty());
+    }
+
+    #[test]
+    fn movement_at_home_airport() {
+        let airport: Airport = "52.3086,4.7639,-11".parse().unwrap();
+        let start = Utc::now();
+        let fly = |latitudes: &[f64], altitude: i32, vertical_rate: i16| {
+            let mut aircraft = test_aircraft(start);
+            aircraft.longitude = Some(4.7639);
+            aircraft.position_altitude = Some(altitude);
+            aircraft.vertical_rate = Some(vertical_rate);
+            for (i, latitude) in latitudes.iter().enumerate() {
+                aircraft.latitude = Some(*latitude);
+                let time = start + Duration::seconds(i as i64 * 20);
+                aircraft.push_track_point(time, 100, None, None);
+            }
+            aircraft.movement(&airport)
+        };
+        let approaching = [52.45, 52.43, 52.41, 52.39];
+        let leaving = [52.32, 52.34, 52.36, 52.38];
+        assert_eq!(fly(&approaching, 2000, -700), Some(Movement::Arrival));
+        assert_eq!(fly(&leaving, 2000, 1500), Some(Movement::Departure));
+        // Low overflight, descending while moving away or level
+        assert_eq!(fly(&leaving, 2000, -500), None);
+        assert_eq!(fly(&approaching, 1500, 0), None);
+        // Too high, and too far away
+        assert_eq!(fly(&approaching, 9000, -700), None);
+        assert_eq!(fly(&[53.0, 52.95], 2000, -700), None);
+        assert!("52.3,4.7,x".parse::<Airport>().is_err());
+    }
+
+    #[test]
+    fn emitter_category_from_identification() {
+        // Identification of 4840D6 as a heavy aircraft
+        const HEAVY_IDENTIFICATION: &str = "*8D4840D6252CC371C32CE00519A1;";
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[IDENTIFICATION]);
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.emitter_category, None);
+        update(&mut tracker, &[HEAVY_IDENTIFICATION]);
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.emitter_category, Some(EmitterCategory::Heavy));
+        // The callsign is still counted
+        assert_eq!(aircraft.callsign.as_deref(), Some("KLM1023"));
+    }
+
+    #[test]
+    fn duplicate_frames_dropped() {
+        let mut tracker = Tracker::new();
+        tracker.set_dedup_window(Some(Duration::seconds(1)));
+        let now = Utc::now();
+        tracker.update_with_avr(IDENTIFICATION, now).unwrap();
+        let later = now + Duration::milliseconds(500);
+        tracker.update_with_avr(IDENTIFICATION, later).unwrap();
+        tracker.update_with_avr(VELOCITY, later).unwrap();
+        assert_eq!(tracker.get_num_messages(), 2);
+        assert_eq!(tracker.get_num_duplicate_messages(), 1);
+        // Outside the window the frame is processed again, confirming the callsign
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        assert_eq!(tracker.get_aircraft(&icao_address).unwrap().callsign, None);
+        tracker
+            .update_with_avr(IDENTIFICATION, now + Duration::seconds(2))
+            .unwrap();
+        assert_eq!(tracker.get_num_messages(), 3);
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.callsign.as_deref(), Some("KLM1023"));
+    }
+
+    #[test]
+    fn events_reported_to_callback() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut tracker = Tracker::new();
+        let sink = events.clone();
+        tracker.set_on_event(Box::new(move |event| sink.lock().unwrap().push(event)));
+        update(&mut tracker, &[IDENTIFICATION; 2]);
+        update(&mut tracker, &[POSITION_EVEN, POSITION_ODD]);
+        tracker.remove_stale(Duration::minutes(1), Utc::now() + Duration::minutes(10));
+        let events = events.lock().unwrap();
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                TrackerEvent::NewAircraft { icao_address, .. } => format!("new {}", icao_address),
+                TrackerEvent::PositionUpdate(record) => format!("position {}", record.icao_address),
+                TrackerEvent::CallsignUpdate { callsign, .. } => format!("callsign {}", callsign),
+                TrackerEvent::Expired { icao_address, .. } => format!("expired {}", icao_address),
+            })
+            .collect();
+        let expected = [
+            "new 4840D6",
+            "callsign KLM1023",
+            "new 40621D",
+            "position 40621D",
+        ];
+        assert_eq!(kinds[..4], expected);
+        // Expired in no particular order
+        assert_eq!(kinds.len(), 6);
+        assert!(kinds[4..].iter().all(|kind| kind.starts_with("expired")));
+    }
+
+    #[test]
+    fn flight_summary_recorded_once_on_removal() {
+        let mut tracker = Tracker::new();
+        tracker.enable_flight_summaries();
+        // The aircraft sending only a velocity never gets a position or callsign
+        update(&mut tracker, &[IDENTIFICATION; 2]);
+        update(&mut tracker, &[POSITION_EVEN, POSITION_ODD, VELOCITY]);
+        let later = Utc::now() + Duration::minutes(10);
+        assert_eq!(tracker.remove_stale(Duration::minutes(1), later), 3);
+        assert!(tracker.get_all_aircraft().is_empty());
+        let mut summaries = tracker.take_flight_summaries();
+        assert_eq!(summaries.len(), 3);
+        summaries.sort_by_key(|s| icao_to_u32(&s.icao_address));
+        assert_eq!(summaries[0].max_altitude, Some(38000));
+        assert_eq!(summaries[0].track.len(), 1);
+        assert_eq!(summaries[1].callsigns, vec!["KLM1023".to_string()]);
+        assert_eq!(summaries[2].max_ground_speed.map(f64::round), Some(159.0));
+        assert!(summaries[2].track.is_empty() && summaries[2].callsigns.is_empty());
+        assert_eq!(tracker.remove_stale(Duration::minutes(1), later), 0);
+        assert!(tracker.take_flight_summaries().is_empty());
+        // Aircraft removed are still counted as seen
+        assert_eq!(tracker.get_num_aircraft_seen(), 3);
+    }
+
+    #[test]
+    fn tracked_aircraft_counted() {
+        let mut tracker = Tracker::new();
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.len(), 0);
+        assert!(!tracker.contains(&icao_address));
+        update(&mut tracker, &[IDENTIFICATION, IDENTIFICATION, VELOCITY]);
+        assert!(!tracker.is_empty());
+        assert_eq!(tracker.len(), 2);
+        assert!(tracker.contains(&icao_address));
+        assert!(!tracker.contains(&icao_from_u32(0xA1B2C3).unwrap()));
+    }
+
+    #[test]
+    fn stale_aircraft_start_afresh() {
+        let mut tracker = Tracker::new();
+        let start = Utc::now() - Duration::minutes(10);
+        tracker.update_with_avr(POSITION_EVEN, start).unwrap();
+        tracker.update_with_avr(VELOCITY, Utc::now()).unwrap();
+        assert_eq!(tracker.remove_stale(Duration::minutes(1), Utc::now()), 1);
+        assert_eq!(tracker.get_all_aircraft().len(), 1);
+        tracker.update_with_avr(POSITION_ODD, Utc::now()).unwrap();
+        let statistics = tracker.get_position_statistics();
+        assert_eq!((statistics.decoded, statistics.waiting), (0, 2));
+        let aircraft = tracker.get_aircraft(&icao_from_u32(0x40621D).unwrap());
+        assert!(aircraft.unwrap().first_seen > start + Duration::minutes(1));
+    }
+
+    #[test]
+    fn time_in_view_restarts_after_stale() {
+        let mut tracker = Tracker::new();
+        let start = Utc.timestamp_millis(1_604_340_192_345);
+        let icao_address = icao_from_u32(0x40621D).unwrap();
+        let in_view = |tracker: &Tracker| {
+            let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+            aircraft.time_in_view()
+        };
+        tracker.update_with_avr(POSITION_EVEN, start).unwrap();
+        assert_eq!(in_view(&tracker), Duration::zero());
+        let later = start + Duration::seconds(30);
+        tracker.update_with_avr(POSITION_ODD, later).unwrap();
+        assert_eq!(in_view(&tracker), Duration::seconds(30));
+        let reappeared = later + Duration::minutes(10);
+        tracker.remove_stale(Duration::minutes(1), reappeared);
+        tracker.update_with_avr(POSITION_EVEN, reappeared).unwrap();
+        assert_eq!(in_view(&tracker), Duration::zero());
+    }
+
+    /// Feed an aircraft headings and positions 10 seconds apart, as (heading, north, east) with
+    /// the offsets from 52N 4E in nautical miles, and return whether it's then orbiting
+    fn flies_orbit(path: &[(f64, f64, f64)]) -> bool {
+        let start = Utc.timestamp_millis(1_604_340_192_345);
+        let mut aircraft = Aircraft::new(icao_from_u32(0x4840D6).unwrap(), start);
+        for (i, &(heading, north, east)) in path.iter().enumerate() {
+            aircraft.latitude = Some(52.0 + north / 60.0);
+            aircraft.longitude = Some(4.0 + east / 60.0 / 52f64.to_radians().cos());
+            aircraft.update_heading(heading, start + Duration::seconds(i as i64 * 10));
+        }
+        aircraft.is_orbiting
+    }
+
+    /// Points turning right by 15 degrees at a time, `radius` nautical miles from the center
+    /// (moved `drift` nautical miles north each time), for a little over a full circle
+    fn circle(radius: impl Fn(usize) -> f64, drift: f64) -> Vec<(f64, f64, f64)> {
+        (0..=25)
+            .map(|i| {
+                let angle = (i as f64 * 15.0).to_radians();
+                let (north, east) = (radius(i) * angle.cos(), radius(i) * angle.sin());
+                (i as f64 * 15.0 + 90.0, north + drift * i as f64, east)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn orbit_detected() {
+        assert!(flies_orbit(&circle(|_| 2.0, 0.0)));
+        // Short of a full circle it's still just a turn
+        assert!(!flies_orbit(&circle(|_| 2.0, 0.0)[..24]));
+    }
+
+    #[test]
+    fn turn_onto_new_heading_not_orbit() {
+        let path: Vec<_> = (0..=9)
+            .map(|i| (i as f64 * 10.0, i as f64 * 0.5, i as f64 * 0.2))
+            .collect();
+        assert!(!flies_orbit(&path));
+    }
+
+    #[test]
+    fn circle_flown_while_travelling_not_orbit() {
+        assert!(!flies_orbit(&circle(|_| 2.0, 1.0)));
+    }
+
+    #[test]
+    fn orbit_needs_roughly_constant_radius() {
+        // Swinging between 0.5 and 4.5 nm from the center, within the area but not circling
+        let erratic = |i: usize| [0.5, 4.5][i % 2];
+        assert!(!flies_orbit(&circle(erratic, 0.0)));
+    }
+
+    #[test]
+    fn aircraft_message_rate_over_recent_window() {
+        let mut tracker = Tracker::new();
+        let start = Utc.timestamp_millis(1_604_340_192_000);
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let rate = |tracker: &Tracker| tracker.get_aircraft(&icao_address)?.messages_per_second();
+        tracker.update_with_avr(IDENTIFICATION, start).unwrap();
+        assert_eq!(rate(&tracker), None);
+        // Over less than a second, the rate is per second
+        let time = start + Duration::milliseconds(500);
+        tracker.update_with_avr(IDENTIFICATION, time).unwrap();
+        assert_eq!(rate(&tracker), Some(2.0));
+        for second in 1..=30 {
+            let time = start + Duration::seconds(second);
+            tracker.update_with_avr(IDENTIFICATION, time).unwrap();
+        }
+        assert_eq!(rate(&tracker), Some(1.0));
+        // Only the last window counts, not the whole time in view
+        let time = start + Duration::seconds(100);
+        tracker.update_with_avr(IDENTIFICATION, time).unwrap();
+        assert_eq!(rate(&tracker), Some(0.1));
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.num_messages, 33);
+    }
+
+    #[test]
+    fn replayed_aircraft_kept_until_stale_by_message_time() {
+        let mut tracker = Tracker::new();
+        let start = Utc.timestamp_millis(1_604_340_192_345);
+        tracker.update_with_avr(POSITION_EVEN, start).unwrap();
+        tracker
+            .update_with_avr(VELOCITY, start + Duration::minutes(5))
+            .unwrap();
+        // Against the time of the last message replayed, only the first aircraft is stale, as
+        // on each refresh of the display
+        let expire = Duration::minutes(1);
+        for _ in 0..2 {
+            let now = tracker.get_current_time();
+            tracker.remove_stale(expire, now);
+            let current = tracker.get_current_aircraft(&expire, now);
+            assert_eq!(current.len(), 1);
+            assert_eq!(current[0].icao_address, icao_from_u32(0x485020).unwrap());
+        }
+        assert_eq!(tracker.get_all_aircraft().len(), 1);
+    }
+
+    #[test]
+    fn beast_frames_split_across_updates() {
+        let mut tracker = Tracker::new();
+        let mut stream = Vec::new();
+        for _ in 0..2 {
+            stream.extend_from_slice(&[0x1a, 0x33, 0, 0, 0, 0, 0, 1, 0x80]);
+            stream.extend_from_slice(&[
+                0x8D, 0x48, 0x40, 0xD6, 0x20, 0x2C, 0xC3, 0x71, 0xC3, 0x2C, 0xE0, 0x57, 0x60, 0x98,
+            ]);
+        }
+        let (first, second) = stream.split_at(30);
+        tracker.update_with_beast(first, Utc::now());
+        assert_eq!(tracker.get_num_messages(), 1);
+        tracker.update_with_beast(second, Utc::now());
+        assert_eq!(tracker.get_num_messages(), 2);
+        let callsign = tracker.get_all_aircraft()[0].callsign.clone();
+        assert_eq!(callsign.as_deref(), Some("KLM1023"));
+    }
+
+    #[test]
+    fn aircraft_json_polls_update_aircraft() {
+        let mut tracker = Tracker::new();
+        let start = Utc.timestamp_millis(1_604_340_192_000);
+        let poll = |lat: f64, seen_pos: f64| {
+            format!(
+                r#"{{"now":0,"aircraft":[{{"hex":"4840d6","flight":"KLM1023 ","alt_baro":38000,
+                "gs":420,"track":92,"squawk":"1000","lat":{},"lon":3.9,"seen_pos":{},"seen":0.5}},
+                {{"hex":"a1b2c3","alt_baro":"ground","seen":2}}]}}"#,
+                lat, seen_pos
+            )
+        };
+        let polls = [(52.25, 0.5), (52.25, 1.5), (52.26, 0.5)];
+        for (i, (lat, seen_pos)) in polls.iter().enumerate() {
+            let time = start + Duration::seconds(i as i64);
+            let updated = tracker.update_with_aircraft_json(&poll(*lat, *seen_pos), time);
+            assert_eq!(updated, Some(2));
+        }
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        // Polls aren't messages
+        assert_eq!(tracker.get_num_messages(), 0);
+        assert_eq!(tracker.get_messages_per_second_recent(), None);
+        let klm = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(klm.num_messages, 0);
+        assert_eq!(klm.callsign.as_deref(), Some("KLM1023"));
+        assert_eq!(klm.altitude, Some(38000));
+        assert_eq!(klm.ground_speed, Some(420.0));
+        assert_eq!(klm.heading, Some(92.0));
+        assert_eq!(klm.squawk, Some(Squawk(0o1000)));
+        assert_eq!(klm.on_ground, Some(false));
+        assert_eq!(klm.last_seen, start + Duration::milliseconds(1500));
+        // The second poll repeats the first position
+        let track: Vec<_> = klm.track().iter().map(|p| p.latitude).collect();
+        assert_eq!(track, vec![52.25, 52.26]);
+        let taxiing = tracker.get_aircraft(&icao_from_u32(0xA1B2C3).unwrap());
+        assert_eq!(taxiing.unwrap().on_ground, Some(true));
+        // Aircraft not heard from since the last poll aren't updated, and age out
+        let later = start + Duration::seconds(60);
+        let document = r#"{"aircraft":[{"hex":"4840d6","seen":1},{"hex":"a1b2c3","seen":62}]}"#;
+        assert_eq!(tracker.update_with_aircraft_json(document, later), Some(1));
+        assert_eq!(tracker.remove_stale(Duration::seconds(30), later), 1);
+        assert_eq!(tracker.update_with_aircraft_json("Not found", later), None);
+    }
+
+    #[test]
+    fn frames_logged() {
+        let mut tracker = Tracker::new();
+        let time = Utc::now();
+        tracker.update_with_avr(IDENTIFICATION, time).unwrap();
+        assert!(tracker.take_frames().is_empty());
+        tracker.enable_frame_log();
+        for frame in &[IDENTIFICATION, "*8D4840D6;", "garbage"] {
+            let _ = tracker.update_with_avr(frame, time);
+        }
+        let frames = tracker.take_frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].time, time);
+        assert_eq!(frames[0].data, decode_avr(IDENTIFICATION).unwrap().0);
+        assert_eq!(frames[1].data, vec![0x8D, 0x48, 0x40, 0xD6]);
+        assert!(tracker.take_frames().is_empty());
+    }
+
+    #[test]
+    fn positions_logged_as_csv() {
+        let mut tracker = Tracker::new();
+        tracker.enable_position_log(Some(Duration::seconds(10)));
+        let start = Utc.timestamp_millis(1_604_340_192_345);
+        let lines = [
+            "MSG,3,1,1,4840D6,1,,,,,,38000,,,52.25720,3.91937,,,0,0,0,0",
+            "MSG,3,1,1,4840D6,1,,,,,,38000,,,52.25800,3.91900,,,0,0,0,0",
+            "MSG,3,1,1,485020,1,,,,,,,,,52.1,4.2,,,0,0,0,0",
+            "MSG,3,1,1,4840D6,1,,,,,,37975,,,52.26100,3.91800,,,0,0,0,0",
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            tracker.update_with_sbs(line, start + Duration::seconds(i as i64 * 5));
+        }
+        // The second position of 4840D6 is within 10 seconds of the first
+        let csv: Vec<_> = tracker
+            .take_positions()
+            .iter()
+            .map(|p| p.to_csv())
+            .collect();
+        assert_eq!(
+            csv,
+            vec![
+                "2020-11-02T18:03:12.345Z,4840D6,52.25720,3.91937,38000",
+                "2020-11-02T18:03:22.345Z,485020,52.10000,4.20000,",
+                "2020-11-02T18:03:27.345Z,4840D6,52.26100,3.91800,37975",
+            ]
+        );
+        assert!(tracker.take_positions().is_empty());
+    }
+
+    #[test]
+    fn sbs_messages_update_aircraft() {
+        let mut tracker = Tracker::new();
+        let lines = [
+            "MSG,1,1,1,4840D6,1,,,,,KLM1023 ,,,,,,,,0,0,0,0",
+            "MSG,1,1,1,4840D6,1,,,,,KLM1023 ,,,,,,,,0,0,0,0",
+            "MSG,3,1,1,4840D6,1,,,,,,38000,,,52.25720,3.91937,,,0,0,0,0",
+            "MSG,4,1,1,4840D6,1,,,,,,,420,182.9,,,-832,,0,0,0,0",
+            "MSG,6,1,1,4840D6,1,,,,,,,,,,,,1000,0,0,0,0",
+            "STA,,1,1,4840D6,1,,,,,RM",
+            "MSG,3,1,1,4840D6",
+        ];
+        for line in &lines {
+            tracker.update_with_sbs(line, Utc::now());
+        }
+        assert_eq!(tracker.get_num_messages(), 5);
+        assert_eq!(tracker.get_num_positions(), 1);
+        let aircraft = tracker.get_all_aircraft();
+        assert_eq!(aircraft.len(), 1);
+        let aircraft = aircraft[0];
+        assert_eq!(aircraft.callsign.as_deref(), Some("KLM1023"));
+        assert_eq!(aircraft.altitude, Some(38000));
+        assert_eq!(aircraft.latitude, Some(52.2572));
+        assert_eq!(aircraft.longitude, Some(3.91937));
+        assert_eq!(aircraft.track().len(), 1);
+        assert_eq!(aircraft.ground_speed, Some(420.0));
+        assert_eq!(aircraft.heading, Some(182.9));
+        assert_eq!(aircraft.vertical_rate, Some(-832));
+        assert_eq!(aircraft.squawk, Some(Squawk(0o1000)));
+    }
+
+    #[test]
+    fn squawk_from_identity_reply() {
+        let mut tracker = Tracker::new();
+        update(
+            &mut tracker,
+            &[
+                IDENTITY_REPLY,
+                IDENTIFICATION,
+                IDENTITY_REPLY,
+                IDENTITY_REPLY_UNKNOWN,
+            ],
+        );
+        let aircraft = tracker.get_all_aircraft();
+        assert_eq!(aircraft.len(), 1);
+        assert_eq!(aircraft[0].squawk, Some(Squawk(0o7000)));
+        assert_eq!(tracker.get_known_message_statistics()[&5], 1);
+        assert_eq!(tracker.get_unknown_message_statistics()[&5], 2);
+
+        let interval = Duration::seconds(60);
+        let now = tracker.get_current_time();
+        let summary = tracker.get_squawk_summary(&interval, SquawkRegion::Europe, now);
+        assert_eq!((summary.vfr, summary.discrete, summary.unknown), (1, 0, 0));
+        let summary = tracker.get_squawk_summary(&interval, SquawkRegion::Us, now);
+        assert_eq!((summary.vfr, summary.discrete, summary.unknown), (0, 1, 0));
+    }
+
+    #[test]
+    fn airspeeds_from_comm_b_replies() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[TRACK_AND_TURN, IDENTIFICATION]);
+        assert_eq!(tracker.get_unknown_message_statistics()[&20], 1);
+        update(&mut tracker, &[TRACK_AND_TURN, HEADING_AND_SPEED]);
+        assert_eq!(tracker.get_known_message_statistics()[&20], 2);
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.num_messages, 3);
+        assert!((aircraft.roll_angle.unwrap() - 2.1).abs() < 0.01);
+        assert_eq!(aircraft.track_angle_rate, Some(0.125));
+        assert_eq!(aircraft.true_airspeed, Some(424.0));
+        assert_eq!(aircraft.indicated_airspeed, Some(252.0));
+        let mach = aircraft.mach().unwrap();
+        assert!((mach.number - 0.42).abs() < 0.001);
+        assert!(!mach.approximate);
+        // The altitude and identity fields of a Comm-B reply aren't taken as a squawk
+        assert_eq!(aircraft.squawk, None);
+    }
+
+    #[test]
+    fn selected_altitude_from_comm_b_reply() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[IDENTIFICATION, SELECTED_VERTICAL_INTENTION]);
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.selected_altitude, Some(3008));
+        assert!((aircraft.pressure_setting.unwrap() - 1020.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn weather_from_comm_b_replies() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[IDENTIFICATION, METEOROLOGICAL_ROUTINE]);
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.wind_speed, Some(22.0));
+        assert!((aircraft.wind_direction.unwrap() - 344.5).abs() < 0.1);
+        assert_eq!(aircraft.static_air_temperature, Some(-48.75));
+        update(&mut tracker, &[METEOROLOGICAL_HAZARD]);
+        let aircraft = tracker.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.static_air_temperature, Some(-30.0));
+        assert_eq!(aircraft.wind_speed, Some(22.0));
+    }
+
+    #[test]
+    fn emergency_follows_squawk() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[IDENTIFICATION, IDENTITY_REPLY]);
+        assert!(tracker.get_emergency_aircraft().is_empty());
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let aircraft = tracker.map.get_mut(&icao_address).unwrap();
+        aircraft.squawk = Some(Squawk(0o7700));
+        assert_eq!(aircraft.emergency(), Some(Emergency::General));
+        assert_eq!(tracker.get_emergency_aircraft().len(), 1);
+        update(&mut tracker, &[IDENTITY_REPLY]);
+        assert!(tracker.get_emergency_aircraft().is_empty());
+        assert_eq!(Squawk(0o7500).emergency(), Some(Emergency::Hijack));
+        assert_eq!(Squawk(0o7600).emergency(), Some(Emergency::RadioFailure));
+    }
+
+    #[test]
+    fn surface_position_needs_receiver_position() {
+        const SURFACE_POSITION: &str = "*8C4841753AAB238733C8CD4020B1;";
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[SURFACE_POSITION]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert_eq!(aircraft.on_ground, Some(true));
+        assert_eq!(aircraft.latitude, None);
+        tracker.set_receiver_position(51.990, 4.375);
+        update(&mut tracker, &[SURFACE_POSITION]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert!((aircraft.latitude.unwrap() - 52.32304).abs() < 1e-5);
+        assert_eq!(aircraft.track().len(), 1);
+        assert_eq!(tracker.get_num_positions(), 1);
+
+        update(&mut tracker, &[POSITION_EVEN]);
+        let airborne = tracker.get_aircraft(&icao_from_u32(0x40621D).unwrap());
+        assert_eq!(airborne.unwrap().on_ground, Some(false));
+    }
+
+    #[test]
+    fn negative_altitude_position() {
+        // The position frames above with the altitude changed to -250 ft
+        const NEGATIVE_EVEN: &str = "*8D40621D5803E2D690C8ACAD5723;";
+        const NEGATIVE_ODD: &str = "*8D40621D5803E6435CC412EC1E52;";
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[NEGATIVE_EVEN, NEGATIVE_ODD]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert_eq!(aircraft.altitude, Some(-250));
+        assert_eq!(aircraft.position_altitude, Some(-250));
+        assert!((aircraft.latitude.unwrap() - 52.26578).abs() < 1e-5);
+        assert_eq!(tracker.get_num_positions(), 1);
+    }
+
+    #[test]
+    fn geometric_altitude_kept_apart() {
+        // The position frames above with GNSS height of 1000 m instead of barometric altitude
+        const GNSS_EVEN: &str = "*8D40621DA03E82D690C8ACFB5B43;";
+        const GNSS_ODD: &str = "*8D40621DA03E86435CC412BA1232;";
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[GNSS_EVEN, GNSS_ODD]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert_eq!(aircraft.geometric_altitude, Some(3281));
+        assert_eq!(aircraft.altitude, None);
+        assert_eq!(tracker.get_num_positions(), 1);
+
+        update(&mut tracker, &[POSITION_EVEN]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert_eq!(aircraft.altitude, Some(38000));
+        assert_eq!(aircraft.geometric_altitude, Some(3281));
+    }
+
+    #[test]
+    fn cpr_frames_paired_within_window() {
+        let mut tracker = Tracker::new();
+        let start = Utc::now();
+        let later = start + Duration::seconds(40);
+        tracker.update_with_avr(POSITION_EVEN, start).unwrap();
+        tracker.update_with_avr(POSITION_ODD, later).unwrap();
+        assert_eq!(tracker.get_num_positions(), 0);
+        assert_eq!(tracker.get_all_aircraft()[0].latitude, None);
+
+        let mut tracker = Tracker::new();
+        tracker.set_cpr_window(Duration::minutes(1));
+        tracker.update_with_avr(POSITION_EVEN, start).unwrap();
+        tracker.update_with_avr(POSITION_ODD, later).unwrap();
+        assert_eq!(tracker.get_num_positions(), 1);
+    }
+
+    #[test]
+    fn lone_frame_resolved_against_receiver() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[POSITION_EVEN]);
+        assert_eq!(tracker.get_all_aircraft()[0].latitude, None);
+
+        // Nor with the default range, which aircraft could be too far out to decode locally in
+        let mut tracker = Tracker::new();
+        tracker.set_receiver_position(52.0, 4.4);
+        update(&mut tracker, &[POSITION_EVEN]);
+        assert_eq!(tracker.get_all_aircraft()[0].latitude, None);
+
+        let mut tracker = Tracker::new();
+        tracker.set_receiver_position(52.0, 4.4);
+        tracker.set_max_range(180.0);
+        update(&mut tracker, &[POSITION_EVEN]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert!((aircraft.latitude.unwrap() - 52.25720).abs() < 1e-5);
+        // Once there's a pair, it's decoded globally
+        update(&mut tracker, &[POSITION_ODD]);
+        let statistics = tracker.get_position_statistics();
+        assert_eq!((statistics.decoded, statistics.local), (2, 1));
+    }
+
+    #[test]
+    fn distant_aircraft_not_decoded_locally() {
+        // The aircraft is about 250 nm north of the receiver, too far for a local decode to be
+        // unambiguous, so a lone frame mustn't give a wrong position which a later pair would
+        // then be checked against
+        let mut tracker = Tracker::new();
+        tracker.set_receiver_position(48.1, 3.9);
+        update(&mut tracker, &[POSITION_EVEN]);
+        assert_eq!(tracker.get_all_aircraft()[0].latitude, None);
+        update(&mut tracker, &[POSITION_ODD]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert!((aircraft.latitude.unwrap() - 52.26578).abs() < 1e-5);
+        let statistics = tracker.get_position_statistics();
+        assert_eq!((statistics.local, statistics.rejected), (0, 0));
+    }
+
+    #[test]
+    fn implausible_positions_rejected() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[POSITION_EVEN, POSITION_ODD]);
+        tracker.set_receiver_position(40.0, -74.0);
+        update(&mut tracker, &[POSITION_EVEN]);
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert!((aircraft.latitude.unwrap() - 52.26578).abs() < 1e-5);
+        assert_eq!(tracker.get_position_statistics().rejected, 1);
+
+        // Jumping from one position to another 100 nautical miles away within a minute
+        let mut tracker = Tracker::new();
+        let start = Utc::now();
+        tracker.update_with_avr(POSITION_EVEN, start).unwrap();
+        tracker.update_with_avr(POSITION_ODD, start).unwrap();
+        let aircraft = tracker.map.values_mut().next().unwrap();
+        aircraft.track[0].latitude -= 100.0 / 60.0;
+        tracker
+            .update_with_avr(POSITION_EVEN, start + Duration::minutes(1))
+            .unwrap();
+        let aircraft = tracker.get_all_aircraft()[0];
+        assert_eq!(aircraft.track().len(), 1);
+        assert_eq!(tracker.get_position_statistics().rejected, 1);
+    }
+
+    #[test]
+    fn max_range_needs_receiver_position() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[POSITION_EVEN, POSITION_ODD]);
+        assert_eq!(tracker.get_max_range(), None);
+
+        let mut tracker = Tracker::new();
+        tracker.set_receiver_position(52.0, 4.4);
+        let time = Utc::now();
+        tracker.update_with_avr(POSITION_EVEN, time).unwrap();
+        tracker.update_with_avr(POSITION_ODD, time).unwrap();
+        let (max_range, icao_address, when) = tracker.get_max_range().unwrap();
+        let aircraft = tracker.get_all_aircraft()[0];
+        let receiver = tracker.receiver_position().unwrap();
+        assert!(max_range >= aircraft.distance_from(receiver).unwrap());
+        assert!((max_range - 23.3).abs() < 0.1);
+        assert_eq!(icao_address, aircraft.icao_address);
+        assert_eq!(when, time);
+        // The record is only replaced by a position farther away
+        let later = time + Duration::seconds(5);
+        tracker.update_with_avr(POSITION_ODD, later).unwrap();
+        assert_eq!(tracker.get_max_range().unwrap().2, time);
+    }
+
+    #[test]
+    fn registration_looked_up_on_first_sight() {
+        let mut tracker = Tracker::new();
+        let registry = CsvRegistry::parse("icao24,registration,typecode\n4840D6,PH-BXA,B738");
+        tracker.set_registry(Box::new(registry));
+        update(&mut tracker, &[IDENTIFICATION, VELOCITY]);
+        let aircraft = tracker
+            .get_aircraft(&icao_from_u32(0x4840D6).unwrap())
+            .unwrap();
+        assert_eq!(aircraft.registration.as_deref(), Some("PH-BXA"));
+        assert_eq!(aircraft.aircraft_type.as_deref(), Some("B738"));
+        assert_eq!(aircraft.country, Some("Netherlands"));
+        let unknown = tracker
+            .get_aircraft(&icao_from_u32(0x485020).unwrap())
+            .unwrap();
+        assert_eq!(unknown.registration, None);
+    }
+
+    #[test]
+    fn heading_reference() {
+        let mut tracker = Tracker::new();
+        update(&mut tracker, &[VELOCITY, AIRSPEED_VELOCITY]);
+        let mut aircraft = tracker.get_all_aircraft();
+        aircraft.sort_by_key(|a| a.icao_address.to_string());
+        assert_eq!(aircraft[0].heading_reference, Some(HeadingReference::True));
+        assert_eq!(
+            aircraft[1].heading_reference,
+            Some(HeadingReference::Magnetic)
+        );
+        assert_eq!(tracker.get_known_message_statistics()[&17], 2);
+    }
+
+    #[test]
+    fn callsign_conflicts_require_overlap() {
+        let mut tracker = Tracker::new();
+        let start = Utc::now() - Duration::seconds(30);
+        let mut add = |icao: u32, callsign: &str, first: i64, last: i64| {
+            let icao_address = icao_from_u32(icao).unwrap();
+            let mut aircraft = Aircraft::new(icao_address, start + Duration::seconds(first));
+            aircraft.callsign = Some(callsign.to_string());
+            aircraft.last_seen = start + Duration::seconds(last);
+            tracker.map.insert(icao_address, aircraft);
+        };
+        add(0xA00003, "UAL1", 0, 20);
+        add(0xA00001, "UAL1", 10, 30);
+        // Takes over DAL2 after the first aircraft using it stopped transmitting
+        add(0xA00002, "DAL2", 0, 10);
+        add(0xA00004, "DAL2", 15, 30);
+        add(0xA00005, "SWA3", 0, 30);
+        let now = start + Duration::seconds(30);
+        let conflicts = tracker.get_callsign_conflicts(&Duration::seconds(60), now);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].callsign, "UAL1");
+        let addresses: Vec<_> = conflicts[0]
+            .icao_addresses
+            .iter()
+            .map(icao_to_u32)
+            .collect();
+        assert_eq!(addresses, vec![0xA00001, 0xA00003]);
+        let found: Vec<_> = tracker
+            .find_by_callsign(" ual1 ")
+            .iter()
+            .map(|a| icao_to_u32(&a.icao_address))
+            .collect();
+        assert_eq!(found, vec![0xA00001, 0xA00003]);
+        assert!(tracker.find_by_callsign("UAL").is_empty());
+    }
+
+    #[test]
+    fn proximity_events_reported_once_per_pair() {
+        let mut tracker = Tracker::new();
+        let now = Utc::now();
+        let mut add = |icao: u32, latitude: f64, altitude: i32, age: i64| {
+            let icao_address = icao_from_u32(icao).unwrap();
+            let time = now - Duration::seconds(age);
+            let mut aircraft = Aircraft::new(icao_address, time);
+            aircraft.latitude = Some(latitude);
+            aircraft.longitude = Some(4.0);
+            aircraft.altitude = Some(altitude);
+            aircraft.push_track_point(time, DEFAULT_MAX_TRACK_POINTS, None, None);
+            tracker.map.insert(icao_address, aircraft);
+        };
+        add(0xA00002, 52.0, 10000, 0);
+        // 3 nm north and 500 ft above
+        add(0xA00001, 52.05, 10500, 0);
+        // Close horizontally but well below
+        add(0xA00003, 52.02, 5000, 0);
+        // Close, but last positioned too long ago
+        add(0xA00004, 52.01, 10000, 120);
+        let events = tracker.proximity_events(5.0, 1000, &Duration::seconds(60), now);
+        assert_eq!(events.len(), 1);
+        let (a, b, distance) = events[0];
+        assert_eq!((icao_to_u32(&a), icao_to_u32(&b)), (0xA00001, 0xA00002));
+        assert!((distance - 3.0).abs() < 0.01);
+        let events = tracker.proximity_events(2.0, 1000, &Duration::seconds(60), now);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn aircraft_in_bbox_across_antimeridian() {
+        let mut tracker = Tracker::new();
+        let positions = [
+            (0xA00001, Some((52.3, 4.8))),
+            (0xA00002, Some((-17.8, 179.5))),
+            (0xA00003, Some((-18.1, -178.9))),
+            (0xA00004, Some((10.0, 0.0))),
+            (0xA00005, None),
+        ];
+        for (icao, position) in &positions {
+            let icao_address = icao_from_u32(*icao).unwrap();
+            let mut aircraft = Aircraft::new(icao_address, Utc::now());
+            aircraft.latitude = position.map(|p| p.0);
+            aircraft.longitude = position.map(|p| p.1);
+            tracker.map.insert(icao_address, aircraft);
+        }
+        let found = |bbox: (f64, f64, f64, f64)| -> Vec<u32> {
+            tracker
+                .aircraft_in_bbox(bbox.0, bbox.1, bbox.2, bbox.3)
+                .iter()
+                .map(|a| icao_to_u32(&a.icao_address))
+                .collect()
+        };
+        assert_eq!(found((50.0, 3.0, 54.0, 7.0)), vec![0xA00001]);
+        // Crossing the antimeridian
+        let pacific = found((-20.0, 179.0, -15.0, -178.0));
+        assert_eq!(pacific, vec![0xA00002, 0xA00003]);
+        assert_eq!(found((-90.0, -180.0, 90.0, 180.0)).len(), 4);
+        assert!(found((20.0, 0.0, 30.0, 10.0)).is_empty());
+    }
+
+    #[test]
+    fn distance_and_bearing_from_receiver() {
+        let mut tracker = Tracker::new();
+        tracker.set_receiver_position(52.0, 4.0);
+        let receiver = tracker.receiver_position().unwrap();
+        let mut aircraft = test_aircraft(Utc::now());
+        assert_eq!(aircraft.distance_from(receiver), None);
+        assert_eq!(aircraft.bearing_from(receiver), None);
+        // One degree of latitude due north is 60 nautical miles
+        aircraft.latitude = Some(53.0);
+        aircraft.longitude = Some(4.0);
+        assert!((aircraft.distance_from(receiver).unwrap() - 60.0).abs() < 0.1);
+        assert!(aircraft.bearing_from(receiver).unwrap().abs() < 1e-9);
+        aircraft.latitude = Some(52.0);
+        aircraft.longitude = Some(3.0);
+        assert!((aircraft.bearing_from(receiver).unwrap() - 270.4).abs() < 0.1);
+    }
+
+    #[test]
+    fn ground_speed_checked_against_positions() {
+        let time = Utc::now();
+        let mut aircraft = test_aircraft(time);
+        aircraft.ground_speed = Some(420.0);
+        let mut add_point = |seconds: i64, longitude: f64| {
+            aircraft.latitude = Some(52.0);
+            aircraft.longitude = Some(longitude);
+            aircraft.push_track_point(time + Duration::seconds(seconds), 10, None, None);
+            let speed = aircraft.computed_ground_speed();
+            (speed, aircraft.position_quality())
+        };
+        assert_eq!(add_point(0, 4.0), (None, None));
+        // 1.2 nm east in 10 s is 432 kt
+        let (speed, quality) = add_point(10, 4.0325);
+        assert!((speed.unwrap() - 432.0).abs() < 1.0);
+        assert_eq!(quality, Some(PositionQuality::Consistent));
+        // A bad decode 30 nm away
+        let (speed, quality) = add_point(20, 4.845);
+        assert!(speed.unwrap() > 10_000.0);
+        assert_eq!(quality, Some(PositionQuality::Inconsistent));
+        // Too long after the last position to compare
+        assert_eq!(add_point(200, 4.9).1, None);
+    }
+
+    #[test]
+    fn mach_prefers_true_airspeed() {
+        let mut aircraft = test_aircraft(Utc::now());
+        aircraft.ground_speed = Some(500.0);
+        assert_eq!(aircraft.mach(), None);
+        aircraft.altitude = Some(38000);
+        let mach = aircraft.mach().unwrap();
+        assert!((mach.number - 0.872).abs() < 0.001);
+        assert!(mach.approximate);
+        aircraft.true_airspeed = Some(460.0);
+        let mach = aircraft.mach().unwrap();
+        assert!((mach.number - 0.802).abs() < 0.001);
+        assert!(!mach.approximate);
+        assert!((speed_of_sound_kt(0.0) - 661.47).abs() < 0.01);
     }
 }
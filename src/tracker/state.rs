@@ -0,0 +1,398 @@
+use super::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// First line of a state file, giving the version of its format
+const STATE_HEADER: &str = "flight-tracker state 2";
+
+impl Tracker {
+    /// Save the tracked aircraft and the message and position counts to a file, to be restored
+    /// with `Tracker::load`. The file is written under a temporary name and renamed, so an
+    /// interrupted save leaves the previous one in place. See `Tracker::state_contents` for
+    /// what's saved.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.state_contents())?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Get the contents of a state file as written by `Tracker::save`, so they can be written
+    /// after letting go of a shared tracker. Each aircraft's identity, emitter category, latest
+    /// position, altitudes, speeds, heading, vertical rate, squawk and selected altitude, heading
+    /// and pressure setting are kept along with its track and callsigns. Comm-B readings
+    /// (indicated airspeed, Mach number, roll angle, track angle rate, wind and temperature) and
+    /// the heading history used to detect orbits aren't saved, nor are settings or anything
+    /// recorded for collection with the `take_` methods.
+    pub fn state_contents(&self) -> String {
+        let mut lines = vec![STATE_HEADER.to_string()];
+        lines.push(fields(&[
+            "messages",
+            &self.num_messages.to_string(),
+            &self.num_unknown_messages.to_string(),
+            &self.error_statistics.invalid_length.to_string(),
+            &self.num_duplicate_messages.to_string(),
+        ]));
+        let positions = &self.position_statistics;
+        lines.push(fields(&[
+            "positions",
+            &positions.decoded.to_string(),
+            &positions.local.to_string(),
+            &positions.failed.to_string(),
+            &positions.waiting.to_string(),
+            &positions.rejected.to_string(),
+        ]));
+        let errors = &self.error_statistics;
+        lines.push(fields(&[
+            "errors",
+            &errors.invalid_avr.to_string(),
+            &errors.crc.to_string(),
+            &errors.parser.to_string(),
+            &errors.unsupported.to_string(),
+        ]));
+        let mut formats: Vec<u8> = self
+            .known_message_counts
+            .keys()
+            .chain(self.unknown_message_counts.keys())
+            .copied()
+            .collect();
+        formats.sort_unstable();
+        formats.dedup();
+        for df in formats {
+            let count = |counts: &HashMap<u8, u64>| counts.get(&df).copied().unwrap_or(0);
+            lines.push(fields(&[
+                "df",
+                &df.to_string(),
+                &count(&self.known_message_counts).to_string(),
+                &count(&self.unknown_message_counts).to_string(),
+            ]));
+        }
+        let mut seen: Vec<String> = self.seen_aircraft.iter().map(|a| a.to_string()).collect();
+        seen.sort_unstable();
+        lines.push(format!("seen\t{}", seen.join(" ")));
+        let mut aircraft: Vec<&Aircraft> = self.map.values().collect();
+        aircraft.sort_by_key(|a| icao_to_u32(&a.icao_address));
+        for a in aircraft {
+            lines.push(fields(&[
+                "aircraft",
+                &a.icao_address.to_string(),
+                &time(a.first_seen),
+                &time(a.last_seen),
+                &a.num_messages.to_string(),
+                &opt(a.callsign.as_ref()),
+                &opt(a.registration.as_ref()),
+                &opt(a.aircraft_type.as_ref()),
+                &opt(a.altitude),
+                &opt(a.geometric_altitude),
+                &opt(a.heading),
+                &opt(a.ground_speed),
+                &opt(a.vertical_rate),
+                &opt(a.squawk),
+                &opt(a.latitude),
+                &opt(a.longitude),
+                &opt(a.position_altitude),
+                &opt(a.on_ground),
+                &opt(a.max_altitude),
+                &opt(a.max_ground_speed),
+                &opt(a.emitter_category),
+                match a.heading_reference {
+                    Some(HeadingReference::True) => "true",
+                    Some(HeadingReference::Magnetic) => "magnetic",
+                    None => "",
+                },
+                &opt(a.true_airspeed),
+                &opt(a.selected_altitude),
+                &opt(a.selected_heading),
+                &opt(a.pressure_setting),
+                match a.vertical_rate_source {
+                    Some(VerticalRateSource::BarometricPressureAltitude) => "barometric",
+                    Some(VerticalRateSource::GeometricAltitude) => "geometric",
+                    None => "",
+                },
+                &a.callsigns.join(" "),
+            ]));
+            for point in &a.track {
+                lines.push(fields(&[
+                    "point",
+                    &time(point.time),
+                    &point.latitude.to_string(),
+                    &point.longitude.to_string(),
+                    &opt(point.altitude),
+                ]));
+            }
+        }
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        contents
+    }
+
+    /// Load a tracker from a file written by `Tracker::save`. The aircraft carry on from where
+    /// they were saved, apart from CPR frames waiting to be paired, which are dropped since
+    /// they'd be stale by now. Settings are the defaults, as for `Tracker::new`.
+    pub fn load(path: &Path) -> io::Result<Tracker> {
+        Tracker::parse_state(&fs::read_to_string(path)?).map_err(|(line, error)| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: line {}: {}", path.display(), line, error),
+            )
+        })
+    }
+
+    /// Parse the contents of a state file, giving the line number and a description on error
+    fn parse_state(contents: &str) -> Result<Tracker, (usize, String)> {
+        let mut lines = contents.lines().enumerate().map(|(n, line)| (n + 1, line));
+        match lines.next() {
+            Some((_, STATE_HEADER)) => {}
+            _ => return Err((1, format!("expected '{}'", STATE_HEADER))),
+        }
+        let mut tracker = Tracker::new();
+        let mut current: Option<ICAOAddress> = None;
+        for (n, line) in lines.filter(|(_, line)| !line.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            tracker
+                .parse_state_line(&fields, &mut current)
+                .map_err(|error| (n, error))?;
+        }
+        Ok(tracker)
+    }
+
+    fn parse_state_line(
+        &mut self,
+        fields: &[&str],
+        current: &mut Option<ICAOAddress>,
+    ) -> Result<(), String> {
+        let expect = |len: usize| {
+            if fields.len() == len {
+                Ok(())
+            } else {
+                Err(format!("expected {} fields, got {}", len, fields.len()))
+            }
+        };
+        match fields[0] {
+            "messages" => {
+                expect(5)?;
+                self.num_messages = parse(fields[1])?;
+                self.num_unknown_messages = parse(fields[2])?;
+                self.error_statistics.invalid_length = parse(fields[3])?;
+                self.num_duplicate_messages = parse(fields[4])?;
+            }
+            "positions" => {
+                expect(6)?;
+                self.position_statistics = PositionStatistics {
+                    decoded: parse(fields[1])?,
+                    local: parse(fields[2])?,
+                    failed: parse(fields[3])?,
+                    waiting: parse(fields[4])?,
+                    rejected: parse(fields[5])?,
+                };
+            }
+            "errors" => {
+                expect(5)?;
+                self.error_statistics.invalid_avr = parse(fields[1])?;
+                self.error_statistics.crc = parse(fields[2])?;
+                self.error_statistics.parser = parse(fields[3])?;
+                self.error_statistics.unsupported = parse(fields[4])?;
+            }
+            "df" => {
+                expect(4)?;
+                let df = parse(fields[1])?;
+                let (known, unknown): (u64, u64) = (parse(fields[2])?, parse(fields[3])?);
+                if known > 0 {
+                    self.known_message_counts.insert(df, known);
+                }
+                if unknown > 0 {
+                    self.unknown_message_counts.insert(df, unknown);
+                }
+            }
+            "seen" => {
+                expect(2)?;
+                for icao in fields[1].split_whitespace() {
+                    self.seen_aircraft.insert(parse_icao(icao)?);
+                }
+            }
+            "aircraft" => {
+                expect(28)?;
+                let icao_address = parse_icao(fields[1])?;
+                let mut aircraft = Aircraft::new(icao_address, parse_time(fields[2])?);
+                aircraft.last_seen = parse_time(fields[3])?;
+                aircraft.num_messages = parse(fields[4])?;
+                aircraft.callsign = parse_opt(fields[5])?;
+                aircraft.registration = parse_opt(fields[6])?;
+                aircraft.aircraft_type = parse_opt(fields[7])?;
+                aircraft.altitude = parse_opt(fields[8])?;
+                aircraft.geometric_altitude = parse_opt(fields[9])?;
+                aircraft.heading = parse_opt(fields[10])?;
+                aircraft.ground_speed = parse_opt(fields[11])?;
+                aircraft.vertical_rate = parse_opt(fields[12])?;
+                aircraft.squawk = match fields[13] {
+                    "" => None,
+                    squawk => Some(Squawk(
+                        u16::from_str_radix(squawk, 8)
+                            .map_err(|_| format!("invalid squawk '{}'", squawk))?,
+                    )),
+                };
+                aircraft.latitude = parse_opt(fields[14])?;
+                aircraft.longitude = parse_opt(fields[15])?;
+                aircraft.position_altitude = parse_opt(fields[16])?;
+                aircraft.on_ground = parse_opt(fields[17])?;
+                aircraft.max_altitude = parse_opt(fields[18])?;
+                aircraft.max_ground_speed = parse_opt(fields[19])?;
+                aircraft.emitter_category = parse_emitter_category(fields[20])?;
+                aircraft.heading_reference = match fields[21] {
+                    "" => None,
+                    "true" => Some(HeadingReference::True),
+                    "magnetic" => Some(HeadingReference::Magnetic),
+                    value => return Err(format!("invalid heading reference '{}'", value)),
+                };
+                aircraft.true_airspeed = parse_opt(fields[22])?;
+                aircraft.selected_altitude = parse_opt(fields[23])?;
+                aircraft.selected_heading = parse_opt(fields[24])?;
+                aircraft.pressure_setting = parse_opt(fields[25])?;
+                aircraft.vertical_rate_source = match fields[26] {
+                    "" => None,
+                    "barometric" => Some(VerticalRateSource::BarometricPressureAltitude),
+                    "geometric" => Some(VerticalRateSource::GeometricAltitude),
+                    value => return Err(format!("invalid vertical rate source '{}'", value)),
+                };
+                aircraft.callsigns = fields[27].split_whitespace().map(String::from).collect();
+                self.map.insert(icao_address, aircraft);
+                *current = Some(icao_address);
+            }
+            "point" => {
+                expect(5)?;
+                let aircraft = current
+                    .and_then(|icao_address| self.map.get_mut(&icao_address))
+                    .ok_or("track point before any aircraft")?;
+                aircraft.track.push(TrackPoint {
+                    time: parse_time(fields[1])?,
+                    latitude: parse(fields[2])?,
+                    longitude: parse(fields[3])?,
+                    altitude: parse_opt(fields[4])?,
+                });
+            }
+            kind => return Err(format!("unknown record '{}'", kind)),
+        }
+        Ok(())
+    }
+}
+
+fn fields(fields: &[&str]) -> String {
+    fields.join("\t")
+}
+
+fn opt<T: fmt::Display>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn time(time: chrono::DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}'", value))
+}
+
+fn parse_opt<T: FromStr>(value: &str) -> Result<Option<T>, String> {
+    match value {
+        "" => Ok(None),
+        value => parse(value).map(Some),
+    }
+}
+
+fn parse_time(value: &str) -> Result<chrono::DateTime<Utc>, String> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("invalid time '{}'", value))
+}
+
+/// Parse an emitter category by its name, as given by its `Display` implementation
+fn parse_emitter_category(value: &str) -> Result<Option<EmitterCategory>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    (2..=4)
+        .flat_map(|type_code| {
+            (0..8).filter_map(move |c| EmitterCategory::from_identification(type_code, c))
+        })
+        .find(|category| category.to_string() == value)
+        .map(Some)
+        .ok_or_else(|| format!("invalid emitter category '{}'", value))
+}
+
+fn parse_icao(value: &str) -> Result<ICAOAddress, String> {
+    u32::from_str_radix(value, 16)
+        .ok()
+        .filter(|_| value.len() == 6)
+        .and_then(icao_from_u32)
+        .ok_or_else(|| format!("invalid ICAO address '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTIFICATION: &str = "*8D4840D6202CC371C32CE0576098;";
+    const POSITION_EVEN: &str = "*8D40621D58C382D690C8AC2863A7;";
+    const POSITION_ODD: &str = "*8D40621D58C386435CC412692AD6;";
+
+    #[test]
+    fn state_saved_and_restored() {
+        let mut tracker = Tracker::new();
+        let now = Utc::now();
+        for frame in &[IDENTIFICATION, IDENTIFICATION, POSITION_EVEN, POSITION_ODD] {
+            tracker.update_with_avr(frame, now).unwrap();
+        }
+        let klm = tracker
+            .map
+            .get_mut(&icao_from_u32(0x4840D6).unwrap())
+            .unwrap();
+        klm.emitter_category = Some(EmitterCategory::HighVortexLarge);
+        klm.heading_reference = Some(HeadingReference::Magnetic);
+        klm.true_airspeed = Some(452.0);
+        klm.selected_altitude = Some(36000);
+        klm.selected_heading = Some(92.5);
+        klm.pressure_setting = Some(1013.2);
+        klm.vertical_rate_source = Some(VerticalRateSource::GeometricAltitude);
+        let path = std::env::temp_dir().join(format!("tracker-state-{}", std::process::id()));
+        tracker.save(&path).unwrap();
+        let restored = Tracker::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(restored.get_num_messages(), 4);
+        assert_eq!(restored.get_num_positions(), 1);
+        assert_eq!(restored.get_num_aircraft_seen(), 2);
+        let icao_address = icao_from_u32(0x4840D6).unwrap();
+        let aircraft = restored.get_aircraft(&icao_address).unwrap();
+        assert_eq!(aircraft.callsign.as_deref(), Some("KLM1023"));
+        assert_eq!(aircraft.country, Some("Netherlands"));
+        assert_eq!(
+            aircraft.emitter_category,
+            Some(EmitterCategory::HighVortexLarge)
+        );
+        assert_eq!(aircraft.heading_reference, Some(HeadingReference::Magnetic));
+        assert_eq!(aircraft.true_airspeed, Some(452.0));
+        assert_eq!(aircraft.selected_altitude, Some(36000));
+        assert_eq!(aircraft.selected_heading, Some(92.5));
+        assert_eq!(aircraft.pressure_setting, Some(1013.2));
+        let source = &aircraft.vertical_rate_source;
+        assert!(matches!(
+            source,
+            Some(VerticalRateSource::GeometricAltitude)
+        ));
+        let original = tracker
+            .get_aircraft(&icao_from_u32(0x40621D).unwrap())
+            .unwrap();
+        let aircraft = restored.get_aircraft(&original.icao_address).unwrap();
+        assert_eq!(aircraft.latitude, original.latitude);
+        assert_eq!(aircraft.longitude, original.longitude);
+        assert_eq!(aircraft.altitude, original.altitude);
+        assert_eq!(aircraft.track(), original.track());
+        assert_eq!(aircraft.last_seen, now);
+        assert!(aircraft.last_cpr_even.is_none() && aircraft.last_cpr_odd.is_none());
+        match Tracker::parse_state("flight-tracker state 2\npoint\tx\n") {
+            Err((line, _)) => assert_eq!(line, 2),
+            Ok(_) => panic!("track point without an aircraft accepted"),
+        }
+    }
+}